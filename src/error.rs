@@ -10,6 +10,10 @@ pub enum Error {
     Bound(String),
     #[error("Chunk Processing Error: {0}")]
     ChunkProcessing(String),
+    #[error("Corrupted node at key {0:?}: {1}")]
+    Corruption(Vec<u8>, String),
+    #[error("Dump Error: {0}")]
+    Dump(String),
     #[error(transparent)]
     Ed(#[from] ed::Error),
     #[error("Fetch Error: {0}")]
@@ -28,6 +32,8 @@ pub enum Error {
     Key(String),
     #[error("Key not found: {0}")]
     KeyNotFound(String),
+    #[error("Key length {0} exceeds maximum of {1} bytes")]
+    KeyTooLong(usize, usize),
     #[error("Proof is missing data for query")]
     MissingData,
     #[error("Path Error: {0}")]
@@ -45,6 +51,12 @@ pub enum Error {
     UnexpectedNode(String),
     #[error("Unknown Error")]
     Unknown,
+    #[error("Value for key {key:?} is {size} bytes, exceeding maximum of {max} bytes")]
+    ValueTooLarge {
+        key: Vec<u8>,
+        size: usize,
+        max: usize,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -35,7 +35,10 @@ pub mod test_utils;
 pub mod tree;
 
 #[cfg(feature = "full")]
-pub use crate::merk::{chunks, restore, snapshot, Merk, MerkSource, Snapshot};
+pub use crate::merk::{
+    chunks, restore, snapshot, ApplyObserver, Compression, EntryIter, KeyValidator, LevelPrune,
+    MergeFn, Merk, MerkSource, MultiApply, PruneStrategy, Snapshot, TreeStats, WriteConfig,
+};
 
 pub use error::{Error, Result};
 pub use tree::{Batch, BatchEntry, Hash, Op, PanicSource, HASH_LENGTH};
@@ -43,4 +46,4 @@ pub use tree::{Batch, BatchEntry, Hash, Op, PanicSource, HASH_LENGTH};
 #[allow(deprecated)]
 pub use proofs::query::verify_query;
 
-pub use proofs::query::verify;
+pub use proofs::query::{verify, verify_into};
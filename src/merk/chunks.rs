@@ -4,9 +4,12 @@
 use super::Merk;
 use crate::proofs::{chunk::get_next_chunk, Node, Op};
 
-use crate::{Error, Result};
+use crate::{tree::Tree, Error, Result};
 use ed::Encode;
 use rocksdb::DBRawIterator;
+use std::cell::Cell;
+use std::ops::Range;
+use std::thread;
 
 /// A `ChunkProducer` allows the creation of chunk proofs, used for trustlessly
 /// replicating entire Merk trees.
@@ -18,6 +21,8 @@ pub struct ChunkProducer<'a> {
     chunk_boundaries: Vec<Vec<u8>>,
     raw_iter: DBRawIterator<'a>,
     index: usize,
+    merk: &'a Merk,
+    estimated_bytes: Cell<Option<u64>>,
 }
 
 impl<'a> ChunkProducer<'a> {
@@ -49,6 +54,8 @@ impl<'a> ChunkProducer<'a> {
             chunk_boundaries,
             raw_iter,
             index: 0,
+            merk,
+            estimated_bytes: Cell::new(None),
         })
     }
 
@@ -61,14 +68,7 @@ impl<'a> ChunkProducer<'a> {
         }
 
         self.index = index;
-
-        if index == 0 || index == 1 {
-            self.raw_iter.seek_to_first();
-        } else {
-            let preceding_key = self.chunk_boundaries.get(index - 2).unwrap();
-            self.raw_iter.seek(preceding_key);
-            self.raw_iter.next();
-        }
+        seek_to_chunk(&mut self.raw_iter, &self.chunk_boundaries, index);
 
         self.next_chunk()
     }
@@ -84,6 +84,64 @@ impl<'a> ChunkProducer<'a> {
         }
     }
 
+    /// Returns the number of chunks which have not yet been produced by
+    /// iterating. After random access with `chunk`, this counts the chunks
+    /// after the one most recently returned.
+    pub fn remaining(&self) -> usize {
+        if self.trunk.is_empty() {
+            return 0;
+        }
+        self.len() - self.index
+    }
+
+    /// Returns an estimate of the total size in bytes of all the encoded
+    /// chunks. This is the exact length of the trunk plus the length of the
+    /// operations for each node in the leaf chunks, which are counted by
+    /// iterating over the keys and values in the backing store. The result is
+    /// computed on the first call and cached, so only the first call may be
+    /// slow for large trees.
+    pub fn estimated_bytes(&self) -> u64 {
+        if let Some(bytes) = self.estimated_bytes.get() {
+            return bytes;
+        }
+
+        let bytes = self.compute_estimated_bytes();
+        self.estimated_bytes.set(Some(bytes));
+        bytes
+    }
+
+    /// Sums the encoded lengths of the trunk and of the operations for each
+    /// node in the leaf chunks, in a single pass over the backing store.
+    fn compute_estimated_bytes(&self) -> u64 {
+        let trunk_bytes = self.trunk.encoding_length().unwrap() as u64;
+        if self.chunk_boundaries.is_empty() {
+            return trunk_bytes;
+        }
+
+        // the boundaries are in key order, so trunk nodes can be skipped by
+        // advancing through them alongside the iterator
+        let mut boundaries = self.chunk_boundaries.iter().peekable();
+        let mut leaf_bytes = 0;
+        let mut iter = self.merk.raw_iter();
+        iter.seek_to_first();
+        while iter.valid() {
+            let key = iter.key().unwrap();
+
+            while boundaries.next_if(|k| k.as_slice() < key).is_some() {}
+            if boundaries.next_if(|k| k.as_slice() == key).is_none() {
+                let node = Tree::decode(key.to_vec(), iter.value().unwrap());
+                let push_op = Op::Push(Node::KV(key.to_vec(), node.value().to_vec()));
+                // each node is also attached to its parent with a parent or
+                // child op
+                leaf_bytes += push_op.encoding_length() as u64 + 1;
+            }
+
+            iter.next();
+        }
+
+        trunk_bytes + leaf_bytes
+    }
+
     /// Gets the next chunk based on the `ChunkProducer`'s internal index state.
     /// This is mostly useful for letting `ChunkIter` yield the chunks in order,
     /// optimizing throughput compared to random access.
@@ -140,12 +198,99 @@ impl<'a> Iterator for ChunkIter<'a> {
     }
 }
 
+/// Positions the iterator at the first node of the chunk with the given index,
+/// given the keys of the nodes separating the leaf chunks.
+fn seek_to_chunk(raw_iter: &mut DBRawIterator, chunk_boundaries: &[Vec<u8>], index: usize) {
+    if index == 0 || index == 1 {
+        raw_iter.seek_to_first();
+    } else {
+        raw_iter.seek(&chunk_boundaries[index - 2]);
+        raw_iter.next();
+    }
+}
+
+/// Produces the encoded leaf chunks with the given indexes in order, reading
+/// from a new iterator over the store.
+fn leaf_chunks(
+    merk: &Merk,
+    chunk_boundaries: &[Vec<u8>],
+    indexes: Range<usize>,
+) -> Result<Vec<Vec<u8>>> {
+    let mut raw_iter = merk.raw_iter();
+    seek_to_chunk(&mut raw_iter, chunk_boundaries, indexes.start);
+
+    indexes
+        .map(|index| {
+            let end_key = chunk_boundaries.get(index - 1).map(|key| key.as_slice());
+            get_next_chunk(&mut raw_iter, end_key)?
+                .encode()
+                .map_err(Into::into)
+        })
+        .collect()
+}
+
 impl Merk {
     /// Creates a `ChunkProducer` which can return chunk proofs for replicating
     /// the entire Merk tree.
     pub fn chunks(&self) -> Result<ChunkProducer> {
         ChunkProducer::new(self)
     }
+
+    /// Creates a proof of the top `depth` levels of the tree, including the
+    /// keys and values of the nodes above `depth` and only the hashes of the
+    /// nodes at `depth`. This is a cheap way to verify the structure at the
+    /// top of the tree, and can be checked with `proofs::verify_trunk_depth`.
+    pub fn prove_trunk(&self, depth: usize) -> Result<Vec<u8>> {
+        let proof = self.walk(|maybe_walker| match maybe_walker {
+            Some(mut walker) => walker.create_depth_proof(depth),
+            None => Err(Error::Proof("Cannot create proof for empty tree".into())),
+        })?;
+
+        Ok(proof.encode()?)
+    }
+
+    /// Produces all the chunks of the tree like iterating over `chunks`, but
+    /// splits the leaf chunks into contiguous ranges which are produced
+    /// concurrently on `threads` threads, each with its own iterator over the
+    /// store. The chunks are returned in index order (the trunk first).
+    pub fn chunks_parallel(&self, threads: usize) -> Result<Vec<Vec<u8>>> {
+        let producer = self.chunks()?;
+        if producer.trunk.is_empty() {
+            return Err(Error::Fetch(
+                "Attempted to fetch chunk on empty tree".into(),
+            ));
+        }
+
+        let mut chunks = vec![producer.trunk.encode()?];
+
+        let leaf_count = producer.len() - 1;
+        if leaf_count == 0 {
+            return Ok(chunks);
+        }
+
+        let per_thread = leaf_count.div_ceil(threads.max(1));
+        let boundaries = producer.chunk_boundaries.as_slice();
+        let results: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = (1..=leaf_count)
+                .step_by(per_thread)
+                .map(|start| {
+                    let end = (start + per_thread).min(leaf_count + 1);
+                    scope.spawn(move || leaf_chunks(self, boundaries, start..end))
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("Chunk producer thread panicked"))
+                .collect()
+        });
+
+        for result in results {
+            chunks.extend(result?);
+        }
+
+        Ok(chunks)
+    }
 }
 
 #[cfg(test)]
@@ -181,6 +326,97 @@ mod tests {
         assert_eq!(chunks.into_iter().size_hint().0, 129);
     }
 
+    #[test]
+    fn chunks_parallel() {
+        let mut merk = TempMerk::new().unwrap();
+        let batch = make_batch_seq(1..10_000);
+        merk.apply(batch.as_slice(), &[]).unwrap();
+
+        let serial: Vec<_> = merk
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(serial.len(), 129);
+        for threads in [1, 3, 4, 200] {
+            assert_eq!(merk.chunks_parallel(threads).unwrap(), serial);
+        }
+
+        // a tree with only a trunk chunk
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(make_batch_seq(1..10).as_slice(), &[]).unwrap();
+        let serial: Vec<_> = merk
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(merk.chunks_parallel(4).unwrap(), serial);
+
+        let merk = TempMerk::new().unwrap();
+        assert!(merk.chunks_parallel(4).is_err());
+    }
+
+    #[test]
+    fn prove_trunk() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(make_batch_seq(1..10_000).as_slice(), &[])
+            .unwrap();
+
+        let proof = merk.prove_trunk(3).unwrap();
+        let tree = crate::proofs::verify_trunk_depth(&proof, merk.root_hash(), 3).unwrap();
+        let (mut kv, mut hash) = (0, 0);
+        tree.visit_refs(&mut |tree| match tree.node {
+            Node::KV(..) => kv += 1,
+            Node::Hash(_) => hash += 1,
+            _ => panic!("unexpected node type"),
+        });
+        assert_eq!((kv, hash), (7, 8));
+
+        // the proof must match the root hash and the claimed depth
+        assert!(crate::proofs::verify_trunk_depth(&proof, [0; 32], 3).is_err());
+        assert!(crate::proofs::verify_trunk_depth(&proof, merk.root_hash(), 2).is_err());
+        assert!(crate::proofs::verify_trunk_depth(&proof, merk.root_hash(), 4).is_err());
+
+        // a depth beyond the height of the tree includes every node
+        let mut small = TempMerk::new().unwrap();
+        small.apply(make_batch_seq(1..8).as_slice(), &[]).unwrap();
+        let proof = small.prove_trunk(10).unwrap();
+        let tree = crate::proofs::verify_trunk_depth(&proof, small.root_hash(), 10).unwrap();
+        let mut kv = 0;
+        tree.visit_refs(&mut |_| kv += 1);
+        assert_eq!(kv, 7);
+
+        assert!(TempMerk::new().unwrap().prove_trunk(3).is_err());
+    }
+
+    #[test]
+    fn remaining_and_estimated_bytes() {
+        let mut merk = TempMerk::new().unwrap();
+        let producer = merk.chunks().unwrap();
+        assert_eq!(producer.remaining(), 0);
+        assert_eq!(producer.estimated_bytes(), 0);
+        drop(producer);
+
+        let batch = make_batch_seq(1..10_000);
+        merk.apply(batch.as_slice(), &[]).unwrap();
+
+        let mut producer = merk.chunks().unwrap();
+        let estimate = producer.estimated_bytes();
+        assert_eq!(producer.estimated_bytes(), estimate);
+        let mut total_bytes = 0;
+        for i in 0..producer.len() {
+            assert_eq!(producer.remaining(), 129 - i);
+            total_bytes += producer.next_chunk().unwrap().len() as u64;
+        }
+        assert_eq!(producer.remaining(), 0);
+
+        // the last node of each leaf chunk has no parent or child op
+        let leaf_chunks = producer.len() as u64 - 1;
+        assert_eq!(estimate, total_bytes + leaf_chunks);
+    }
+
     #[test]
     fn generate_and_verify_chunks() -> Result<()> {
         let mut merk = TempMerk::new().unwrap();
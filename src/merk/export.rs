@@ -0,0 +1,198 @@
+//! Portable dumps of a store's key/value data, independent of the RocksDB
+//! files backing it.
+//!
+//! A dump starts with a header of the bytes `MERKDUMP`, a format version byte
+//! and the store's hash algorithm byte. It is followed by every entry in key
+//! order, each encoded as a one-byte key length, the key, a four-byte
+//! big-endian value length and the value. The dump ends at the end of the
+//! stream.
+
+use std::convert::TryInto;
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::Path;
+
+use super::Merk;
+use crate::tree::{BatchEntry, HashAlgorithm, Op, MAX_KEY_LENGTH};
+use crate::{Error, Result};
+
+const MAGIC: &[u8; 8] = b"MERKDUMP";
+const VERSION: u8 = 1;
+
+// key lengths are encoded as a single byte
+const _: () = assert!(MAX_KEY_LENGTH <= u8::MAX as usize);
+
+/// The number of entries `Merk::import` applies at a time.
+const IMPORT_BATCH_SIZE: usize = 10_000;
+
+impl Merk {
+    /// Writes every entry in the store to `writer` in key order, as a portable
+    /// dump which can be loaded into a new store with `Merk::import`.
+    /// Auxiliary data is not included.
+    pub fn export<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION, self.hash_algorithm.to_byte()])?;
+
+        for entry in self.iter() {
+            let (key, value) = entry?;
+            let value_length: u32 = value.len().try_into()?;
+            writer.write_all(&[key.len() as u8])?;
+            writer.write_all(&key)?;
+            writer.write_all(&value_length.to_be_bytes())?;
+            writer.write_all(&value)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Creates a store at the given path from a dump written by
+    /// `Merk::export`, using the hash algorithm of the exported store. The
+    /// resulting store has the same root hash as the exported one.
+    ///
+    /// Returns an error if the store at the path already contains data, or
+    /// `Error::Dump` if the dump is malformed. If the import fails after the
+    /// store was created, the store is destroyed so no partially imported
+    /// data is left behind.
+    pub fn import<P: AsRef<Path>, R: Read>(path: P, mut reader: R) -> Result<Merk> {
+        let mut header = [0; 10];
+        reader.read_exact(&mut header).map_err(read_error)?;
+        if &header[..8] != MAGIC {
+            return Err(Error::Dump("Input is not a Merk dump".into()));
+        }
+        if header[8] != VERSION {
+            return Err(Error::Dump(format!(
+                "Unsupported dump version {}",
+                header[8]
+            )));
+        }
+        let hash_algorithm = HashAlgorithm::from_byte(header[9])
+            .ok_or_else(|| Error::Dump(format!("Unknown hash algorithm byte {}", header[9])))?;
+
+        let mut merk = Merk::open_with_hash_algorithm(path, hash_algorithm)?;
+        if merk.has_root_key()? {
+            return Err(Error::Path(
+                "The store at the given path already contains data".into(),
+            ));
+        }
+
+        if let Err(err) = import_entries(&mut merk, &mut reader) {
+            merk.destroy()?;
+            return Err(err);
+        }
+
+        Ok(merk)
+    }
+}
+
+/// Applies the entries of a dump (following its header) to `merk`.
+fn import_entries<R: Read>(merk: &mut Merk, reader: &mut R) -> Result<()> {
+    let mut batch: Vec<BatchEntry> = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let mut last_key: Option<Vec<u8>> = None;
+    while let Some((key, value)) = read_entry(reader)? {
+        let prev_key = batch.last().map(|(key, _)| key).or(last_key.as_ref());
+        if prev_key.is_some_and(|prev_key| *prev_key >= key) {
+            return Err(Error::Dump(format!(
+                "Entries are not in ascending key order at key {key:?}"
+            )));
+        }
+        batch.push((key, Op::Put(value)));
+
+        if batch.len() == IMPORT_BATCH_SIZE {
+            merk.apply(&batch, &[])?;
+            last_key = batch.pop().map(|(key, _)| key);
+            batch.clear();
+        }
+    }
+    merk.apply(&batch, &[])
+}
+
+/// Reads the next entry of a dump, returning `None` at the end of the stream.
+fn read_entry<R: Read>(reader: &mut R) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+    let mut key_length = [0];
+    loop {
+        match reader.read(&mut key_length) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    let mut key = vec![0; key_length[0] as usize];
+    reader.read_exact(&mut key).map_err(read_error)?;
+
+    let mut value_length = [0; 4];
+    reader.read_exact(&mut value_length).map_err(read_error)?;
+    let mut value = vec![0; u32::from_be_bytes(value_length) as usize];
+    reader.read_exact(&mut value).map_err(read_error)?;
+
+    Ok(Some((key, value)))
+}
+
+/// Converts an error from reading a dump, reporting a truncated dump as
+/// `Error::Dump`.
+fn read_error(err: io::Error) -> Error {
+    match err.kind() {
+        ErrorKind::UnexpectedEof => Error::Dump("Unexpected end of dump".into()),
+        _ => err.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn export_import() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..1000), &[])
+            .expect("apply failed");
+
+        let mut dump = vec![];
+        merk.export(&mut dump).expect("export failed");
+
+        let path = TempMerk::create_path();
+        let imported = Merk::import(&path, dump.as_slice()).expect("import failed");
+        assert_eq!(imported.root_hash(), merk.root_hash());
+        assert_eq!(imported.len().unwrap(), 1000);
+        assert_eq!(
+            imported.get(&seq_key(500)).unwrap(),
+            Some(put_entry_value())
+        );
+        drop(imported);
+
+        // importing into a store which already has data fails
+        assert!(Merk::import(&path, dump.as_slice()).is_err());
+        Merk::open(&path).unwrap().destroy().unwrap();
+
+        // malformed dumps are rejected, without leaving a store behind
+        let path = TempMerk::create_path();
+        let mut bad_version = dump.clone();
+        bad_version[8] = VERSION + 1;
+        assert!(matches!(
+            Merk::import(&path, bad_version.as_slice()),
+            Err(Error::Dump(_))
+        ));
+        assert!(matches!(
+            Merk::import(&path, &dump[..dump.len() - 1]),
+            Err(Error::Dump(_))
+        ));
+        assert!(!Path::new(&path).exists());
+
+        let mut unordered = dump[..10].to_vec();
+        for i in [1, 0] {
+            let mut entry = vec![];
+            let mut merk = TempMerk::new().unwrap();
+            merk.apply(&make_batch_seq(i..i + 1), &[]).unwrap();
+            merk.export(&mut entry).unwrap();
+            unordered.extend_from_slice(&entry[10..]);
+        }
+        let path = TempMerk::create_path();
+        assert!(matches!(
+            Merk::import(&path, unordered.as_slice()),
+            Err(Error::Dump(_))
+        ));
+        assert!(!Path::new(&path).exists());
+    }
+}
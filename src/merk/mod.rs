@@ -1,42 +1,202 @@
 pub mod chunks;
+mod export;
+mod multi;
 pub mod restore;
 pub mod snapshot;
 
 use std::cmp::Ordering;
-use std::collections::LinkedList;
+use std::collections::{HashMap, LinkedList};
+use std::convert::TryInto;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::sync::RwLock;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
 
 use rocksdb::DB;
-use rocksdb::{checkpoint::Checkpoint, ColumnFamilyDescriptor, WriteBatch};
+use rocksdb::{
+    checkpoint::Checkpoint, ColumnFamily, ColumnFamilyDescriptor, Direction, IteratorMode,
+    WriteBatch, DEFAULT_COLUMN_FAMILY_NAME,
+};
+use sha2::Digest;
 
 use crate::error::{Error, Result};
-use crate::proofs::{encode_into, query::QueryItem};
-use crate::tree::{Batch, Commit, Fetch, GetResult, Hash, Op, RefWalker, Tree, Walker, NULL_HASH};
-
+use crate::proofs::{
+    encode_into,
+    query::{split_query, Query, QueryItem},
+    Node, Op as ProofOp,
+};
+use crate::tree::{
+    kv_hash, Batch, BatchEntry, Commit, Fetch, GetResult, Hash, HashAlgorithm, Hasher, NoopCommit,
+    Op, RefWalker, Tree, Walker, HASH_LENGTH, MAX_KEY_LENGTH, NULL_HASH,
+};
+
+pub use self::multi::MultiApply;
 pub use self::snapshot::Snapshot;
+use self::snapshot::StaticSnapshot;
 
 const ROOT_KEY_KEY: &[u8] = b"root";
+const HASH_ALGORITHM_KEY: &[u8] = b"hash_algorithm";
+const TREE_CF_NAME: &str = DEFAULT_COLUMN_FAMILY_NAME;
 const AUX_CF_NAME: &str = "aux";
 const INTERNAL_CF_NAME: &str = "internal";
-
-fn column_families() -> Vec<ColumnFamilyDescriptor> {
+const SNAPSHOTS_CF_NAME: &str = "snapshots";
+/// The column families every store has. Stores created before named snapshots
+/// were added have no snapshots column family, so it is optional when opening
+/// an existing store without creating column families.
+const REQUIRED_CF_NAMES: [&str; 3] = [TREE_CF_NAME, AUX_CF_NAME, INTERNAL_CF_NAME];
+const CF_NAMES: [&str; 4] = [
+    TREE_CF_NAME,
+    AUX_CF_NAME,
+    INTERNAL_CF_NAME,
+    SNAPSHOTS_CF_NAME,
+];
+const DEFAULT_PRUNE_LEVELS: u8 = 21;
+const MAX_DEFAULT_COMMIT_CAPACITY: usize = 10_000;
+
+fn column_families(aux_opts: rocksdb::Options) -> Vec<ColumnFamilyDescriptor> {
     vec![
-        // TODO: clone opts or take args
-        ColumnFamilyDescriptor::new(AUX_CF_NAME, Merk::default_db_opts()),
+        ColumnFamilyDescriptor::new(AUX_CF_NAME, aux_opts),
         ColumnFamilyDescriptor::new(INTERNAL_CF_NAME, Merk::default_db_opts()),
+        ColumnFamilyDescriptor::new(SNAPSHOTS_CF_NAME, Merk::default_db_opts()),
     ]
 }
 
+/// Returns the descriptors of the column families of the existing store at
+/// `path`, for opening it without creating column families (e.g. read-only).
+/// The snapshots column family is only included if the store has one.
+fn existing_column_families(path: &Path) -> Result<Vec<ColumnFamilyDescriptor>> {
+    let names = rocksdb::DB::list_cf(&Merk::default_db_opts(), path)?;
+    let mut cfs = vec![
+        ColumnFamilyDescriptor::new(AUX_CF_NAME, Merk::default_db_opts()),
+        ColumnFamilyDescriptor::new(INTERNAL_CF_NAME, Merk::default_db_opts()),
+    ];
+    if names.iter().any(|name| name == SNAPSHOTS_CF_NAME) {
+        cfs.push(ColumnFamilyDescriptor::new(
+            SNAPSHOTS_CF_NAME,
+            Merk::default_db_opts(),
+        ));
+    }
+    Ok(cfs)
+}
+
 /// A handle to a Merkle key/value store backed by RocksDB.
 pub struct Merk {
     pub(crate) tree: RwLock<Option<Tree>>,
-    pub(crate) db: rocksdb::DB,
+    pub(crate) db: Arc<rocksdb::DB>,
     pub(crate) path: PathBuf,
+    pub(crate) cf_prefix: String,
+    pub(crate) shared_db: bool,
+    pub(crate) prune_strategy: Box<dyn PruneStrategy>,
+    pub(crate) commit_capacity: Option<usize>,
+    pub(crate) inorder_commit: bool,
+    pub(crate) apply_observer: Option<ApplyObserver>,
+    pub(crate) key_validator: Option<KeyValidator>,
+    pub(crate) max_key_length: usize,
+    pub(crate) max_value_size: Option<usize>,
+    pub(crate) write_config: WriteConfig,
+    pub(crate) named_snapshots: RwLock<HashMap<Vec<u8>, StaticSnapshot>>,
+    pub(crate) hash_algorithm: HashAlgorithm,
+    // declared last so it is dropped after the database is closed
+    pub(crate) fork_dir: Option<ForkDir>,
+}
+
+/// The directory of a store created by `Merk::fork`, which is deleted when
+/// dropped.
+pub(crate) struct ForkDir(PathBuf);
+
+impl ForkDir {
+    /// Returns a new, unique path in the system's temporary directory.
+    fn create_path() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let count = COUNTER.fetch_add(1, AtomicOrdering::Relaxed);
+        std::env::temp_dir().join(format!("merk-fork-{}-{time}-{count}", std::process::id()))
+    }
+}
+
+impl Drop for ForkDir {
+    fn drop(&mut self) {
+        match std::fs::remove_dir_all(&self.0) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                log::warn!("Failed to delete fork at {:?}: {}", self.0, err);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Statistics about the shape and size of the tree, as returned by
+/// `Merk::stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TreeStats {
+    /// The height of the tree (0 if the tree is empty).
+    pub height: u8,
+    /// The number of nodes in the tree.
+    pub node_count: u64,
+    /// The total length of all keys in the tree.
+    pub key_bytes: u64,
+    /// The total length of all values in the tree.
+    pub value_bytes: u64,
+}
+
+/// Options used when writing to the backing store.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteConfig {
+    /// Whether to sync the write-ahead log to disk before each write completes.
+    pub sync: bool,
+    /// Whether to skip writing to the write-ahead log. Writes may be lost if
+    /// the process crashes before they are flushed.
+    pub disable_wal: bool,
+}
+
+/// The algorithm used to compress data in the backing store, as passed to
+/// `Merk::open_compressed`. The algorithms other than `None` require the
+/// crate's feature of the same name, which builds RocksDB with the codec.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Data is stored uncompressed.
+    #[default]
+    None,
+    /// LZ4 compression, which is fast but compresses less.
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// Zstandard compression, which is slower but compresses more.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl From<Compression> for rocksdb::DBCompressionType {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::None => rocksdb::DBCompressionType::None,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => rocksdb::DBCompressionType::Lz4,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => rocksdb::DBCompressionType::Zstd,
+        }
+    }
 }
 
 pub type UseTreeMutResult = Result<Vec<(Vec<u8>, Option<Vec<u8>>)>>;
 
+/// A function used by `Merk::apply_with_merges` to compute a key's new value
+/// from its current value (`None` if the key does not exist). Returning `None`
+/// deletes the key.
+pub type MergeFn = Box<dyn Fn(Option<&[u8]>) -> Option<Vec<u8>>>;
+
+/// A function set with `Merk::set_apply_observer`, called with each key changed
+/// by an applied batch and its new value (`None` if the key was deleted).
+pub type ApplyObserver = Box<dyn FnMut(&[u8], Option<&[u8]>) + Send + Sync>;
+
+/// A function set with `Merk::set_key_validator`, called with each key in a
+/// batch before it is applied. Returning an error rejects the batch.
+pub type KeyValidator = Box<dyn Fn(&[u8]) -> Result<()> + Send + Sync>;
+
 impl Merk {
     /// Opens a store with the specified file path. If no store exists at that
     /// path, one will be created.
@@ -45,6 +205,68 @@ impl Merk {
         Merk::open_opt(path, db_opts)
     }
 
+    /// Opens an existing store with the specified file path. Unlike `open`,
+    /// this returns an error if no store exists at that path, or if the store
+    /// does not contain any data.
+    pub fn open_existing<P: AsRef<Path>>(path: P) -> Result<Merk> {
+        if !path.as_ref().exists() {
+            return Err(Error::Path("The given path does not exist".into()));
+        }
+
+        let mut db_opts = Merk::default_db_opts();
+        db_opts.create_if_missing(false);
+        let merk = Merk::open_opt(path, db_opts)?;
+
+        if !merk.has_root_key()? {
+            return Err(Error::Path(
+                "The store at the given path does not contain any data".into(),
+            ));
+        }
+
+        Ok(merk)
+    }
+
+    /// Opens a store with the specified file path, creating one if none
+    /// exists. Also returns `true` if the store was newly created, or `false`
+    /// if it already contained data (a store which exists but does not contain
+    /// any data is treated as newly created).
+    pub fn open_or_create<P: AsRef<Path>>(path: P) -> Result<(Merk, bool)> {
+        let merk = Merk::open(path)?;
+        let created = !merk.has_root_key()?;
+        Ok((merk, created))
+    }
+
+    /// Opens a store with the specified file path, using the given hash
+    /// algorithm. If no store exists at that path, one will be created.
+    ///
+    /// The algorithm is persisted in the store, so later calls to `open` will
+    /// use it as well. Returns an error if the store already contains data
+    /// hashed with a different algorithm.
+    pub fn open_with_hash_algorithm<P: AsRef<Path>>(
+        path: P,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<Merk> {
+        let mut merk = Merk::open(path)?;
+
+        let stored = read_hash_algorithm(&merk.db, &merk.cf_prefix)?;
+        if stored.is_none() && !merk.has_root_key()? {
+            let internal_cf = merk.cf(INTERNAL_CF_NAME);
+            let mut batch = WriteBatch::default();
+            batch.put_cf(internal_cf, HASH_ALGORITHM_KEY, [hash_algorithm.to_byte()]);
+            merk.write(batch)?;
+            merk.hash_algorithm = hash_algorithm;
+        }
+
+        if merk.hash_algorithm != hash_algorithm {
+            return Err(Error::Path(format!(
+                "The store uses the {:?} hash algorithm",
+                merk.hash_algorithm
+            )));
+        }
+
+        Ok(merk)
+    }
+
     pub fn open_readonly<P: AsRef<Path>>(path: P) -> Result<Merk> {
         let db_opts = Merk::default_db_opts();
 
@@ -53,31 +275,168 @@ impl Merk {
         let db = rocksdb::DB::open_cf_descriptors_read_only(
             &db_opts,
             &path_buf,
-            column_families(),
+            existing_column_families(&path_buf)?,
             false,
         )?;
 
-        Ok(Merk {
-            tree: RwLock::new(load_root(&db)?),
-            db,
-            path: path_buf,
-        })
+        Merk::from_db(Arc::new(db), path_buf, String::new(), false)
+    }
+
+    /// Opens a secondary instance of the store at `path`, which reads the
+    /// primary's data without copying it and without taking its lock. The
+    /// secondary keeps its own logs at `secondary_path`, and only sees writes
+    /// made by the primary after calling `catch_up_with_primary`.
+    pub fn open_as_secondary<P: AsRef<Path>>(path: P, secondary_path: P) -> Result<Merk> {
+        let mut db_opts = Merk::default_db_opts();
+        // required by RocksDB for secondary instances
+        db_opts.set_max_open_files(-1);
+
+        let mut path_buf = PathBuf::new();
+        path_buf.push(&path);
+        let db = rocksdb::DB::open_cf_descriptors_as_secondary(
+            &db_opts,
+            path.as_ref(),
+            secondary_path.as_ref(),
+            existing_column_families(path.as_ref())?,
+        )?;
+
+        Merk::from_db(Arc::new(db), path_buf, String::new(), false)
+    }
+
+    /// Updates a secondary instance (see `open_as_secondary`) with the writes
+    /// made by the primary since it was opened or last caught up, then reloads
+    /// the root of the tree.
+    pub fn catch_up_with_primary(&self) -> Result<()> {
+        self.db.try_catch_up_with_primary()?;
+        *self.tree.write().unwrap() = load_root(&self.db, &self.cf_prefix, self.hash_algorithm)?;
+        Ok(())
+    }
+
+    /// Opens a store with the specified file path, using a block cache of
+    /// `cache_bytes` bytes shared by the tree nodes and the auxiliary data. If
+    /// no store exists at that path, one will be created.
+    pub fn open_with_cache<P: AsRef<Path>>(path: P, cache_bytes: usize) -> Result<Merk> {
+        let cache = rocksdb::Cache::new_lru_cache(cache_bytes);
+        let mut block_opts = rocksdb::BlockBasedOptions::default();
+        block_opts.set_block_cache(&cache);
+
+        let mut db_opts = Merk::default_db_opts();
+        db_opts.set_block_based_table_factory(&block_opts);
+        let mut aux_opts = Merk::default_db_opts();
+        aux_opts.set_block_based_table_factory(&block_opts);
+
+        Merk::open_cfs(path, db_opts, column_families(aux_opts))
+    }
+
+    /// Opens a store with the specified file path, compressing the tree nodes
+    /// and the auxiliary data with the given algorithm. If no store exists at
+    /// that path, one will be created.
+    ///
+    /// Compression is done by RocksDB when writing data to disk, so hashes are
+    /// still computed over the uncompressed values and proofs are the same as
+    /// for an uncompressed store. Reads decompress transparently, and a store
+    /// can be reopened with a different (or no) compression algorithm, which
+    /// only applies to data written after reopening.
+    ///
+    /// `Compression::Lz4` and `Compression::Zstd` are only available with
+    /// this crate's `lz4` and `zstd` features, which link the codecs into
+    /// RocksDB.
+    pub fn open_compressed<P: AsRef<Path>>(path: P, compression: Compression) -> Result<Merk> {
+        let mut db_opts = Merk::default_db_opts();
+        db_opts.set_compression_type(compression.into());
+        let mut aux_opts = Merk::default_db_opts();
+        aux_opts.set_compression_type(compression.into());
+
+        Merk::open_cfs(path, db_opts, column_families(aux_opts))
     }
 
     /// Opens a store with the specified file path and the given options. If no
     /// store exists at that path, one will be created.
     pub fn open_opt<P>(path: P, db_opts: rocksdb::Options) -> Result<Merk>
+    where
+        P: AsRef<Path>,
+    {
+        Merk::open_cfs(path, db_opts, column_families(Merk::default_db_opts()))
+    }
+
+    fn open_cfs<P>(
+        path: P,
+        db_opts: rocksdb::Options,
+        cfs: Vec<ColumnFamilyDescriptor>,
+    ) -> Result<Merk>
     where
         P: AsRef<Path>,
     {
         let mut path_buf = PathBuf::new();
         path_buf.push(path);
-        let db = rocksdb::DB::open_cf_descriptors(&db_opts, &path_buf, column_families())?;
+        let db = rocksdb::DB::open_cf_descriptors(&db_opts, &path_buf, cfs)?;
+
+        Merk::from_db(Arc::new(db), path_buf, String::new(), false)
+    }
+
+    /// Opens a store inside an existing RocksDB database which is shared with
+    /// other data, e.g. when embedding Merk in a larger application. The
+    /// store's column families are namespaced by prefixing their names with
+    /// `cf_prefix`, so the database must have been opened with the column
+    /// families returned by `Merk::column_families_with_prefix`. The snapshots
+    /// column family may be left out (e.g. for databases created before it
+    /// was added), in which case named snapshots are unavailable.
+    ///
+    /// Merk only reads and writes its own column families, so other data in
+    /// the database is left untouched. A store opened this way can not be
+    /// destroyed or repaired, since that would affect the whole database.
+    pub fn open_on_db(db: Arc<rocksdb::DB>, cf_prefix: &[u8]) -> Result<Merk> {
+        let cf_prefix = cf_prefix_str(cf_prefix)?;
+        for name in REQUIRED_CF_NAMES {
+            let name = format!("{cf_prefix}{name}");
+            if db.cf_handle(&name).is_none() {
+                return Err(Error::Path(format!("Missing column family {name:?}")));
+            }
+        }
+
+        let path = db.path().to_path_buf();
+        Merk::from_db(db, path, cf_prefix, true)
+    }
+
+    /// Returns the descriptors of the column families used by a store opened
+    /// with `Merk::open_on_db` with the given prefix. These must be included
+    /// when opening the shared database, except for the snapshots column
+    /// family, which is optional (see `open_on_db`).
+    pub fn column_families_with_prefix(cf_prefix: &[u8]) -> Result<Vec<ColumnFamilyDescriptor>> {
+        let cf_prefix = cf_prefix_str(cf_prefix)?;
+        Ok(CF_NAMES
+            .iter()
+            .map(|name| {
+                ColumnFamilyDescriptor::new(format!("{cf_prefix}{name}"), Merk::default_db_opts())
+            })
+            .collect())
+    }
+
+    fn from_db(
+        db: Arc<rocksdb::DB>,
+        path: PathBuf,
+        cf_prefix: String,
+        shared_db: bool,
+    ) -> Result<Merk> {
+        let hash_algorithm = read_hash_algorithm(&db, &cf_prefix)?.unwrap_or_default();
 
         Ok(Merk {
-            tree: RwLock::new(load_root(&db)?),
+            tree: RwLock::new(load_root(&db, &cf_prefix, hash_algorithm)?),
             db,
-            path: path_buf,
+            path,
+            cf_prefix,
+            shared_db,
+            prune_strategy: Box::new(LevelPrune::new(DEFAULT_PRUNE_LEVELS)),
+            commit_capacity: None,
+            inorder_commit: false,
+            apply_observer: None,
+            key_validator: None,
+            max_key_length: MAX_KEY_LENGTH,
+            max_value_size: None,
+            write_config: WriteConfig::default(),
+            named_snapshots: RwLock::new(HashMap::new()),
+            hash_algorithm,
+            fork_dir: None,
         })
     }
 
@@ -101,10 +460,264 @@ impl Merk {
         opts
     }
 
+    /// Sets the number of levels of the tree which will be kept in memory after
+    /// each commit, below which nodes are pruned and will be fetched from the
+    /// backing store when needed. A value of 0 keeps only the root node in
+    /// memory, and `u8::MAX` effectively never prunes. Defaults to 21.
+    pub fn set_prune_levels(&mut self, levels: u8) {
+        self.set_prune_strategy(LevelPrune::new(levels));
+    }
+
+    /// Sets the initial capacity of the buffer which nodes are written into
+    /// when committing. By default, the capacity is derived from the height of
+    /// the tree (capped at 10,000 nodes), so setting it explicitly is mostly
+    /// useful to avoid reallocations when applying very large batches. Passing
+    /// `None` restores the default.
+    pub fn set_commit_capacity(&mut self, capacity: Option<usize>) {
+        self.commit_capacity = capacity;
+    }
+
+    /// Sets whether modified nodes are written in key order when committing
+    /// (see `Tree::commit_inorder`), rather than in post-order. The resulting
+    /// state is the same either way, but writing in key order lets RocksDB
+    /// receive a sequential write batch. Defaults to `false`.
+    pub fn set_inorder_commit(&mut self, inorder: bool) {
+        self.inorder_commit = inorder;
+    }
+
+    /// Sets a function to be called with each key changed by an applied batch,
+    /// along with its new value (or `None` if it was deleted), e.g. to feed
+    /// changes to a downstream index. The observer is called in key order once
+    /// the batch has been committed, with exactly the keys in the batch (nodes
+    /// which were only rewritten because of rebalancing are not included).
+    /// For `Op::Append`, the observer receives the resulting value.
+    pub fn set_apply_observer(&mut self, observer: ApplyObserver) {
+        self.apply_observer = Some(observer);
+    }
+
+    /// Removes the observer set with `set_apply_observer`, if any.
+    pub fn clear_apply_observer(&mut self) {
+        self.apply_observer = None;
+    }
+
+    /// Sets a function which `apply` (and the other checked apply methods)
+    /// call with each key in a batch before modifying the tree, e.g. to
+    /// enforce an application-specific key format. If the validator returns an
+    /// error for any key, the batch is not applied and the error is returned.
+    ///
+    /// `apply_unchecked` does not call the validator.
+    pub fn set_key_validator(&mut self, validator: KeyValidator) {
+        self.key_validator = Some(validator);
+    }
+
+    /// Removes the validator set with `set_key_validator`, if any.
+    pub fn clear_key_validator(&mut self) {
+        self.key_validator = None;
+    }
+
+    /// Sets the maximum length in bytes of keys in batches passed to `apply`
+    /// (and the other checked apply methods). Batches containing a longer key
+    /// are rejected with `Error::KeyTooLong` before the tree is modified.
+    ///
+    /// The limit can only be lowered from the default of `MAX_KEY_LENGTH`
+    /// (255), since node references and proofs encode key lengths as a single
+    /// byte.
+    pub fn set_max_key_length(&mut self, bytes: u8) {
+        self.max_key_length = bytes as usize;
+    }
+
+    /// Sets the maximum size in bytes of values written with `Op::Put` or
+    /// `Op::Append`. Batches passed to `apply` (and the other checked apply
+    /// methods) which would result in a larger value (for appends, the
+    /// existing value plus the appended bytes) are rejected with
+    /// `Error::ValueTooLarge` before the tree is modified. By default, value
+    /// sizes are not limited.
+    pub fn set_max_value_size(&mut self, bytes: usize) {
+        self.max_value_size = Some(bytes);
+    }
+
+    /// Checks that the keys in `batch` are sorted and unique (see
+    /// `check_batch`) and within the maximum key length, then runs the key
+    /// validator (if any) over them and checks the sizes of the values against
+    /// the maximum value size (if any).
+    pub(crate) fn validate_batch(&self, batch: &Batch) -> Result<()> {
+        check_batch(batch)?;
+
+        for (key, _) in batch {
+            if key.len() > self.max_key_length {
+                return Err(Error::KeyTooLong(key.len(), self.max_key_length));
+            }
+        }
+
+        if let Some(validator) = &self.key_validator {
+            for (key, _) in batch {
+                validator(key)?;
+            }
+        }
+
+        if let Some(max) = self.max_value_size {
+            // read the existing values of all appended keys in one walk
+            let append_keys: Vec<_> = batch
+                .iter()
+                .filter(|(_, op)| matches!(op, Op::Append(_)))
+                .map(|(key, _)| key.clone())
+                .collect();
+            let mut existing = if append_keys.is_empty() {
+                vec![]
+            } else {
+                self.get_many(&append_keys)?
+            }
+            .into_iter();
+
+            for (key, op) in batch {
+                let size = match op {
+                    Op::Put(value) => value.len(),
+                    Op::Append(suffix) => {
+                        let existing_len = existing.next().flatten().map_or(0, |value| value.len());
+                        existing_len + suffix.len()
+                    }
+                    Op::Delete => continue,
+                };
+                if size > max {
+                    return Err(Error::ValueTooLarge {
+                        key: key.clone(),
+                        size,
+                        max,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Commits the changes made to the in-memory tree by applying `batch` (see
+    /// `commit_with`), then calls the apply observer (if any) with the changes.
+    fn commit_batch<F>(
+        &mut self,
+        batch: &Batch,
+        deleted_keys: LinkedList<Vec<u8>>,
+        aux: &Batch,
+        before_write: F,
+    ) -> Result<Vec<(Vec<u8>, bool)>>
+    where
+        F: FnOnce(&mut WriteBatch) -> Result<()>,
+    {
+        if self.apply_observer.is_none() {
+            return self.commit_with(deleted_keys, aux, before_write);
+        }
+
+        let deleted: Vec<_> = deleted_keys.iter().cloned().collect();
+        let changes = self.commit_with(deleted_keys, aux, before_write)?;
+        self.notify_apply_observer(batch, &deleted)?;
+        Ok(changes)
+    }
+
+    /// Calls the apply observer (if any) with the changes made by the
+    /// committed `batch`, given the sorted keys which were actually deleted.
+    pub(crate) fn notify_apply_observer(
+        &mut self,
+        batch: &Batch,
+        deleted: &[Vec<u8>],
+    ) -> Result<()> {
+        let mut observer = match self.apply_observer.take() {
+            Some(observer) => observer,
+            None => return Ok(()),
+        };
+
+        // deleting a key which does not exist is a no-op, so only keys which
+        // were actually deleted are observed
+        let res = batch.iter().try_for_each(|(key, op)| {
+            match op {
+                Op::Put(value) => observer(key, Some(value)),
+                Op::Delete if deleted.binary_search(key).is_ok() => observer(key, None),
+                Op::Delete => {}
+                Op::Append(_) => observer(key, self.get(key)?.as_deref()),
+            }
+            Ok(())
+        });
+
+        self.apply_observer = Some(observer);
+        res
+    }
+
+    /// Sets the strategy used to decide which nodes are pruned from memory
+    /// after each commit.
+    pub fn set_prune_strategy<P: PruneStrategy + 'static>(&mut self, strategy: P) {
+        self.prune_strategy = Box::new(strategy);
+    }
+
+    /// Sets the options used when writing to the backing store. By default,
+    /// writes go through the write-ahead log but are not synced to disk.
+    pub fn set_write_config(&mut self, config: WriteConfig) {
+        self.write_config = config;
+    }
+
     /// Gets an auxiliary value.
     pub fn get_aux(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        let aux_cf = self.db.cf_handle(AUX_CF_NAME);
-        Ok(self.db.get_cf(aux_cf.unwrap(), key)?)
+        let aux_cf = self.cf(AUX_CF_NAME);
+        Ok(self.db.get_cf(aux_cf, key)?)
+    }
+
+    /// Gets multiple auxiliary values in a single batched read. The returned
+    /// values are in the same order as `keys`, with `None` for keys which are
+    /// not present.
+    pub fn get_aux_many(&self, keys: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>> {
+        let aux_cf = self.cf(AUX_CF_NAME);
+        self.db
+            .multi_get_cf(keys.iter().map(|key| (aux_cf, key)))
+            .into_iter()
+            .map(|res| Ok(res?))
+            .collect()
+    }
+
+    /// Returns an iterator over the auxiliary entries in key order.
+    pub fn aux_iter(&self) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + '_ {
+        let aux_cf = self.cf(AUX_CF_NAME);
+        self.db
+            .iterator_cf(aux_cf, IteratorMode::Start)
+            .map(|entry| {
+                let (key, value) = entry?;
+                Ok((key.to_vec(), value.to_vec()))
+            })
+    }
+
+    /// Returns an iterator over the auxiliary entries with keys in the given
+    /// range (`range.start` inclusive, `range.end` exclusive), in key order.
+    pub fn aux_range(
+        &self,
+        range: Range<Vec<u8>>,
+    ) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + '_ {
+        let aux_cf = self.cf(AUX_CF_NAME);
+        let Range { start, end } = range;
+        self.db
+            .iterator_cf(aux_cf, IteratorMode::From(&start, Direction::Forward))
+            .take_while(move |entry| match entry {
+                Ok((key, _)) => key.as_ref() < end.as_slice(),
+                Err(_) => true,
+            })
+            .map(|entry| {
+                let (key, value) = entry?;
+                Ok((key.to_vec(), value.to_vec()))
+            })
+    }
+
+    /// Computes a fingerprint of the entire auxiliary column family, by folding
+    /// the hash of each entry (in key order) into a rolling hash. Stores with
+    /// identical auxiliary contents have the same fingerprint, which can be
+    /// used to detect drift between replicas of the auxiliary data.
+    pub fn aux_fingerprint(&self) -> Result<Hash> {
+        let mut fingerprint = NULL_HASH;
+        for entry in self.aux_iter() {
+            let (key, value) = entry?;
+
+            let mut hasher = Hasher::new();
+            hasher.update(fingerprint);
+            hasher.update(kv_hash::<Hasher>(&key, &value)?);
+            fingerprint.copy_from_slice(&hasher.finalize()[..]);
+        }
+
+        Ok(fingerprint)
     }
 
     /// Gets a value for the given key. If the key is not found, `None` is
@@ -120,6 +733,94 @@ impl Merk {
         })
     }
 
+    /// Loads the nodes with keys in the given range (`start` inclusive, `end`
+    /// exclusive) into memory, along with the nodes bounding the range and
+    /// their ancestors, so that subsequent reads of those keys do not need to
+    /// fetch from the backing store.
+    ///
+    /// The loaded nodes are subject to the store's prune strategy, so they may
+    /// be pruned again by the next commit.
+    pub fn load_range(&mut self, range: Range<Vec<u8>>) -> Result<()> {
+        let mut tree = self.tree.write().unwrap();
+        match tree.as_mut() {
+            Some(tree) => load_range(tree, &self.source(), &range),
+            None => Ok(()),
+        }
+    }
+
+    /// Loads the nodes a proof of `query` would touch into memory, so that a
+    /// subsequent `prove_query` (or `prove`) of the same query does not need to
+    /// read from the backing store. Reading the nodes also warms RocksDB's
+    /// block cache. The query's limit and offset are ignored, so this may load
+    /// more nodes than the proof needs.
+    ///
+    /// Loaded nodes stay in memory until they are pruned by the next commit.
+    pub fn prefetch(&self, query: &Query) -> Result<()> {
+        let items: Vec<_> = query.iter().cloned().collect();
+        let mut tree = self.tree.write().unwrap();
+        match tree.as_mut() {
+            Some(tree) => prefetch(tree, &self.source(), &items),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns `true` if the store contains the given key. This avoids copying
+    /// the value, and keys in pruned parts of the tree are checked in the
+    /// backing store without allocating.
+    pub fn contains_key(&self, key: &[u8]) -> Result<bool> {
+        self.use_tree(
+            |maybe_tree| match maybe_tree.map(|tree| tree.contains_key(key)) {
+                None => Ok(false),
+                Some(Some(contains)) => Ok(contains),
+                Some(None) => Ok(self.db.get_pinned_cf(self.tree_cf(), key)?.is_some()),
+            },
+        )
+    }
+
+    /// Gets the values for multiple keys, in a single walk of the in-memory
+    /// tree. Keys in pruned parts of the tree are fetched from the backing
+    /// store. The returned values are in the same order as `keys`, with `None`
+    /// for keys which are not in the store.
+    pub fn get_many(&self, keys: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut values = vec![None; keys.len()];
+
+        let mut sorted_keys: Vec<_> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| (i, key.as_slice()))
+            .collect();
+        sorted_keys.sort_by(|a, b| a.1.cmp(b.1));
+
+        self.use_tree(|maybe_tree| match maybe_tree {
+            Some(tree) => get_many(tree, &self.source(), &sorted_keys, &mut values),
+            None => Ok(()),
+        })?;
+
+        Ok(values)
+    }
+
+    /// Returns the smallest key in the store, or `None` if the store is empty.
+    /// This walks the left edge of the tree, fetching pruned nodes from the
+    /// backing store if necessary.
+    pub fn first_key(&self) -> Result<Option<Vec<u8>>> {
+        self.use_tree(|maybe_tree| {
+            maybe_tree
+                .map(|tree| edge_key(tree, &self.source(), true))
+                .transpose()
+        })
+    }
+
+    /// Returns the largest key in the store, or `None` if the store is empty.
+    /// This walks the right edge of the tree, fetching pruned nodes from the
+    /// backing store if necessary.
+    pub fn last_key(&self) -> Result<Option<Vec<u8>>> {
+        self.use_tree(|maybe_tree| {
+            maybe_tree
+                .map(|tree| edge_key(tree, &self.source(), false))
+                .transpose()
+        })
+    }
+
     /// Returns the root hash of the tree (a digest for the entire store which
     /// proofs can be checked against). If the tree is empty, returns the null
     /// hash (zero-filled).
@@ -127,6 +828,12 @@ impl Merk {
         self.use_tree(root_hash)
     }
 
+    /// Returns the key of the root node of the tree, or `None` if the tree is
+    /// empty.
+    pub fn root_key(&self) -> Option<Vec<u8>> {
+        self.use_tree(|maybe_tree| maybe_tree.map(|tree| tree.key().to_vec()))
+    }
+
     /// Applies a batch of operations (puts and deletes) to the tree.
     ///
     /// This will fail if the keys in `batch` are not sorted and unique. This
@@ -148,22 +855,7 @@ impl Merk {
     /// store.apply(batch, &[]).unwrap();
     /// ```
     pub fn apply(&mut self, batch: &Batch, aux: &Batch) -> Result<()> {
-        // ensure keys in batch are sorted and unique
-        let mut maybe_prev_key: Option<Vec<u8>> = None;
-        for (key, _) in batch.iter() {
-            if let Some(prev_key) = maybe_prev_key {
-                match prev_key.cmp(key) {
-                    Ordering::Greater => {
-                        return Err(Error::BatchKey("Keys in batch must be sorted".into()));
-                    }
-                    Ordering::Equal => {
-                        return Err(Error::BatchKey("Keys in batch must be unique".into()));
-                    }
-                    _ => (),
-                }
-            }
-            maybe_prev_key = Some(key.to_vec());
-        }
+        self.validate_batch(batch)?;
 
         unsafe { self.apply_unchecked(batch, aux) }
     }
@@ -198,60 +890,310 @@ impl Merk {
         drop(tree);
 
         // commit changes to db
-        self.commit(deleted_keys, aux)
+        self.commit_batch(batch, deleted_keys, aux, |_| Ok(()))
+            .map(|_| ())
     }
 
-    /// Closes the store and deletes all data from disk.
-    pub fn destroy(self) -> Result<()> {
-        let opts = Merk::default_db_opts();
-        let path = self.path.clone();
-        drop(self);
-        rocksdb::DB::destroy(&opts, path)?;
+    /// Applies a batch of operations like `apply_unchecked`, but in debug
+    /// builds also validates that the keys in `batch` are sorted and unique
+    /// before applying it, and checks the invariants of the in-memory tree
+    /// afterwards (see `test_utils::assert_tree_invariants`). Panics with a
+    /// description of the violated invariant if any check fails.
+    ///
+    /// In release builds this skips the checks, so it is exactly as fast as
+    /// `apply_unchecked`.
+    ///
+    /// # Safety
+    /// In release builds, the keys in `batch` must be sorted and unique, as
+    /// for `apply_unchecked`.
+    pub unsafe fn apply_debug_checked(&mut self, batch: &Batch, aux: &Batch) -> Result<()> {
+        #[cfg(debug_assertions)]
+        if let Err(err) = check_batch(batch) {
+            panic!("Invalid batch: {}", err);
+        }
+
+        self.apply_unchecked(batch, aux)?;
+
+        #[cfg(debug_assertions)]
+        self.use_tree(|maybe_tree| {
+            if let Some(tree) = maybe_tree {
+                crate::test_utils::assert_tree_invariants(tree);
+            }
+        });
+
         Ok(())
     }
 
-    /// Completely rebuilds the tree, keeping all the same stored keys and
-    /// values.
-    pub fn repair(self) -> Result<Self> {
-        use rocksdb::IteratorMode;
+    /// Applies a batch of operations like `apply`, returning the keys of the
+    /// tree nodes which were written or deleted in the backing store (in key
+    /// order), along with `true` if the key was deleted.
+    ///
+    /// Along with the keys in `batch`, this includes the keys of any existing
+    /// nodes which were rewritten because their children changed (e.g. from
+    /// rebalancing or updated child hashes). The values of these keys are
+    /// unchanged.
+    pub fn apply_returning_changes(
+        &mut self,
+        batch: &Batch,
+        aux: &Batch,
+    ) -> Result<Vec<(Vec<u8>, bool)>> {
+        self.validate_batch(batch)?;
 
-        let path = self.path.clone();
+        let mut tree = self.tree.write().unwrap();
+        let maybe_walker = tree.take().map(|tree| Walker::new(tree, self.source()));
 
-        let create_path = |suffix| {
-            let mut tmp_path = path.clone();
-            let tmp_file_name =
-                format!("{}-{}", path.file_name().unwrap().to_str().unwrap(), suffix);
-            tmp_path.set_file_name(tmp_file_name);
-            tmp_path
-        };
+        let (maybe_tree, deleted_keys) = Walker::apply_to(maybe_walker, batch, self.source())?;
+        *tree = maybe_tree;
+        drop(tree);
 
-        let tmp_path = create_path("repair1");
-        let tmp = Merk::open(&tmp_path)?;
-        tmp.destroy()?;
+        self.commit_batch(batch, deleted_keys, aux, |_| Ok(()))
+    }
 
-        // TODO: split up batch
-        let mut node = Tree::new(vec![], vec![])?;
-        let batch: Vec<_> = self
-            .db
-            .iterator(IteratorMode::Start)
-            .map(|entry| {
-                let (key, node_bytes) = entry.unwrap(); // TODO
-                node.decode_into(vec![], &node_bytes);
-                (key.to_vec(), Op::Put(node.value().to_vec()))
+    /// Applies a read-modify-write merge function to each of the given keys.
+    /// The current values of all keys are read in a single walk of the tree,
+    /// then the resulting puts and deletes are applied as one batch. Keys must
+    /// be unique, but do not need to be sorted.
+    pub fn apply_with_merges(&mut self, merges: &[(Vec<u8>, MergeFn)]) -> Result<()> {
+        let keys: Vec<_> = merges.iter().map(|(key, _)| key.clone()).collect();
+        let values = self.get_many(&keys)?;
+
+        let mut batch: Vec<_> = merges
+            .iter()
+            .zip(values)
+            .filter_map(|((key, merge), value)| {
+                let exists = value.is_some();
+                match merge(value.as_deref()) {
+                    Some(value) => Some((key.clone(), Op::Put(value))),
+                    None if exists => Some((key.clone(), Op::Delete)),
+                    // nothing to delete
+                    None => None,
+                }
             })
             .collect();
+        batch.sort_by(|a, b| a.0.cmp(&b.0));
 
-        let aux_cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
-        let aux: Vec<_> = self
-            .db
-            .iterator_cf(aux_cf, IteratorMode::Start)
-            .map(|entry| {
-                let (key, value) = entry.unwrap(); // TODO
-                (key.to_vec(), Op::Put(value.to_vec()))
+        self.apply(&batch, &[])
+    }
+
+    /// Applies a batch of operations like `apply`, but first removes operations
+    /// which would not change the tree: puts of the value a key already has,
+    /// deletes of keys which do not exist, and empty appends. Returns `true` if
+    /// any entries in the tree changed.
+    ///
+    /// The current values are read in a single walk of the tree. If nothing
+    /// changes and `aux` is empty, nothing is written, so the tree's nodes are
+    /// not rewritten.
+    pub fn apply_if_changed(&mut self, batch: &Batch, aux: &Batch) -> Result<bool> {
+        self.validate_batch(batch)?;
+
+        let keys: Vec<_> = batch.iter().map(|(key, _)| key.clone()).collect();
+        let values = self.get_many(&keys)?;
+
+        let changed: Vec<BatchEntry> = batch
+            .iter()
+            .zip(values)
+            .filter_map(|((key, op), value)| match op {
+                Op::Put(new_value) if value.as_ref() != Some(new_value) => {
+                    Some((key.clone(), Op::Put(new_value.clone())))
+                }
+                Op::Delete if value.is_some() => Some((key.clone(), Op::Delete)),
+                Op::Append(suffix) if !suffix.is_empty() => {
+                    Some((key.clone(), Op::Append(suffix.clone())))
+                }
+                _ => None,
             })
             .collect();
 
-        drop(self);
+        if changed.is_empty() && aux.is_empty() {
+            return Ok(false);
+        }
+
+        unsafe { self.apply_unchecked(&changed, aux)? };
+        Ok(!changed.is_empty())
+    }
+
+    /// Applies a batch of operations like `apply`, returning the root hash of
+    /// the tree after the changes have been committed.
+    pub fn apply_returning_hash(&mut self, batch: &Batch, aux: &Batch) -> Result<Hash> {
+        self.apply(batch, aux)?;
+
+        Ok(self
+            .tree
+            .get_mut()
+            .unwrap()
+            .as_ref()
+            .map_or(NULL_HASH, |tree| tree.hash()))
+    }
+
+    /// Applies a batch of operations to the tree like `apply`, but applies the
+    /// operations for the root node's left and right subtrees in parallel. The
+    /// resulting tree is identical to the one produced by `apply`.
+    pub fn apply_parallel(&mut self, batch: &Batch, aux: &Batch) -> Result<()> {
+        self.validate_batch(batch)?;
+
+        let mut tree = self.tree.write().unwrap();
+        let maybe_walker = tree.take().map(|tree| Walker::new(tree, self.source()));
+
+        let (maybe_tree, deleted_keys) =
+            Walker::apply_to_parallel(maybe_walker, batch, self.source())?;
+        *tree = maybe_tree;
+        drop(tree);
+
+        self.commit_batch(batch, deleted_keys, aux, |_| Ok(()))
+            .map(|_| ())
+    }
+
+    /// Applies a batch of operations to the tree like `apply`, calling
+    /// `before_write` with the RocksDB write batch before it is written. The
+    /// closure may add writes of its own, which are written atomically along
+    /// with the changes to the tree, or return an error to abort.
+    ///
+    /// If any step fails, nothing is written and the in-memory tree is
+    /// reloaded from disk, so the store is left unchanged.
+    pub fn apply_transactional<F>(
+        &mut self,
+        batch: &Batch,
+        aux: &Batch,
+        before_write: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&mut WriteBatch) -> Result<()>,
+    {
+        self.validate_batch(batch)?;
+
+        let mut tree = self.tree.write().unwrap();
+        let maybe_walker = tree.take().map(|tree| Walker::new(tree, self.source()));
+
+        match Walker::apply_to(maybe_walker, batch, self.source()) {
+            Ok((maybe_tree, deleted_keys)) => {
+                *tree = maybe_tree;
+                drop(tree);
+                self.commit_batch(batch, deleted_keys, aux, before_write)
+                    .map(|_| ())
+            }
+            Err(err) => {
+                drop(tree);
+                self.load_root()?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Computes the root hash the tree would have after applying the given
+    /// batch, without modifying the store. The batch must be sorted and unique,
+    /// as with `apply`.
+    ///
+    /// Only the root node is copied, the rest of the tree is read from the
+    /// backing store as needed, so this does not change which nodes are kept
+    /// in memory.
+    pub fn compute_root_after(&self, batch: &Batch) -> Result<Hash> {
+        check_batch(batch)?;
+
+        let maybe_tree = self.use_tree(|maybe_tree| {
+            maybe_tree.map(|tree| {
+                Tree::decode(tree.key().to_vec(), tree.encode().as_slice())
+                    .with_hash_algorithm(self.hash_algorithm)
+            })
+        });
+        let maybe_walker = maybe_tree.map(|tree| Walker::new(tree, self.source()));
+
+        let (maybe_tree, _) = Walker::apply_to(maybe_walker, batch, self.source())?;
+        match maybe_tree {
+            None => Ok(NULL_HASH),
+            Some(mut tree) => {
+                tree.commit(&mut NoopCommit {})?;
+                Ok(tree.hash())
+            }
+        }
+    }
+
+    /// Deletes all entries with keys in the given range (`range.start`
+    /// inclusive, `range.end` exclusive) in a single batch, along with applying
+    /// the given auxiliary batch. The keys to delete are read from the backing
+    /// store, so their values are never decoded.
+    pub fn delete_range(&mut self, range: Range<Vec<u8>>, aux: &Batch) -> Result<()> {
+        let mut batch = Vec::new();
+
+        let mut iter = self.raw_iter();
+        iter.seek(&range.start);
+        while iter.valid() {
+            let key = iter.key().unwrap();
+            if key >= range.end.as_slice() {
+                break;
+            }
+            batch.push((key.to_vec(), Op::Delete));
+            iter.next();
+        }
+        iter.status()?;
+        drop(iter);
+
+        // keys from the iterator are already sorted and unique
+        unsafe { self.apply_unchecked(&batch, aux) }
+    }
+
+    /// Closes the store and deletes all data from disk.
+    pub fn destroy(self) -> Result<()> {
+        if self.shared_db {
+            return Err(Error::Path(
+                "Cannot destroy a store opened on a shared database".into(),
+            ));
+        }
+
+        let opts = Merk::default_db_opts();
+        let path = self.path.clone();
+        drop(self);
+        rocksdb::DB::destroy(&opts, path)?;
+        Ok(())
+    }
+
+    /// Completely rebuilds the tree, keeping all the same stored keys and
+    /// values.
+    pub fn repair(self) -> Result<Self> {
+        use rocksdb::IteratorMode;
+
+        if self.shared_db {
+            return Err(Error::Path(
+                "Cannot repair a store opened on a shared database".into(),
+            ));
+        }
+
+        let path = self.path.clone();
+
+        let create_path = |suffix| {
+            let mut tmp_path = path.clone();
+            let tmp_file_name =
+                format!("{}-{}", path.file_name().unwrap().to_str().unwrap(), suffix);
+            tmp_path.set_file_name(tmp_file_name);
+            tmp_path
+        };
+
+        let tmp_path = create_path("repair1");
+        let tmp = Merk::open(&tmp_path)?;
+        tmp.destroy()?;
+
+        // TODO: split up batch
+        let mut node = Tree::new(vec![], vec![])?;
+        let batch: Vec<_> = self
+            .db
+            .iterator_cf(self.tree_cf(), IteratorMode::Start)
+            .map(|entry| {
+                let (key, node_bytes) = entry.unwrap(); // TODO
+                node.decode_into(vec![], &node_bytes);
+                (key.to_vec(), Op::Put(node.value().to_vec()))
+            })
+            .collect();
+
+        let aux_cf = self.cf(AUX_CF_NAME);
+        let aux: Vec<_> = self
+            .db
+            .iterator_cf(aux_cf, IteratorMode::Start)
+            .map(|entry| {
+                let (key, value) = entry.unwrap(); // TODO
+                (key.to_vec(), Op::Put(value.to_vec()))
+            })
+            .collect();
+
+        drop(self);
 
         let mut tmp = Self::open(&tmp_path)?;
         tmp.apply(&batch, &aux)?;
@@ -277,24 +1219,487 @@ impl Merk {
         Q: Into<QueryItem>,
         I: IntoIterator<Item = Q>,
     {
-        self.use_tree_mut(move |maybe_tree| prove(maybe_tree, self.source(), query))
+        self.use_tree_mut(move |maybe_tree| {
+            prove(maybe_tree, self.source(), query, None, None, true)
+        })
+    }
+
+    /// Creates a Merkle proof for the given `Query`. This is equivalent to
+    /// `prove`, except that the query's offset and limit are applied, so the
+    /// proof skips the first `offset` entries and includes at most `limit`
+    /// entries (counting from the highest keys if the query is reversed).
+    pub fn prove_query(&self, query: Query) -> Result<Vec<u8>> {
+        let (limit, offset, left_to_right) = (query.limit(), query.offset(), query.left_to_right());
+        self.use_tree_mut(move |maybe_tree| {
+            prove(
+                maybe_tree,
+                self.source(),
+                query,
+                limit,
+                offset,
+                left_to_right,
+            )
+        })
+    }
+
+    /// Creates a Merkle proof for the given `Query` like `prove_query`, also
+    /// returning a pair of flags which are `true` if the query extends past the
+    /// tree's lowest key (left) or highest key (right). In that case the proof
+    /// includes the edge of the tree on that side, so a verifier knows there
+    /// are no entries beyond the ones it contains.
+    pub fn prove_with_absence(&self, query: Query) -> Result<(Vec<u8>, (bool, bool))> {
+        let (limit, offset, left_to_right) = (query.limit(), query.offset(), query.left_to_right());
+        let (proof, absence) = self.use_tree_mut(move |maybe_tree| {
+            create_proof_with_absence(
+                maybe_tree,
+                self.source(),
+                query,
+                limit,
+                offset,
+                left_to_right,
+            )
+        })?;
+
+        let mut bytes = Vec::with_capacity(128);
+        encode_into(proof.iter(), &mut bytes);
+        Ok((bytes, absence))
+    }
+
+    /// Creates the operators of a proof for the given `Query` (applying its
+    /// offset and limit), without encoding them.
+    pub(crate) fn query_proof_ops(&self, query: &Query) -> Result<LinkedList<ProofOp>> {
+        self.use_tree_mut(|maybe_tree| {
+            create_proof(
+                maybe_tree,
+                self.source(),
+                query.iter().cloned(),
+                query.limit(),
+                query.offset(),
+                query.left_to_right(),
+            )
+        })
+    }
+
+    /// Gets the value for the given key along with a proof of it, walking the
+    /// tree only once. The value is `None` if the key does not exist, in which
+    /// case the proof proves its absence. The proof can be checked with
+    /// `verify`.
+    pub fn get_with_proof(&self, key: &[u8]) -> Result<(Option<Vec<u8>>, Vec<u8>)> {
+        let proof = self.use_tree_mut(|maybe_tree| {
+            create_proof(
+                maybe_tree,
+                self.source(),
+                vec![key.to_vec()],
+                None,
+                None,
+                true,
+            )
+        })?;
+
+        let value = proof.iter().find_map(|op| match op {
+            ProofOp::Push(Node::KV(node_key, value)) if node_key == key => Some(value.clone()),
+            _ => None,
+        });
+
+        let mut bytes = Vec::with_capacity(128);
+        encode_into(proof.iter(), &mut bytes);
+        Ok((value, bytes))
+    }
+
+    /// Creates a Merkle proof for the given `Query` like `prove_query`, but
+    /// writes it into the given buffer instead of allocating a new one. The
+    /// buffer is cleared first, so it can be reused across many proofs.
+    pub fn prove_into(&self, query: Query, buf: &mut Vec<u8>) -> Result<()> {
+        let (limit, offset, left_to_right) = (query.limit(), query.offset(), query.left_to_right());
+        let proof = self.use_tree_mut(move |maybe_tree| {
+            create_proof(
+                maybe_tree,
+                self.source(),
+                query,
+                limit,
+                offset,
+                left_to_right,
+            )
+        })?;
+
+        buf.clear();
+        encode_into(proof.iter(), buf);
+        Ok(())
+    }
+
+    /// Creates a proof for each of the given queries, encoded together as a
+    /// sequence of sections. Each section is a 4-byte big-endian length
+    /// followed by a proof which can be verified independently, so the proven
+    /// entries can be attributed to the query which requested them. Verify the
+    /// result with `merk::proofs::verify_sectioned`.
+    pub fn prove_sectioned(&self, queries: &[Query]) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(128);
+        for query in queries {
+            let proof = self.query_proof_ops(query)?;
+
+            let length: usize = proof.iter().map(|op| op.encoding_length()).sum();
+            let length: u32 = length.try_into()?;
+            bytes.extend_from_slice(&length.to_be_bytes());
+            encode_into(proof.iter(), &mut bytes);
+        }
+
+        Ok(bytes)
+    }
+
+    /// Creates a proof of the entry with the smallest key greater than `key`,
+    /// which also proves that no other keys lie between the two (including
+    /// `key` itself). If there is no such entry, the proof instead shows that
+    /// no keys greater than `key` exist. Verify it with
+    /// `proofs::query::verify_successor`.
+    pub fn prove_successor(&self, key: &[u8]) -> Result<Vec<u8>> {
+        // the smallest key greater than `key` is `key` followed by a zero byte
+        let mut start = key.to_vec();
+        start.push(0);
+
+        let mut query = Query::new();
+        query.insert_range_from(start..);
+        query.set_limit(1);
+        self.prove_query(query)
+    }
+
+    /// Creates a proof of the entry with the largest key less than `key`, like
+    /// `prove_successor`. Verify it with `proofs::query::verify_predecessor`.
+    pub fn prove_predecessor(&self, key: &[u8]) -> Result<Vec<u8>> {
+        let mut query = Query::new();
+        query.insert_range_to(..key.to_vec());
+        query.set_limit(1);
+        query.set_reverse();
+        self.prove_query(query)
+    }
+
+    /// Creates a proof that the given key does not exist in the store. Only
+    /// the keys of the neighboring entries on either side of the key are
+    /// included (without their values), so the proof stays small. Returns an
+    /// error if the key exists.
+    pub fn prove_absence(&self, key: &[u8]) -> Result<Vec<u8>> {
+        let proof = self.use_tree_mut(|maybe_tree| {
+            create_proof(
+                maybe_tree,
+                self.source(),
+                vec![key.to_vec()],
+                None,
+                None,
+                true,
+            )
+        })?;
+
+        let exists = proof
+            .iter()
+            .any(|op| matches!(op, ProofOp::Push(Node::KV(node_key, _)) if node_key == key));
+        if exists {
+            return Err(Error::Key(format!(
+                "Cannot prove absence of existing key: {key:?}"
+            )));
+        }
+
+        let mut bytes = Vec::with_capacity(128);
+        encode_into(proof.iter(), &mut bytes);
+        Ok(bytes)
+    }
+
+    /// Creates a proof that no keys exist in the given range, e.g. after the
+    /// range has been deleted. The proof includes the entries just outside
+    /// either end of the range (if any), showing that nothing lies between
+    /// them. Verify it with `proofs::query::verify_range_empty`. Returns an
+    /// error if the range contains any keys.
+    pub fn prove_range_empty(&self, range: Range<Vec<u8>>) -> Result<Vec<u8>> {
+        let mut query = Query::new();
+        query.insert_range(range.clone());
+        let proof = self.query_proof_ops(&query)?;
+
+        let existing = proof.iter().find_map(|op| match op {
+            ProofOp::Push(Node::KV(key, _)) if range.contains(key) => Some(key),
+            _ => None,
+        });
+        if let Some(key) = existing {
+            return Err(Error::Key(format!(
+                "Cannot prove range is empty, it contains key {key:?}"
+            )));
+        }
+
+        let mut bytes = Vec::with_capacity(128);
+        encode_into(proof.iter(), &mut bytes);
+        Ok(bytes)
+    }
+
+    /// Returns the length in bytes of the proof `prove_query` would create for
+    /// the given `Query`, without encoding it. This can be used to reject
+    /// queries which would produce proofs which are too large.
+    pub fn prove_size_estimate(&self, query: &Query) -> Result<usize> {
+        let (limit, offset, left_to_right) = (query.limit(), query.offset(), query.left_to_right());
+        self.use_tree_mut(move |maybe_tree| {
+            let proof = create_proof(
+                maybe_tree,
+                self.source(),
+                query.iter().cloned(),
+                limit,
+                offset,
+                left_to_right,
+            )?;
+            Ok(proof.iter().map(|op| op.encoding_length()).sum())
+        })
+    }
+
+    /// Returns an iterator over the entries in the store in key order, yielding
+    /// the decoded key/value pair of each tree node. Auxiliary data is not
+    /// included.
+    ///
+    /// The iterator is double-ended, so it can also be consumed from the end
+    /// (see `iter_rev`).
+    pub fn iter(&self) -> EntryIter<'_> {
+        EntryIter::new(&self.db, self.tree_cf())
+    }
+
+    /// Returns an iterator over the keys in the store in key order, without
+    /// decoding the tree nodes. Auxiliary data is not included.
+    pub fn keys(&self) -> impl Iterator<Item = Result<Vec<u8>>> + '_ {
+        let mut iter = self.raw_iter();
+        iter.seek_to_first();
+
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            if !iter.valid() {
+                done = true;
+                return iter.status().err().map(|err| Err(err.into()));
+            }
+
+            let key = iter.key().map(|key| key.to_vec());
+            iter.next();
+            key.map(Ok)
+        })
+    }
+
+    /// Returns an iterator over the entries in the store in reverse key order,
+    /// yielding the decoded key/value pair of each tree node. Auxiliary data is
+    /// not included.
+    pub fn iter_rev(&self) -> std::iter::Rev<EntryIter<'_>> {
+        self.iter().rev()
+    }
+
+    /// Returns statistics about the shape and size of the tree. This visits
+    /// every node, fetching pruned nodes from the backing store without
+    /// keeping them in memory.
+    pub fn stats(&self) -> Result<TreeStats> {
+        self.use_tree(|maybe_tree| {
+            let mut stats = TreeStats::default();
+            if let Some(tree) = maybe_tree {
+                stats.height = tree.height();
+                visit_nodes(tree, &self.source(), &mut |node| {
+                    stats.node_count += 1;
+                    stats.key_bytes += node.key().len() as u64;
+                    stats.value_bytes += node.value().len() as u64;
+                    Ok(())
+                })?;
+            }
+            Ok(stats)
+        })
+    }
+
+    /// Returns the number of nodes in the tree with a balance factor of -1, 0,
+    /// and +1 respectively. Errors if any node is unbalanced (with a balance
+    /// factor outside of that range), which would indicate a bug in
+    /// rebalancing. Like `stats`, this visits every node.
+    pub fn balance_histogram(&self) -> Result<[u64; 3]> {
+        self.use_tree(|maybe_tree| {
+            let mut histogram = [0; 3];
+            if let Some(tree) = maybe_tree {
+                visit_nodes(tree, &self.source(), &mut |node| {
+                    let balance_factor = node.balance_factor();
+                    if balance_factor.abs() > 1 {
+                        return Err(Error::Tree(format!(
+                            "Node {:?} has balance factor {}",
+                            node.key(),
+                            balance_factor
+                        )));
+                    }
+                    histogram[(balance_factor + 1) as usize] += 1;
+                    Ok(())
+                })?;
+            }
+            Ok(histogram)
+        })
+    }
+
+    /// Renders the whole tree as a diagram for debugging, with one line per
+    /// node showing its key, height and balance factor. Left children are
+    /// drawn above their parent and right children below, in the style of
+    /// the `Debug` impl for `Tree`. Pruned nodes are fetched from the
+    /// backing store, so this reads every node.
+    pub fn print_tree(&self) -> Result<String> {
+        self.use_tree(|maybe_tree| {
+            let mut out = String::new();
+            if let Some(tree) = maybe_tree {
+                render_tree(tree, &self.source(), &mut vec![], false, &mut out)?;
+            }
+            Ok(out)
+        })
+    }
+
+    /// Returns the keys of the in-memory nodes which have changed since the
+    /// last commit, in key order: those whose links are `Modified` or
+    /// `Uncommitted`. The root node has no link, so it is not included.
+    ///
+    /// `apply` and the other public apply methods always commit their changes
+    /// (upgrading the links to `Loaded`), so through the public API this
+    /// always returns an empty list. It only shows changes made to the
+    /// in-memory tree without going through `apply`, e.g. by code within this
+    /// crate which applies a batch directly with `Walker::apply_to`.
+    pub fn pending_changes(&self) -> Vec<Vec<u8>> {
+        self.use_tree(|maybe_tree| {
+            let mut keys = vec![];
+            if let Some(tree) = maybe_tree {
+                collect_pending(tree, false, &mut keys);
+            }
+            keys
+        })
+    }
+
+    /// Returns the number of entries in the store, by iterating over all the
+    /// tree nodes in the main column family. Auxiliary data is not counted.
+    pub fn len(&self) -> Result<u64> {
+        let mut count = 0;
+        for entry in self.db.iterator_cf(self.tree_cf(), IteratorMode::Start) {
+            entry?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Returns `true` if the store contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.use_tree(|maybe_tree| maybe_tree.is_none())
+    }
+
+    /// Creates a proof of the number of entries in the store, which can be
+    /// verified with `merk::proofs::verify_len`. The proof includes a hash for
+    /// every node in the tree, so its size is linear in the number of entries,
+    /// and every node will be loaded into memory until the next commit.
+    pub fn len_proof(&self) -> Result<Vec<u8>> {
+        self.walk(|maybe_walker| {
+            let mut bytes = vec![];
+            if let Some(mut walker) = maybe_walker {
+                let proof = walker.create_len_proof()?;
+                encode_into(proof.iter(), &mut bytes);
+            }
+            Ok(bytes)
+        })
     }
 
     pub fn flush(&self) -> Result<()> {
         Ok(self.db.flush()?)
     }
 
+    /// Compacts the whole backing store, including the auxiliary, internal and
+    /// snapshot data, so that space used by deleted or overwritten entries is
+    /// reclaimed without waiting for RocksDB to compact in the background.
+    pub fn compact(&self) -> Result<()> {
+        for cf in self.cf_handles() {
+            self.db.compact_range_cf::<&[u8], &[u8]>(cf, None, None);
+        }
+        Ok(())
+    }
+
+    /// Returns the approximate size in bytes of the store on disk, summing the
+    /// size of the SST files of all column families. Data which has not yet
+    /// been flushed from memory is not included.
+    pub fn size_on_disk(&self) -> Result<u64> {
+        const PROPERTY: &str = "rocksdb.total-sst-files-size";
+
+        let mut size = 0;
+        for cf in self.cf_handles() {
+            size += self.db.property_int_value_cf(cf, PROPERTY)?.unwrap_or(0);
+        }
+        Ok(size)
+    }
+
+    /// Compacts the tree nodes with keys in the given range (`start` inclusive,
+    /// `end` exclusive) in the backing store.
+    pub fn compact_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        self.db
+            .compact_range_cf(self.tree_cf(), Some(start), Some(end));
+        Ok(())
+    }
+
     pub fn commit(&mut self, deleted_keys: LinkedList<Vec<u8>>, aux: &Batch) -> Result<()> {
-        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
-        let aux_cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
+        self.commit_with(deleted_keys, aux, |_| Ok(())).map(|_| ())
+    }
 
+    /// Commits the tree like `commit`, but calls `before_write` with the write
+    /// batch before it is written, which may add writes of its own or return
+    /// an error to abort the commit. All writes are applied atomically.
+    ///
+    /// If the batch can not be written, the in-memory tree is reloaded from
+    /// disk so the two do not diverge.
+    ///
+    /// Returns the keys of the tree nodes which were written or deleted (in
+    /// key order), along with whether or not they were deleted.
+    fn commit_with<F>(
+        &mut self,
+        deleted_keys: LinkedList<Vec<u8>>,
+        aux: &Batch,
+        before_write: F,
+    ) -> Result<Vec<(Vec<u8>, bool)>>
+    where
+        F: FnOnce(&mut WriteBatch) -> Result<()>,
+    {
         let mut batch = rocksdb::WriteBatch::default();
+        let to_batch = self.prepare_commit(deleted_keys, aux, &mut batch)?;
+
+        // write to db
+        let res = before_write(&mut batch).and_then(|_| self.write(batch));
+        if res.is_err() {
+            self.load_root()?;
+        }
+
+        res.map(|_| {
+            to_batch
+                .into_iter()
+                .map(|(key, maybe_value)| (key, maybe_value.is_none()))
+                .collect()
+        })
+    }
+
+    /// Commits the in-memory tree like `commit`, but adds the writes to `batch`
+    /// instead of writing them to the backing store. Nodes are pruned from
+    /// memory as if the writes had happened, so if the batch is not written,
+    /// the root must be reloaded with `load_root`.
+    ///
+    /// Returns the tree nodes which were written or deleted (in key order),
+    /// along with their encoded values (`None` if they were deleted).
+    pub(crate) fn prepare_commit(
+        &mut self,
+        deleted_keys: LinkedList<Vec<u8>>,
+        aux: &Batch,
+        batch: &mut WriteBatch,
+    ) -> UseTreeMutResult {
+        if let Some(root) = self.tree.get_mut().unwrap().as_ref() {
+            self.prune_strategy.prepare(root);
+        }
+
+        let internal_cf = self.cf(INTERNAL_CF_NAME);
+        let aux_cf = self.cf(AUX_CF_NAME);
+
         let mut to_batch = self.use_tree_mut(|maybe_tree| -> UseTreeMutResult {
             // TODO: concurrent commit
             if let Some(tree) = maybe_tree {
                 // TODO: configurable committer
-                let mut committer = MerkCommitter::new(tree.height(), 21);
-                tree.commit(&mut committer)?;
+                let capacity = self
+                    .commit_capacity
+                    .unwrap_or_else(|| default_commit_capacity(tree.height()));
+                let mut committer = MerkCommitter::new(self.prune_strategy.as_ref(), capacity);
+                if self.inorder_commit {
+                    tree.commit_inorder(&mut committer)?;
+                } else {
+                    tree.commit(&mut committer)?;
+                }
 
                 // update pointer to root node
                 batch.put_cf(internal_cf, ROOT_KEY_KEY, tree.key());
@@ -313,11 +1718,12 @@ impl Merk {
             to_batch.push((key, None));
         }
         to_batch.sort_by(|a, b| a.0.cmp(&b.0));
-        for (key, maybe_value) in to_batch {
+        let tree_cf = self.tree_cf();
+        for (key, maybe_value) in to_batch.iter() {
             if let Some(value) = maybe_value {
-                batch.put(key, value);
+                batch.put_cf(tree_cf, key, value);
             } else {
-                batch.delete(key);
+                batch.delete_cf(tree_cf, key);
             }
         }
 
@@ -325,13 +1731,15 @@ impl Merk {
             match value {
                 Op::Put(value) => batch.put_cf(aux_cf, key, value),
                 Op::Delete => batch.delete_cf(aux_cf, key),
+                Op::Append(suffix) => {
+                    let mut value = self.db.get_cf(aux_cf, key)?.unwrap_or_default();
+                    value.extend_from_slice(suffix);
+                    batch.put_cf(aux_cf, key, value)
+                }
             };
         }
 
-        // write to db
-        self.write(batch)?;
-
-        Ok(())
+        Ok(to_batch)
     }
 
     pub fn walk<T>(&self, f: impl FnOnce(Option<RefWalker<MerkSource>>) -> T) -> T {
@@ -342,264 +1750,2789 @@ impl Merk {
         f(maybe_walker)
     }
 
-    pub fn raw_iter(&self) -> rocksdb::DBRawIterator {
-        self.db.raw_iterator()
-    }
+    /// Returns a low-level RocksDB iterator over the encoded tree nodes. The
+    /// iterator is not positioned until one of its `seek` methods is called,
+    /// and `valid` must be checked before each call to `key`, `value` or
+    /// `next`. Prefer `iter` or `keys` unless raw access is needed.
+    pub fn raw_iter(&self) -> rocksdb::DBRawIterator {
+        self.db.raw_iterator_cf(self.tree_cf())
+    }
+
+    /// Creates a checkpoint of the backing store at the given path and opens
+    /// it. For a store opened with `open_on_db`, the checkpoint contains the
+    /// whole shared database, and the returned store is opened on it with the
+    /// same column family prefix.
+    ///
+    /// The memtables of all of the store's column families are flushed before
+    /// the checkpoint is created, so it contains all committed writes.
+    pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<Merk> {
+        self.flush_cfs()?;
+        Checkpoint::new(&self.db)?.create_checkpoint(&path)?;
+        if !self.shared_db {
+            return Merk::open(path);
+        }
+
+        let db_opts = Merk::default_db_opts();
+        let cfs = rocksdb::DB::list_cf(&db_opts, &path)?
+            .into_iter()
+            .map(|name| ColumnFamilyDescriptor::new(name, Merk::default_db_opts()));
+        let db = rocksdb::DB::open_cf_descriptors(&db_opts, &path, cfs)?;
+        Merk::open_on_db(Arc::new(db), self.cf_prefix.as_bytes())
+    }
+
+    /// Creates a checkpoint like `checkpoint`, but also syncs the source
+    /// store's write-ahead log to disk first. Once this returns, every write
+    /// committed before the call is both contained in the checkpoint and
+    /// durable in the source store, regardless of the store's `WriteConfig`.
+    pub fn checkpoint_consistent<P: AsRef<Path>>(&self, path: P) -> Result<Merk> {
+        self.db.flush_wal(true)?;
+        self.checkpoint(path)
+    }
+
+    /// Flushes the memtables of all of the store's column families to disk.
+    fn flush_cfs(&self) -> Result<()> {
+        for cf in self.cf_handles() {
+            self.db.flush_cf(cf)?;
+        }
+        Ok(())
+    }
+
+    /// Creates a copy of the store which can be modified without affecting the
+    /// original, e.g. to speculatively apply batches and then throw them away.
+    ///
+    /// The fork is a checkpoint (see `checkpoint`) in a new directory under the
+    /// system's temporary directory, so it is cheap to create since the files
+    /// are hard-linked rather than copied. The directory is deleted when the
+    /// fork is dropped. Settings such as the prune strategy are not copied.
+    pub fn fork(&self) -> Result<Merk> {
+        let fork_dir = ForkDir(ForkDir::create_path());
+        let mut fork = self.checkpoint(&fork_dir.0)?;
+        fork.fork_dir = Some(fork_dir);
+        Ok(fork)
+    }
+
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let snapshot = Snapshot::new(
+            self.db.snapshot(),
+            load_root(&self.db, &self.cf_prefix, self.hash_algorithm)?,
+        );
+        if self.cf_prefix.is_empty() {
+            return Ok(snapshot);
+        }
+
+        let cf_name = format!("{}{}", self.cf_prefix, TREE_CF_NAME);
+        Ok(snapshot.with_cf(self.tree_cf(), cf_name))
+    }
+
+    /// Creates a snapshot of the current state which can later be opened by
+    /// name with `open_snapshot`. Errors if a snapshot with the given name
+    /// already exists.
+    ///
+    /// Named snapshots are scoped to this `Merk` instance: the snapshot's data
+    /// is held by a RocksDB snapshot, which is released when the `Merk` is
+    /// dropped. Tree nodes are overwritten in place, so historical states can
+    /// not be loaded after reopening the store. Only the name and root hash are
+    /// persisted in the `snapshots` column family, so the name stays reserved
+    /// and `snapshot_root_hash` keeps working, but after reopening
+    /// `open_snapshot` only succeeds while the state has the same root hash.
+    pub fn snapshot_named(&self, name: &[u8]) -> Result<()> {
+        let snapshots_cf = self.snapshots_cf().ok_or_else(|| {
+            Error::Path("The store was opened without a snapshots column family".into())
+        })?;
+        if self.db.get_pinned_cf(snapshots_cf, name)?.is_some() {
+            return Err(Error::Key(format!("Snapshot {name:?} already exists")));
+        }
+
+        let snapshot = self.snapshot()?;
+        self.db.put_cf(snapshots_cf, name, snapshot.root_hash())?;
+        self.named_snapshots
+            .write()
+            .unwrap()
+            .insert(name.to_vec(), snapshot.staticize());
+
+        Ok(())
+    }
+
+    /// Opens the snapshot created with the given name by `snapshot_named`. If
+    /// the snapshot was created by a previous `Merk` instance (e.g. before the
+    /// store was reopened), its data is no longer available and an error is
+    /// returned unless the current state has the snapshot's root hash.
+    pub fn open_snapshot(&self, name: &[u8]) -> Result<Snapshot<'_>> {
+        if let Some(ss) = self.named_snapshots.read().unwrap().get(name) {
+            // the snapshot can only be released by `remove_snapshot` or when
+            // the `Merk` is dropped, both of which require no outstanding
+            // borrows
+            return Ok(unsafe { ss.with_db(&self.db) });
+        }
+
+        let root_hash = self
+            .snapshot_root_hash(name)?
+            .ok_or_else(|| Error::KeyNotFound(format!("Snapshot {name:?} does not exist")))?;
+        if root_hash != self.root_hash() {
+            return Err(Error::Key(format!(
+                "Data for snapshot {name:?} is no longer available"
+            )));
+        }
+
+        self.snapshot()
+    }
+
+    /// Returns the root hash of the snapshot created with the given name by
+    /// `snapshot_named`, or `None` if there is no snapshot with that name.
+    pub fn snapshot_root_hash(&self, name: &[u8]) -> Result<Option<Hash>> {
+        let snapshots_cf = match self.snapshots_cf() {
+            None => return Ok(None),
+            Some(cf) => cf,
+        };
+        self.db
+            .get_pinned_cf(snapshots_cf, name)?
+            .map(|record| {
+                record
+                    .get(..HASH_LENGTH)
+                    .and_then(|hash| hash.try_into().ok())
+                    .ok_or_else(|| Error::Key(format!("Invalid record for snapshot {name:?}")))
+            })
+            .transpose()
+    }
+
+    /// Returns a snapshot created by `snapshot_named` with the given root hash,
+    /// if one is held in memory.
+    pub(crate) fn named_snapshot_by_root_hash(&self, root_hash: Hash) -> Option<Snapshot<'_>> {
+        self.named_snapshots
+            .read()
+            .unwrap()
+            .values()
+            // the snapshot can only be released by `remove_snapshot` or when the
+            // `Merk` is dropped, both of which require no outstanding borrows
+            .map(|ss| unsafe { ss.with_db(&self.db) })
+            .find(|ss| ss.root_hash() == root_hash)
+    }
+
+    /// Removes the snapshot created with the given name by `snapshot_named`,
+    /// allowing its data to be compacted.
+    pub fn remove_snapshot(&mut self, name: &[u8]) -> Result<()> {
+        if let Some(snapshots_cf) = self.snapshots_cf() {
+            self.db.delete_cf(snapshots_cf, name)?;
+        }
+
+        if let Some(ss) = self.named_snapshots.get_mut().unwrap().remove(name) {
+            unsafe { ss.drop(&self.db) };
+        }
+
+        Ok(())
+    }
+
+    pub fn db(&self) -> &DB {
+        &self.db
+    }
+
+    /// Returns the algorithm used for KV hashes and node hashes in this store.
+    /// Proofs created by this store must be verified with the same algorithm.
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+
+    fn source(&self) -> MerkSource {
+        MerkSource {
+            db: &self.db,
+            cf: self.tree_cf(),
+            hash_algorithm: self.hash_algorithm,
+        }
+    }
+
+    /// Returns the handle of the column family with the given name, taking the
+    /// store's column family prefix into account.
+    fn cf(&self, name: &str) -> &ColumnFamily {
+        cf_handle(&self.db, &self.cf_prefix, name)
+    }
+
+    /// Returns the handles of all of the store's column families, skipping the
+    /// snapshots column family if the store has none.
+    fn cf_handles(&self) -> impl Iterator<Item = &ColumnFamily> {
+        CF_NAMES
+            .iter()
+            .filter_map(move |name| self.db.cf_handle(&format!("{}{}", self.cf_prefix, name)))
+    }
+
+    /// Returns the handle of the snapshots column family, or `None` if the
+    /// store was opened without one (see `REQUIRED_CF_NAMES`).
+    fn snapshots_cf(&self) -> Option<&ColumnFamily> {
+        self.db
+            .cf_handle(&format!("{}{}", self.cf_prefix, SNAPSHOTS_CF_NAME))
+    }
+
+    /// Returns the handle of the column family containing the tree nodes.
+    pub(crate) fn tree_cf(&self) -> &ColumnFamily {
+        self.cf(TREE_CF_NAME)
+    }
+
+    fn has_root_key(&self) -> Result<bool> {
+        has_root_key(&self.db, &self.cf_prefix)
+    }
+
+    fn use_tree<T>(&self, f: impl FnOnce(Option<&Tree>) -> T) -> T {
+        let tree = self.tree.read().unwrap();
+        f(tree.as_ref())
+    }
+
+    fn use_tree_mut<T>(&self, f: impl FnOnce(Option<&mut Tree>) -> T) -> T {
+        let mut tree_slot = self.tree.write().unwrap();
+        let mut tree = tree_slot.take();
+        let res = f(tree.as_mut());
+        *tree_slot = tree;
+        res
+    }
+
+    pub(crate) fn write(&mut self, batch: WriteBatch) -> Result<()> {
+        let mut opts = rocksdb::WriteOptions::default();
+        opts.set_sync(self.write_config.sync);
+        opts.disable_wal(self.write_config.disable_wal);
+        self.db.write_opt(batch, &opts)?;
+        Ok(())
+    }
+
+    pub(crate) fn set_root_key(&mut self, key: Vec<u8>) -> Result<()> {
+        let internal_cf = self.cf(INTERNAL_CF_NAME);
+        let mut batch = WriteBatch::default();
+        batch.put_cf(internal_cf, ROOT_KEY_KEY, key);
+        self.write(batch)
+    }
+
+    pub(crate) fn fetch_node(&self, key: &[u8]) -> Result<Option<Tree>> {
+        self.source().fetch_by_key(key)
+    }
+
+    pub(crate) fn load_root(&mut self) -> Result<()> {
+        let root = load_root(&self.db, &self.cf_prefix, self.hash_algorithm)?;
+        *self.tree.write().unwrap() = root;
+        Ok(())
+    }
+}
+
+impl Drop for Merk {
+    fn drop(&mut self) {
+        // release the RocksDB snapshots held for named snapshots
+        for (_, ss) in self.named_snapshots.get_mut().unwrap().drain() {
+            unsafe { ss.drop(&self.db) };
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MerkSource<'a> {
+    db: &'a rocksdb::DB,
+    cf: &'a ColumnFamily,
+    hash_algorithm: HashAlgorithm,
+}
+
+impl<'a> Fetch for MerkSource<'a> {
+    fn fetch_by_key(&self, key: &[u8]) -> Result<Option<Tree>> {
+        self.db
+            .get_pinned_cf(self.cf, key)?
+            .map(|bytes| {
+                Tree::try_decode(key.to_vec(), &bytes)
+                    .map(|tree| tree.with_hash_algorithm(self.hash_algorithm))
+                    .map_err(|err| Error::Corruption(key.to_vec(), err.to_string()))
+            })
+            .transpose()
+    }
+
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+}
+
+/// An iterator over the entries in the store, yielding the decoded key/value
+/// pair of each tree node. Created by `Merk::iter`.
+pub struct EntryIter<'a> {
+    front: rocksdb::DBRawIterator<'a>,
+    back: rocksdb::DBRawIterator<'a>,
+    done: bool,
+}
+
+impl<'a> EntryIter<'a> {
+    fn new(db: &'a DB, cf: &'a ColumnFamily) -> Self {
+        let mut front = db.raw_iterator_cf(cf);
+        front.seek_to_first();
+        let mut back = db.raw_iterator_cf(cf);
+        back.seek_to_last();
+
+        EntryIter {
+            front,
+            back,
+            done: false,
+        }
+    }
+
+    /// Decodes the entry at the front or back cursor and advances it. The
+    /// cursors always point at the next entry to be yielded from their end, so
+    /// the iteration is finished once they yield the same key.
+    fn next_from(&mut self, front: bool) -> Option<Result<(Vec<u8>, Vec<u8>)>> {
+        if self.done {
+            return None;
+        }
+
+        let (cursor, other) = if front {
+            (&mut self.front, &self.back)
+        } else {
+            (&mut self.back, &self.front)
+        };
+
+        let (key, bytes) = match (cursor.key(), cursor.value()) {
+            (Some(key), Some(bytes)) => (key, bytes),
+            _ => {
+                self.done = true;
+                return cursor.status().err().map(|err| Err(err.into()));
+            }
+        };
+
+        let tree = Tree::decode(key.to_vec(), bytes);
+        self.done = other.key() == Some(key);
+        if front {
+            cursor.next();
+        } else {
+            cursor.prev();
+        }
+
+        let value = tree.value().to_vec();
+        Some(Ok((tree.take_key(), value)))
+    }
+}
+
+impl<'a> Iterator for EntryIter<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_from(true)
+    }
+}
+
+impl<'a> DoubleEndedIterator for EntryIter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.next_from(false)
+    }
+}
+
+/// Decides which nodes are kept in memory after each commit. Pruned nodes are
+/// fetched from the backing store when they are needed again.
+pub trait PruneStrategy: Send + Sync {
+    /// Called at the start of each commit with the root node of the tree,
+    /// before any calls to `should_prune`.
+    fn prepare(&mut self, _root: &Tree) {}
+
+    /// Called once per updated node after it has been written. The returned
+    /// tuple specifies whether or not to prune the left and right child nodes
+    /// from memory, respectively.
+    fn should_prune(&self, tree: &Tree) -> (bool, bool);
+}
+
+/// A `PruneStrategy` which keeps a fixed number of the top levels of the tree
+/// in memory.
+pub struct LevelPrune {
+    levels: u8,
+    height: u8,
+}
+
+impl LevelPrune {
+    /// Creates a `LevelPrune` which keeps the top `levels` levels of the tree
+    /// in memory.
+    pub fn new(levels: u8) -> Self {
+        LevelPrune { levels, height: 0 }
+    }
+}
+
+impl PruneStrategy for LevelPrune {
+    fn prepare(&mut self, root: &Tree) {
+        self.height = root.height();
+    }
+
+    fn should_prune(&self, tree: &Tree) -> (bool, bool) {
+        // keep N top levels of tree
+        let prune = (self.height - tree.height()) >= self.levels;
+        (prune, prune)
+    }
+}
+
+struct MerkCommitter<'a> {
+    batch: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    strategy: &'a dyn PruneStrategy,
+}
+
+impl<'a> MerkCommitter<'a> {
+    fn new(strategy: &'a dyn PruneStrategy, capacity: usize) -> Self {
+        MerkCommitter {
+            batch: Vec::with_capacity(capacity),
+            strategy,
+        }
+    }
+}
+
+/// Returns the initial committer capacity for a tree of the given height: the
+/// maximum number of nodes in a tree of that height, capped at
+/// `MAX_DEFAULT_COMMIT_CAPACITY`.
+fn default_commit_capacity(height: u8) -> usize {
+    if height as u32 >= usize::BITS {
+        return MAX_DEFAULT_COMMIT_CAPACITY;
+    }
+    ((1usize << height) - 1).min(MAX_DEFAULT_COMMIT_CAPACITY)
+}
+
+impl<'a> Commit for MerkCommitter<'a> {
+    fn write(&mut self, tree: &Tree) -> Result<()> {
+        let mut buf = Vec::with_capacity(tree.encoding_length());
+        tree.encode_into(&mut buf);
+        self.batch.push((tree.key().to_vec(), Some(buf)));
+        Ok(())
+    }
+
+    fn prune(&self, tree: &Tree) -> (bool, bool) {
+        self.strategy.should_prune(tree)
+    }
+}
+
+pub fn get<F: Fetch>(tree: &Tree, source: F, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    Ok(match tree.get_value(key)? {
+        GetResult::Found(value) => Some(value),
+        GetResult::NotFound => None,
+        GetResult::Pruned => source.fetch_by_key(key)?.map(|node| node.value().to_vec()),
+    })
+}
+
+/// Loads the nodes of the tree which are in or bound the given range, fetching
+/// pruned nodes from the source.
+fn load_range<F: Fetch>(tree: &mut Tree, source: &F, range: &Range<Vec<u8>>) -> Result<()> {
+    // the left subtree contains keys in the range, or the node bounding its
+    // start, if this key is not before the start (and vice versa for the
+    // right subtree and the end)
+    let key = tree.key();
+    let sides = [
+        (true, range.start.as_slice() <= key),
+        (false, key < range.end.as_slice()),
+    ];
+
+    for (left, visit) in sides {
+        if !visit {
+            continue;
+        }
+        match tree.link(left) {
+            None => continue,
+            Some(link) if link.is_reference() => tree.load(left, source)?,
+            Some(_) => {}
+        }
+        let child = tree.child_mut(left).expect("Expected loaded child");
+        load_range(child, source, range)?;
+    }
+
+    Ok(())
+}
+
+/// Loads the nodes which a proof of the given sorted query items walks through
+/// into memory, following the same traversal as `RefWalker::create_proof`.
+fn prefetch<F: Fetch>(tree: &mut Tree, source: &F, query: &[QueryItem]) -> Result<()> {
+    let node_key = QueryItem::Key(tree.key().to_vec());
+    let search = query.binary_search_by(|item| item.cmp(&node_key));
+    let (left_items, right_items) = split_query(query, search, tree.key());
+
+    for (left, items) in [(true, left_items), (false, right_items)] {
+        if items.is_empty() {
+            continue;
+        }
+        match tree.link(left) {
+            None => continue,
+            Some(link) if link.is_reference() => tree.load(left, source)?,
+            Some(_) => {}
+        }
+        let child = tree.child_mut(left).expect("Expected loaded child");
+        prefetch(child, source, items)?;
+    }
+
+    Ok(())
+}
+
+/// Gets the values for the given keys (sorted, with their indices in the
+/// output) by walking down the tree, writing each found value into `values`.
+fn get_many<F: Fetch>(
+    tree: &Tree,
+    source: &F,
+    keys: &[(usize, &[u8])],
+    values: &mut [Option<Vec<u8>>],
+) -> Result<()> {
+    let start = keys.partition_point(|(_, key)| *key < tree.key());
+    let end = keys.partition_point(|(_, key)| *key <= tree.key());
+    let (left_keys, rest) = keys.split_at(start);
+    let (found_keys, right_keys) = rest.split_at(end - start);
+
+    let mut get_child = |left: bool, keys: &[(usize, &[u8])]| -> Result<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        match tree.link(left).map(|link| link.tree()) {
+            None => {}
+            Some(Some(child)) => get_many(child, source, keys, values)?,
+            // the child is pruned, fetch the values from the backing store
+            Some(None) => {
+                for (i, key) in keys {
+                    values[*i] = source.fetch_by_key(key)?.map(|node| node.value().to_vec());
+                }
+            }
+        }
+
+        Ok(())
+    };
+
+    get_child(true, left_keys)?;
+    get_child(false, right_keys)?;
+
+    for (i, _) in found_keys {
+        values[*i] = Some(tree.value().to_vec());
+    }
+
+    Ok(())
+}
+
+/// Calls `visit` for `tree` and each of its descendants, in pre-order.
+/// Pruned nodes are fetched from `source` without being kept in memory.
+fn visit_nodes<F, V>(tree: &Tree, source: &F, visit: &mut V) -> Result<()>
+where
+    F: Fetch,
+    V: FnMut(&Tree) -> Result<()>,
+{
+    visit(tree)?;
+
+    for left in [true, false] {
+        match tree.link(left) {
+            None => {}
+            Some(link) => match link.tree() {
+                Some(child) => visit_nodes(child, source, visit)?,
+                None => {
+                    let child = source.fetch_by_key_expect(link.key())?;
+                    visit_nodes(&child, source, visit)?;
+                }
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Renders `tree` and its descendants for `Merk::print_tree`, in key order.
+/// `stack` holds the key ranges spanned by the links from each ancestor to its
+/// child, which determine where vertical lines are drawn.
+fn render_tree<F: Fetch>(
+    tree: &Tree,
+    source: &F,
+    stack: &mut Vec<(Vec<u8>, Vec<u8>)>,
+    left: bool,
+    out: &mut String,
+) -> Result<()> {
+    let render_child = |left: bool, stack: &mut Vec<_>, out: &mut String| -> Result<()> {
+        let link = match tree.link(left) {
+            None => return Ok(()),
+            Some(link) => link,
+        };
+        let span = if left {
+            (link.key().to_vec(), tree.key().to_vec())
+        } else {
+            (tree.key().to_vec(), link.key().to_vec())
+        };
+
+        stack.push(span);
+        match link.tree() {
+            Some(child) => render_tree(child, source, stack, left, out)?,
+            None => {
+                let child = source.fetch_by_key_expect(link.key())?;
+                render_tree(&child, source, stack, left, out)?;
+            }
+        }
+        stack.pop();
+
+        Ok(())
+    };
+
+    render_child(true, stack, out)?;
+
+    let depth = stack.len();
+    if depth > 0 {
+        // draw ancestor's vertical lines
+        for (low, high) in stack.iter().take(depth - 1) {
+            let draw_line = tree.key() > low.as_slice() && tree.key() < high.as_slice();
+            out.push_str(if draw_line { " │  " } else { "    " });
+        }
+        out.push_str(if left { " ┌-" } else { " └-" });
+    }
+    out.push_str(&format!(
+        "{:?} (height {}, balance {})\n",
+        tree.key(),
+        tree.height(),
+        tree.balance_factor()
+    ));
+
+    render_child(false, stack, out)
+}
+
+/// Appends the keys of `tree` (if `pending` is `true`) and of its descendants
+/// which are linked as `Modified` or `Uncommitted` to `keys`, in key order.
+/// Changes propagate up to the root, so only the children of changed nodes
+/// need to be visited.
+fn collect_pending(tree: &Tree, pending: bool, keys: &mut Vec<Vec<u8>>) {
+    let pending_child = |left: bool| {
+        tree.link(left)
+            .filter(|link| link.is_modified() || link.is_uncommitted())
+            .and_then(|link| link.tree())
+    };
+
+    if let Some(child) = pending_child(true) {
+        collect_pending(child, true, keys);
+    }
+    if pending {
+        keys.push(tree.key().to_vec());
+    }
+    if let Some(child) = pending_child(false) {
+        collect_pending(child, true, keys);
+    }
+}
+
+/// Ensures the keys in the batch are sorted and unique.
+fn check_batch(batch: &Batch) -> Result<()> {
+    // ensure keys in batch are within the length limit, sorted and unique
+    let mut maybe_prev_key: Option<&[u8]> = None;
+    for (index, (key, _)) in batch.iter().enumerate() {
+        if key.len() > MAX_KEY_LENGTH {
+            return Err(Error::KeyTooLong(key.len(), MAX_KEY_LENGTH));
+        }
+        if let Some(prev_key) = maybe_prev_key {
+            let problem = match prev_key.cmp(key) {
+                Ordering::Greater => Some("unsorted"),
+                Ordering::Equal => Some("duplicate"),
+                Ordering::Less => None,
+            };
+            if let Some(problem) = problem {
+                return Err(Error::BatchKey(format!(
+                    "Keys in batch must be sorted and unique, {} at index {}: key {}",
+                    problem,
+                    index,
+                    to_hex(key)
+                )));
+            }
+        }
+        maybe_prev_key = Some(key);
+    }
+
+    Ok(())
+}
+
+/// Formats bytes as a lowercase hex string, for use in error messages.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Returns the key of the leftmost (if `left` is `true`) or rightmost node
+/// under `tree`.
+fn edge_key<F: Fetch>(tree: &Tree, source: &F, left: bool) -> Result<Vec<u8>> {
+    let mut cursor = tree;
+    let mut key = loop {
+        match cursor.link(left) {
+            None => return Ok(cursor.key().to_vec()),
+            Some(link) => match link.tree() {
+                Some(child) => cursor = child,
+                None => break link.key().to_vec(),
+            },
+        }
+    };
+
+    // the rest of the edge is pruned, follow it in the backing store
+    loop {
+        let node = source.fetch_by_key_expect(&key)?;
+        match node.link(left) {
+            None => return Ok(key),
+            Some(link) => key = link.key().to_vec(),
+        }
+    }
+}
+
+fn root_hash(maybe_tree: Option<&Tree>) -> Hash {
+    maybe_tree.map_or(NULL_HASH, |tree| tree.hash())
+}
+
+fn prove<Q, I, F>(
+    maybe_tree: Option<&mut Tree>,
+    source: F,
+    query: I,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    left_to_right: bool,
+) -> Result<Vec<u8>>
+where
+    Q: Into<QueryItem>,
+    I: IntoIterator<Item = Q>,
+    F: Fetch + Send + Clone,
+{
+    let proof = create_proof(maybe_tree, source, query, limit, offset, left_to_right)?;
+
+    let mut bytes = Vec::with_capacity(128);
+    encode_into(proof.iter(), &mut bytes);
+    Ok(bytes)
+}
+
+/// Walks the tree to create the operators of a proof for the given query,
+/// without encoding them.
+fn create_proof<Q, I, F>(
+    maybe_tree: Option<&mut Tree>,
+    source: F,
+    query: I,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    left_to_right: bool,
+) -> Result<LinkedList<ProofOp>>
+where
+    Q: Into<QueryItem>,
+    I: IntoIterator<Item = Q>,
+    F: Fetch + Send + Clone,
+{
+    create_proof_with_absence(maybe_tree, source, query, limit, offset, left_to_right)
+        .map(|(proof, _)| proof)
+}
+
+/// Walks the tree to create the operators of a proof for the given query like
+/// `create_proof`, also returning whether the proof reaches the tree's left and
+/// right edges.
+fn create_proof_with_absence<Q, I, F>(
+    maybe_tree: Option<&mut Tree>,
+    source: F,
+    query: I,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    left_to_right: bool,
+) -> Result<(LinkedList<ProofOp>, (bool, bool))>
+where
+    Q: Into<QueryItem>,
+    I: IntoIterator<Item = Q>,
+    F: Fetch + Send + Clone,
+{
+    let query_vec: Vec<QueryItem> = query.into_iter().map(Into::into).collect();
+
+    let tree =
+        maybe_tree.ok_or_else(|| Error::Proof("Cannot create proof for empty tree".into()))?;
+
+    let mut ref_walker = RefWalker::new(tree, source);
+    let (proof, absence, ..) =
+        ref_walker.create_full_proof(query_vec.as_slice(), limit, offset, left_to_right)?;
+
+    Ok((proof, absence))
+}
+
+/// Converts a column family prefix to a string, since RocksDB column family
+/// names must be valid UTF-8.
+fn cf_prefix_str(cf_prefix: &[u8]) -> Result<String> {
+    String::from_utf8(cf_prefix.to_vec())
+        .map_err(|_| Error::Path("Column family prefix must be valid UTF-8".into()))
+}
+
+/// Returns the handle of the column family with the given name and prefix.
+fn cf_handle<'a>(db: &'a DB, cf_prefix: &str, name: &str) -> &'a ColumnFamily {
+    let handle = if cf_prefix.is_empty() {
+        db.cf_handle(name)
+    } else {
+        db.cf_handle(&format!("{cf_prefix}{name}"))
+    };
+    handle.expect("Missing column family")
+}
+
+/// Returns `true` if the store has a pointer to a root node, meaning it
+/// contains data.
+fn has_root_key(db: &DB, cf_prefix: &str) -> Result<bool> {
+    let internal_cf = cf_handle(db, cf_prefix, INTERNAL_CF_NAME);
+    Ok(db.get_pinned_cf(internal_cf, ROOT_KEY_KEY)?.is_some())
+}
+
+/// Reads the hash algorithm persisted by `Merk::open_with_hash_algorithm`, if
+/// any.
+fn read_hash_algorithm(db: &DB, cf_prefix: &str) -> Result<Option<HashAlgorithm>> {
+    let internal_cf = cf_handle(db, cf_prefix, INTERNAL_CF_NAME);
+    db.get_pinned_cf(internal_cf, HASH_ALGORITHM_KEY)?
+        .map(|bytes| match bytes.as_ref() {
+            [byte] => HashAlgorithm::from_byte(*byte)
+                .ok_or_else(|| Error::Path(format!("Unsupported hash algorithm: {byte}"))),
+            _ => Err(Error::Path("Invalid hash algorithm".into())),
+        })
+        .transpose()
+}
+
+fn load_root(db: &DB, cf_prefix: &str, hash_algorithm: HashAlgorithm) -> Result<Option<Tree>> {
+    let internal_cf = cf_handle(db, cf_prefix, INTERNAL_CF_NAME);
+    let source = MerkSource {
+        db,
+        cf: cf_handle(db, cf_prefix, TREE_CF_NAME),
+        hash_algorithm,
+    };
+    db.get_pinned_cf(internal_cf, ROOT_KEY_KEY)?
+        .map(|key| source.fetch_by_key_expect(key.to_vec().as_slice()))
+        .transpose()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        default_commit_capacity, LevelPrune, Merk, MerkCommitter, MerkSource, PruneStrategy, Query,
+        RefWalker, Tree, TreeStats, WriteConfig, NULL_HASH,
+    };
+    use crate::test_utils::*;
+    use crate::tree::{Fetch, Link};
+    use crate::Op;
+    use crate::{Error, Result};
+    use std::ops::Range;
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    // TODO: Close and then reopen test
+
+    fn assert_invariants(merk: &TempMerk) {
+        merk.use_tree(|maybe_tree| {
+            let tree = maybe_tree.expect("expected tree");
+            assert_tree_invariants(tree);
+        })
+    }
+
+    #[test]
+    fn simple_insert_apply() {
+        let batch_size = 20;
+
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+
+        let batch = make_batch_seq(0..batch_size);
+        merk.apply(&batch, &[]).expect("apply failed");
+
+        assert_invariants(&merk);
+        assert_eq!(
+            merk.root_hash(),
+            [
+                243, 89, 89, 252, 166, 74, 69, 19, 51, 147, 69, 190, 183, 100, 98, 216, 70, 166,
+                132, 36, 17, 211, 66, 98, 104, 210, 212, 129, 70, 110, 76, 101
+            ]
+        );
+    }
+
+    #[test]
+    fn insert_uncached() {
+        let batch_size = 20;
+
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+
+        let batch = make_batch_seq(0..batch_size);
+        merk.apply(&batch, &[]).expect("apply failed");
+        assert_invariants(&merk);
+
+        let batch = make_batch_seq(batch_size..(batch_size * 2));
+        merk.apply(&batch, &[]).expect("apply failed");
+        assert_invariants(&merk);
+    }
+
+    #[test]
+    fn insert_rand() {
+        let tree_size = 40;
+        let batch_size = 4;
+
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+
+        for i in 0..(tree_size / batch_size) {
+            println!("i:{i}");
+            let batch = make_batch_rand(batch_size, i);
+            merk.apply(&batch, &[]).expect("apply failed");
+        }
+    }
+
+    #[test]
+    fn actual_deletes() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+
+        let batch = make_batch_rand(10, 1);
+        merk.apply(&batch, &[]).expect("apply failed");
+
+        let key = batch.first().unwrap().0.clone();
+        merk.apply(&[(key.clone(), Op::Delete)], &[]).unwrap();
+
+        let value = merk.db.get(key.as_slice()).unwrap();
+        assert!(value.is_none());
+    }
+
+    #[test]
+    fn aux_data() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+        merk.apply(&[], &[(vec![1, 2, 3], Op::Put(vec![4, 5, 6]))])
+            .expect("apply failed");
+        let val = merk.get_aux(&[1, 2, 3]).unwrap();
+        assert_eq!(val, Some(vec![4, 5, 6]));
+    }
+
+    #[test]
+    fn simulated_crash() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = CrashMerk::open(path).expect("failed to open merk");
+
+        merk.apply(
+            &[(vec![0], Op::Put(vec![1]))],
+            &[(vec![2], Op::Put(vec![3]))],
+        )
+        .expect("apply failed");
+
+        // make enough changes so that main column family gets auto-flushed
+        for i in 0..250 {
+            merk.apply(&make_batch_seq(i * 2_000..(i + 1) * 2_000), &[])
+                .expect("apply failed");
+        }
+
+        unsafe {
+            merk.crash().unwrap();
+        }
+
+        assert_eq!(merk.get_aux(&[2]).unwrap(), Some(vec![3]));
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn prove_limited_query() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..1000), &[])
+            .expect("apply failed");
+
+        let mut query = Query::new();
+        query.insert_range(seq_key(100)..seq_key(900));
+        query.set_limit(10);
+        let proof = merk.prove_query(query).expect("prove failed");
+
+        let map = crate::verify(proof.as_slice(), merk.root_hash()).expect("verify failed");
+        let entries = map
+            .range(seq_key(100).as_slice()..seq_key(900).as_slice())
+            .take(10)
+            .collect::<crate::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(entries.len(), 10);
+        assert_eq!(entries[9].0, seq_key(109).as_slice());
+        assert!(map.get(seq_key(110).as_slice()).is_err());
+
+        let mut query = Query::new();
+        query.insert_range(seq_key(100)..seq_key(900));
+        query.set_limit(10);
+        let values =
+            crate::verify_query(proof.as_slice(), &query, merk.root_hash()).expect("verify failed");
+        assert_eq!(values.len(), 10);
+        assert_eq!(
+            values,
+            (100..110)
+                .map(|n| (seq_key(n), put_entry_value()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn apply_returning_changes() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+
+        let mut batch = make_batch_seq(100..110);
+        batch.push((seq_key(5), Op::Put(vec![1, 2, 3])));
+        batch.extend(make_del_batch_seq(10..15));
+        batch.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let changes = merk
+            .apply_returning_changes(&batch, &[])
+            .expect("apply failed");
+        assert_invariants(&merk);
+
+        let mut sorted = changes.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted, changes);
+
+        for (key, op) in batch.iter() {
+            let deleted = matches!(op, Op::Delete);
+            assert!(changes.contains(&(key.clone(), deleted)));
+        }
+
+        // other keys were only rewritten because their children changed
+        for (key, deleted) in changes.iter() {
+            if !batch.iter().any(|(batch_key, _)| batch_key == key) {
+                assert!(!deleted);
+                assert_eq!(merk.get(key).unwrap(), Some(put_entry_value()));
+            }
+        }
+    }
+
+    #[test]
+    fn prove_into() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..1000), &[])
+            .expect("apply failed");
+
+        let range_query = || {
+            let mut query = Query::new();
+            query.insert_range(seq_key(100)..seq_key(200));
+            query
+        };
+        let key_query = || {
+            let mut query = Query::new();
+            query.insert_key(seq_key(5));
+            query
+        };
+
+        let mut buf = vec![];
+        merk.prove_into(range_query(), &mut buf)
+            .expect("prove failed");
+        assert_eq!(buf, merk.prove_query(range_query()).unwrap());
+        let map = crate::verify(&buf, merk.root_hash()).expect("verify failed");
+        let entries = map
+            .range(seq_key(100).as_slice()..seq_key(200).as_slice())
+            .collect::<crate::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(entries.len(), 100);
+
+        merk.prove_into(key_query(), &mut buf)
+            .expect("prove failed");
+        assert_eq!(buf, merk.prove_query(key_query()).unwrap());
+        let map = crate::verify(&buf, merk.root_hash()).expect("verify failed");
+        assert_eq!(
+            map.get(seq_key(5).as_slice()).unwrap(),
+            Some(put_entry_value().as_slice())
+        );
+    }
+
+    #[test]
+    fn prove_verify_rand() {
+        use rand::prelude::*;
+
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        let mut rng: SmallRng = SeedableRng::seed_from_u64(0);
+
+        for seed in 0..5 {
+            let batch = make_batch_rand(200, seed);
+            merk.apply(&batch, &[]).expect("apply failed");
+
+            for _ in 0..10 {
+                let mut query = Query::new();
+                for _ in 0..rng.gen_range(1..4) {
+                    let a = seq_key(rng.gen());
+                    let b = seq_key(rng.gen());
+                    let (start, end) = if a < b { (a, b) } else { (b, a) };
+                    match rng.gen_range(0..7) {
+                        0 => query.insert_key(batch[rng.gen_range(0..batch.len())].0.clone()),
+                        1 => query.insert_key(start),
+                        2 => query.insert_range(start..end),
+                        3 => query.insert_range_inclusive(start..=end),
+                        4 => query.insert_range_from(start..),
+                        5 => query.insert_range_to(..end),
+                        _ => query.insert_range_to_inclusive(..=end),
+                    }
+                }
+                assert_prove_verify(&merk, query);
+            }
+        }
+    }
+
+    #[test]
+    fn prove_sectioned() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+
+        let queries = || {
+            let mut first = Query::new();
+            first.insert_range(seq_key(10)..seq_key(15));
+            let mut second = Query::new();
+            second.insert_range(seq_key(80)..seq_key(83));
+            vec![first, second]
+        };
+        let proof = merk.prove_sectioned(&queries()).expect("prove failed");
+
+        let maps = crate::proofs::verify_sectioned(&proof, &queries(), merk.root_hash())
+            .expect("verify failed");
+        assert_eq!(maps.len(), 2);
+
+        let entries = |map: &crate::proofs::query::Map, start, end| {
+            map.range(seq_key(start).as_slice()..seq_key(end).as_slice())
+                .map(|entry| entry.map(|(key, _)| key.to_vec()))
+                .collect::<Result<Vec<_>>>()
+        };
+        assert_eq!(
+            entries(&maps[0], 10, 15).unwrap(),
+            (10..15).map(seq_key).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            entries(&maps[1], 80, 83).unwrap(),
+            (80..83).map(seq_key).collect::<Vec<_>>()
+        );
+        assert!(entries(&maps[0], 80, 83).is_err());
+        assert!(entries(&maps[1], 10, 15).is_err());
+
+        // each section is a standalone proof
+        let length = u32::from_be_bytes([proof[0], proof[1], proof[2], proof[3]]) as usize;
+        crate::verify(&proof[4..4 + length], merk.root_hash()).expect("verify failed");
+
+        // sections are checked against the query at the same index
+        let mut swapped = queries();
+        swapped.reverse();
+        assert!(crate::proofs::verify_sectioned(&proof, &swapped, merk.root_hash()).is_err());
+        assert!(
+            crate::proofs::verify_sectioned(&proof, &queries()[..1], merk.root_hash()).is_err()
+        );
+        assert!(crate::proofs::verify_sectioned(
+            &proof[..proof.len() - 1],
+            &queries(),
+            merk.root_hash()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn prove_size_estimate() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..1000), &[])
+            .expect("apply failed");
+
+        let mut queries = vec![];
+
+        let mut query = Query::new();
+        query.insert_key(seq_key(5));
+        query.insert_key(vec![0, 0, 0, 0, 0, 0, 0, 9, 1]);
+        queries.push(query);
+
+        let mut query = Query::new();
+        query.insert_range(seq_key(100)..seq_key(900));
+        queries.push(query);
+
+        let mut query = Query::new();
+        query.insert_range(seq_key(100)..seq_key(900));
+        query.set_limit(10);
+        query.set_offset(20);
+        queries.push(query);
+
+        let mut query = Query::new();
+        query.insert_range_full();
+        queries.push(query);
+
+        for query in queries {
+            let estimate = merk.prove_size_estimate(&query).unwrap();
+            let proof = merk.prove_query(query).unwrap();
+            assert!(estimate.abs_diff(proof.len()) <= proof.len() / 100);
+        }
+    }
+
+    #[test]
+    fn prove_offset_query() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..20), &[])
+            .expect("apply failed");
+
+        let mut query = Query::new();
+        query.insert_range(seq_key(0)..seq_key(20));
+        query.set_offset(5);
+        query.set_limit(5);
+        let proof = merk.prove_query(query).expect("prove failed");
+
+        let map = crate::verify(proof.as_slice(), merk.root_hash()).expect("verify failed");
+        let entries = map
+            .range(seq_key(5).as_slice()..seq_key(10).as_slice())
+            .take(5)
+            .collect::<crate::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            entries,
+            (5..10)
+                .map(|n| (seq_key(n), put_entry_value()))
+                .collect::<Vec<_>>()
+                .iter()
+                .map(|(k, v)| (k.as_slice(), v.as_slice()))
+                .collect::<Vec<_>>()
+        );
+        for n in (0..5).chain(10..20) {
+            assert!(map.get(seq_key(n).as_slice()).is_err());
+        }
+    }
+
+    #[test]
+    fn len_proof() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+
+        let proof = merk.len_proof().expect("len_proof failed");
+        assert_eq!(
+            crate::proofs::verify_len(proof.as_slice(), merk.root_hash()).unwrap(),
+            0
+        );
+
+        merk.apply(&make_batch_seq(0..1000), &[])
+            .expect("apply failed");
+        let proof = merk.len_proof().expect("len_proof failed");
+        assert_eq!(
+            crate::proofs::verify_len(proof.as_slice(), merk.root_hash()).unwrap(),
+            1000
+        );
+    }
+
+    #[test]
+    fn len() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+        assert_eq!(merk.len().unwrap(), 0);
+        assert!(merk.is_empty());
+
+        merk.apply(
+            &make_batch_seq(0..500),
+            &[(vec![1, 2, 3], Op::Put(vec![4, 5, 6]))],
+        )
+        .expect("apply failed");
+        assert_eq!(merk.len().unwrap(), 500);
+        assert!(!merk.is_empty());
+    }
+
+    #[test]
+    fn iter() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+        assert_eq!(merk.iter().count(), 0);
+
+        merk.apply(
+            &make_batch_seq(0..100),
+            &[(vec![1, 2, 3], Op::Put(vec![4, 5, 6]))],
+        )
+        .expect("apply failed");
+
+        let entries = merk
+            .iter()
+            .collect::<crate::Result<Vec<_>>>()
+            .expect("iteration failed");
+        assert_eq!(
+            entries,
+            (0..100)
+                .map(|n| (seq_key(n), put_entry_value()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn open_with_cache() {
+        let path = TempMerk::create_path();
+
+        {
+            let mut merk = Merk::open_with_cache(&path, 16 * 1024 * 1024).unwrap();
+            merk.apply(
+                &make_batch_seq(0..100),
+                &[(vec![1, 2, 3], Op::Put(vec![4, 5, 6]))],
+            )
+            .unwrap();
+            assert_eq!(merk.get(&seq_key(5)).unwrap(), Some(put_entry_value()));
+            assert_eq!(merk.get_aux(&[1, 2, 3]).unwrap(), Some(vec![4, 5, 6]));
+        }
+
+        let merk = TempMerk::open(&path).unwrap();
+        assert_eq!(merk.get(&seq_key(99)).unwrap(), Some(put_entry_value()));
+    }
+
+    #[test]
+    fn compact() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..1_000), &[])
+            .expect("apply failed");
+        merk.delete_range(seq_key(100)..seq_key(900), &[])
+            .expect("delete_range failed");
+        let hash = merk.root_hash();
+
+        merk.compact().unwrap();
+        merk.compact_range(&seq_key(0), &seq_key(100)).unwrap();
+
+        assert_eq!(merk.root_hash(), hash);
+        assert_eq!(merk.get(&seq_key(50)).unwrap(), Some(put_entry_value()));
+        assert_eq!(merk.get(&seq_key(500)).unwrap(), None);
+        assert_eq!(merk.iter().count(), 200);
+        assert_invariants(&merk);
+    }
+
+    #[test]
+    fn size_on_disk() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.flush().unwrap();
+        let initial_size = merk.size_on_disk().unwrap();
+
+        merk.apply(&make_batch_seq(0..10_000), &[])
+            .expect("apply failed");
+        merk.flush().unwrap();
+
+        let size = merk.size_on_disk().unwrap();
+        assert!(size > 0);
+        assert!(size > initial_size);
+    }
+
+    #[test]
+    fn open_as_secondary() {
+        let path = TempMerk::create_path();
+        let secondary_path = TempMerk::create_path();
+
+        let mut primary = TempMerk::open(&path).unwrap();
+        primary.apply(&make_batch_seq(0..10), &[]).unwrap();
+        primary.flush().unwrap();
+
+        let secondary = Merk::open_as_secondary(&path, &secondary_path).unwrap();
+        assert_eq!(secondary.root_hash(), primary.root_hash());
+        assert_eq!(secondary.get(&seq_key(10)).unwrap(), None);
+
+        primary.apply(&make_batch_seq(10..20), &[]).unwrap();
+        primary.flush().unwrap();
+        assert_ne!(secondary.root_hash(), primary.root_hash());
+
+        secondary.catch_up_with_primary().unwrap();
+        assert_eq!(secondary.root_hash(), primary.root_hash());
+        assert_eq!(
+            secondary.get(&seq_key(15)).unwrap(),
+            Some(put_entry_value())
+        );
+
+        drop(secondary);
+        std::fs::remove_dir_all(&secondary_path).ok();
+    }
+
+    #[test]
+    fn get_corrupted_node() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.set_prune_levels(0);
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+
+        let key = seq_key(10);
+        assert!(merk.use_tree(|tree| tree.unwrap().key() != key.as_slice()));
+        merk.db().put(&key, [0xff; 8]).unwrap();
+
+        match merk.get(&key) {
+            Err(Error::Corruption(corrupted_key, _)) => assert_eq!(corrupted_key, key),
+            res => panic!("expected corruption error, got {:?}", res),
+        }
+        assert_eq!(merk.get(&seq_key(20)).unwrap(), Some(put_entry_value()));
+    }
+
+    #[test]
+    fn apply_returning_hash() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+
+        let hash = merk
+            .apply_returning_hash(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+        assert_ne!(hash, NULL_HASH);
+        assert_eq!(hash, merk.root_hash());
+
+        let hash = merk
+            .apply_returning_hash(&make_del_batch_seq(0..100), &[])
+            .expect("apply failed");
+        assert_eq!(hash, NULL_HASH);
+        assert_eq!(hash, merk.root_hash());
+    }
+
+    #[test]
+    fn get_with_proof() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+        merk.apply(&[(seq_key(50), Op::Put(vec![1, 2, 3]))], &[])
+            .expect("apply failed");
+
+        for key in [seq_key(0), seq_key(50), seq_key(99), seq_key(100)] {
+            let (value, proof) = merk.get_with_proof(&key).unwrap();
+            assert_eq!(value, merk.get(&key).unwrap());
+
+            let map = crate::verify(&proof, merk.root_hash()).unwrap();
+            assert_eq!(map.get(&key).unwrap(), value.as_deref());
+        }
+        assert_eq!(merk.get_with_proof(&seq_key(100)).unwrap().0, None);
+    }
+
+    #[test]
+    fn prove_absence() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+
+        let mut key = seq_key(50);
+        key.push(0);
+        let proof = merk.prove_absence(&key).unwrap();
+
+        let map = crate::verify(&proof, merk.root_hash()).unwrap();
+        assert_eq!(map.get(&key).unwrap(), None);
+
+        // only the neighboring keys are included, without their values
+        let keyed_nodes: Vec<_> = crate::proofs::Decoder::new(&proof)
+            .filter_map(|op| match op.unwrap() {
+                crate::proofs::Op::Push(crate::proofs::Node::KV(..)) => panic!("unexpected KV"),
+                crate::proofs::Op::Push(crate::proofs::Node::KVDigest(key, _)) => Some(key),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(keyed_nodes, vec![seq_key(50), seq_key(51)]);
+
+        assert!(merk.prove_absence(&seq_key(50)).is_err());
+    }
+
+    #[test]
+    fn root_key() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        assert_eq!(merk.root_key(), None);
+
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+
+        let internal_cf = merk.db().cf_handle(super::INTERNAL_CF_NAME).unwrap();
+        let stored_root_key = merk.db().get_cf(internal_cf, super::ROOT_KEY_KEY).unwrap();
+        assert!(stored_root_key.is_some());
+        assert_eq!(merk.root_key(), stored_root_key);
+
+        merk.apply(&make_del_batch_seq(0..100), &[])
+            .expect("apply failed");
+        assert_eq!(merk.root_key(), None);
+    }
+
+    #[test]
+    fn append_existing() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.set_prune_levels(0);
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+
+        merk.apply(
+            &[(seq_key(10), Op::Append(vec![1, 2, 3]))],
+            &[(vec![0], Op::Append(vec![4]))],
+        )
+        .expect("apply failed");
+        merk.apply(&[], &[(vec![0], Op::Append(vec![5]))])
+            .expect("apply failed");
+
+        let mut expected_value = put_entry_value();
+        expected_value.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(
+            merk.get(&seq_key(10)).unwrap(),
+            Some(expected_value.clone())
+        );
+        assert_eq!(merk.get_aux(&[0]).unwrap(), Some(vec![4, 5]));
+
+        let mut expected = TempMerk::new().expect("failed to open merk");
+        expected
+            .apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+        expected
+            .apply(&[(seq_key(10), Op::Put(expected_value))], &[])
+            .expect("apply failed");
+        assert_eq!(merk.root_hash(), expected.root_hash());
+    }
+
+    #[test]
+    fn append_missing() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+        merk.apply(
+            &[
+                (seq_key(100), Op::Append(vec![1, 2, 3])),
+                (seq_key(101), Op::Append(vec![4])),
+            ],
+            &[],
+        )
+        .expect("apply failed");
+        assert_eq!(merk.get(&seq_key(100)).unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(merk.get(&seq_key(101)).unwrap(), Some(vec![4]));
+
+        let mut expected = TempMerk::new().expect("failed to open merk");
+        expected
+            .apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+        expected
+            .apply(
+                &[
+                    (seq_key(100), Op::Put(vec![1, 2, 3])),
+                    (seq_key(101), Op::Put(vec![4])),
+                ],
+                &[],
+            )
+            .expect("apply failed");
+        assert_eq!(merk.root_hash(), expected.root_hash());
+    }
+
+    #[test]
+    fn apply_debug_checked() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        unsafe { merk.apply_debug_checked(&make_batch_seq(0..100), &[]) }.expect("apply failed");
+        assert_eq!(merk.iter().count(), 100);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(
+        expected = "Invalid batch: Batch Key Error: Keys in batch must be sorted and unique, duplicate at index 1"
+    )]
+    fn apply_debug_checked_duplicate() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        let batch = [(vec![1], Op::Put(vec![1])), (vec![1], Op::Put(vec![2]))];
+        unsafe { merk.apply_debug_checked(&batch, &[]) }.unwrap();
+    }
+
+    /// A `Fetch` source which counts the nodes fetched from the store.
+    #[derive(Clone)]
+    struct CountSource<'a>(MerkSource<'a>, &'a AtomicUsize);
+
+    impl<'a> Fetch for CountSource<'a> {
+        fn fetch_by_key(&self, key: &[u8]) -> Result<Option<Tree>> {
+            self.1.fetch_add(1, AtomicOrdering::Relaxed);
+            self.0.fetch_by_key(key)
+        }
+    }
+
+    #[test]
+    fn load_range() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.set_prune_levels(0);
+        merk.apply(&make_batch_seq(0..1000), &[])
+            .expect("apply failed");
+
+        let fetches = AtomicUsize::new(0);
+        let count_gets = |merk: &Merk, keys: Range<u64>| {
+            fetches.store(0, AtomicOrdering::Relaxed);
+            merk.use_tree(|maybe_tree| {
+                let tree = maybe_tree.expect("expected tree");
+                for n in keys {
+                    let source = CountSource(merk.source(), &fetches);
+                    let value = super::get(tree, source, &seq_key(n)).expect("get failed");
+                    assert!(value.is_some());
+                }
+            });
+            fetches.load(AtomicOrdering::Relaxed)
+        };
+        assert!(count_gets(&merk, 100..200) > 0);
+
+        merk.load_range(seq_key(100)..seq_key(200))
+            .expect("load_range failed");
+
+        // keys in the range and the nodes bounding it are in memory
+        assert_eq!(count_gets(&merk, 99..201), 0);
+        assert!(count_gets(&merk, 900..901) > 0);
+
+        assert_eq!(merk.get(&seq_key(150)).unwrap(), Some(put_entry_value()));
+    }
+
+    #[test]
+    fn print_tree() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        assert_eq!(merk.print_tree().unwrap(), "");
+
+        let batch: Vec<_> = (1..=7).map(|n| (vec![n], Op::Put(vec![]))).collect();
+        merk.apply(&batch, &[]).expect("apply failed");
+        merk.set_prune_levels(0);
+        merk.apply(&[], &[]).expect("apply failed");
+
+        let output = merk.print_tree().unwrap();
+        assert_eq!(
+            output,
+            [
+                "     ┌-[1] (height 1, balance 0)",
+                " ┌-[2] (height 2, balance 0)",
+                " │   └-[3] (height 1, balance 0)",
+                "[4] (height 3, balance 0)",
+                " │   ┌-[5] (height 1, balance 0)",
+                " └-[6] (height 2, balance 0)",
+                "     └-[7] (height 1, balance 0)",
+                "",
+            ]
+            .join("\n")
+        );
+        assert_eq!(output.lines().count(), 7);
+        assert!(output.contains(&format!("{:?}", merk.root_key().unwrap())));
+    }
+
+    #[test]
+    fn apply_if_changed() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        let batch = make_batch_seq(0..100);
+        assert!(merk.apply_if_changed(&batch, &[]).expect("apply failed"));
+        let hash = merk.root_hash();
+
+        let observed = Arc::new(Mutex::new(0));
+        let counter = observed.clone();
+        merk.set_apply_observer(Box::new(move |_, _| *counter.lock().unwrap() += 1));
+
+        assert!(!merk.apply_if_changed(&batch, &[]).expect("apply failed"));
+        assert_eq!(merk.root_hash(), hash);
+        let noops = [
+            (seq_key(5), Op::Put(put_entry_value())),
+            (seq_key(200), Op::Delete),
+        ];
+        assert!(!merk.apply_if_changed(&noops, &[]).expect("apply failed"));
+        assert_eq!(*observed.lock().unwrap(), 0);
+
+        // only the changed entries are applied
+        let mut batch = make_batch_seq(0..10);
+        batch[3].1 = Op::Put(vec![1, 2, 3]);
+        assert!(merk.apply_if_changed(&batch, &[]).expect("apply failed"));
+        assert_eq!(*observed.lock().unwrap(), 1);
+        assert_eq!(merk.get(&seq_key(3)).unwrap(), Some(vec![1, 2, 3]));
+        assert_ne!(merk.root_hash(), hash);
+    }
+
+    #[test]
+    fn pending_changes() {
+        use crate::tree::Walker;
+
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+        assert!(merk.pending_changes().is_empty());
+
+        // apply a batch to the in-memory tree without committing it
+        let batch = [(seq_key(10), Op::Put(vec![1, 2, 3]))];
+        {
+            let mut tree = merk.tree.write().unwrap();
+            let walker = Walker::new(tree.take().unwrap(), merk.source());
+            let (maybe_tree, _) =
+                Walker::apply_to(Some(walker), &batch, merk.source()).expect("apply_to failed");
+            *tree = maybe_tree;
+        }
+
+        // every node on the path from the root to the changed key is pending
+        let mut expected = merk.walk(|maybe_walker| {
+            let walker = maybe_walker.expect("expected tree");
+            let mut node = walker.tree();
+            let mut path = vec![];
+            while node.key() != seq_key(10).as_slice() {
+                node = node.child(seq_key(10).as_slice() < node.key()).unwrap();
+                path.push(node.key().to_vec());
+            }
+            path
+        });
+        expected.sort();
+        assert!(!expected.is_empty());
+        assert_eq!(merk.pending_changes(), expected);
+
+        merk.apply(&[], &[]).expect("apply failed");
+        assert!(merk.pending_changes().is_empty());
+        assert_eq!(merk.get(&seq_key(10)).unwrap(), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn prefetch() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.set_prune_levels(0);
+        merk.apply(&make_batch_seq(0..1000), &[])
+            .expect("apply failed");
+
+        let mut query = Query::new();
+        query.insert_range(seq_key(100)..seq_key(120));
+        query.insert_key(seq_key(500));
+        query.insert_key(b"missing".to_vec());
+
+        let fetches = AtomicUsize::new(0);
+        let count_prove = |merk: &Merk, query: &Query| {
+            fetches.store(0, AtomicOrdering::Relaxed);
+            merk.use_tree_mut(|maybe_tree| {
+                let source = CountSource(merk.source(), &fetches);
+                super::create_proof(maybe_tree, source, query.iter().cloned(), None, None, true)
+                    .expect("prove failed");
+            });
+            fetches.load(AtomicOrdering::Relaxed)
+        };
+        assert!(count_prove(&merk, &query) > 0);
+
+        // committing prunes the nodes loaded by the proof
+        merk.apply(&[], &[]).expect("apply failed");
+        merk.prefetch(&query).expect("prefetch failed");
+        assert_eq!(count_prove(&merk, &query), 0);
+
+        let mut other = Query::new();
+        other.insert_key(seq_key(900));
+        assert!(count_prove(&merk, &other) > 0);
+
+        let proof = merk.prove_query(query).expect("prove failed");
+        let map = crate::verify(&proof, merk.root_hash()).expect("verify failed");
+        assert_eq!(
+            map.get(&seq_key(500)).unwrap(),
+            Some(&put_entry_value()[..])
+        );
+    }
+
+    #[test]
+    fn apply_observer() {
+        type Changes = Arc<Mutex<Vec<(Vec<u8>, Option<Vec<u8>>)>>>;
+
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..10), &[])
+            .expect("apply failed");
+
+        let changes: Changes = Default::default();
+        let observed = changes.clone();
+        merk.set_apply_observer(Box::new(move |key, value| {
+            observed
+                .lock()
+                .unwrap()
+                .push((key.to_vec(), value.map(|value| value.to_vec())));
+        }));
+
+        // enough changes to cause rebalancing
+        let mut batch = make_del_batch_seq(0..5);
+        batch.push((seq_key(5), Op::Put(vec![1])));
+        batch.push((seq_key(6), Op::Append(vec![2])));
+        batch.extend(make_batch_seq(10..20));
+        merk.apply(&batch, &[]).expect("apply failed");
+
+        let mut expected: Vec<_> = (0..5).map(|n| (seq_key(n), None)).collect();
+        expected.push((seq_key(5), Some(vec![1])));
+        let mut appended = put_entry_value();
+        appended.push(2);
+        expected.push((seq_key(6), Some(appended)));
+        expected.extend((10..20).map(|n| (seq_key(n), Some(put_entry_value()))));
+        assert_eq!(*changes.lock().unwrap(), expected);
+
+        // deleting a missing key does not change anything
+        changes.lock().unwrap().clear();
+        merk.apply(&[(seq_key(0), Op::Delete)], &[])
+            .expect("apply failed");
+        merk.apply_parallel(&make_batch_seq(20..22), &[])
+            .expect("apply failed");
+        let expected: Vec<_> = (20..22)
+            .map(|n| (seq_key(n), Some(put_entry_value())))
+            .collect();
+        assert_eq!(*changes.lock().unwrap(), expected);
+
+        merk.clear_apply_observer();
+        merk.apply(&make_batch_seq(22..23), &[])
+            .expect("apply failed");
+        assert_eq!(changes.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn prove_range_empty() {
+        use crate::proofs::query::verify_range_empty;
+
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+        assert!(merk.prove_range_empty(seq_key(20)..seq_key(40)).is_err());
+
+        merk.apply(&make_del_batch_seq(20..40), &[])
+            .expect("apply failed");
+        let hash = merk.root_hash();
+
+        let range = seq_key(20)..seq_key(40);
+        let proof = merk.prove_range_empty(range.clone()).expect("prove failed");
+        verify_range_empty(&proof, range.clone(), hash).expect("verify failed");
+        assert!(verify_range_empty(&proof, range, [0; 32]).is_err());
+
+        // the proof does not cover a larger range
+        assert!(verify_range_empty(&proof, seq_key(20)..seq_key(41), hash).is_err());
+        assert!(verify_range_empty(&proof, seq_key(19)..seq_key(40), hash).is_err());
+
+        // ranges past the edges of the tree
+        let range = seq_key(100)..seq_key(200);
+        let proof = merk.prove_range_empty(range.clone()).expect("prove failed");
+        verify_range_empty(&proof, range, hash).expect("verify failed");
+
+        // a proof of a smaller range can not show a larger range is empty
+        let proof = merk
+            .prove_range_empty(seq_key(25)..seq_key(30))
+            .expect("prove failed");
+        assert!(verify_range_empty(&proof, seq_key(18)..seq_key(30), hash).is_err());
+    }
+
+    #[test]
+    fn prove_successor() {
+        use crate::proofs::query::{verify_predecessor, verify_successor};
+
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        let batch: Vec<_> = (0..100)
+            .map(|n| (seq_key(n * 2), Op::Put(vec![n as u8])))
+            .collect();
+        merk.apply(&batch, &[]).expect("apply failed");
+        let hash = merk.root_hash();
+
+        // present and absent keys with a successor
+        for (key, expected) in [(10, 12), (11, 12), (197, 198)] {
+            let proof = merk.prove_successor(&seq_key(key)).expect("prove failed");
+            let successor = verify_successor(&proof, &seq_key(key), hash).expect("verify failed");
+            assert_eq!(
+                successor,
+                Some((seq_key(expected), vec![(expected / 2) as u8]))
+            );
+        }
+
+        // keys without a successor
+        for key in [198, 199] {
+            let proof = merk.prove_successor(&seq_key(key)).expect("prove failed");
+            let successor = verify_successor(&proof, &seq_key(key), hash).expect("verify failed");
+            assert_eq!(successor, None);
+        }
+
+        // the proof can not be used for a different key
+        let proof = merk.prove_successor(&seq_key(10)).unwrap();
+        assert!(verify_successor(&proof, &seq_key(12), hash).is_err());
+        assert!(verify_successor(&proof, &seq_key(10), [0; 32]).is_err());
+
+        for (key, expected) in [(10, Some(8)), (11, Some(10)), (1, Some(0)), (0, None)] {
+            let proof = merk.prove_predecessor(&seq_key(key)).expect("prove failed");
+            let predecessor =
+                verify_predecessor(&proof, &seq_key(key), hash).expect("verify failed");
+            let expected = expected.map(|n: u64| (seq_key(n), vec![(n / 2) as u8]));
+            assert_eq!(predecessor, expected);
+        }
+    }
+
+    #[test]
+    fn prove_with_absence() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+
+        let mut query = Query::new();
+        query.insert_range_from(seq_key(90)..);
+        let (proof, (left_absence, right_absence)) =
+            merk.prove_with_absence(query).expect("prove failed");
+        assert!(!left_absence);
+        assert!(right_absence);
+        let map = crate::verify(&proof, merk.root_hash()).expect("verify failed");
+        assert_eq!(map.range(seq_key(90).as_slice()..).count(), 10);
+
+        // a range entirely beyond the highest key
+        let mut query = Query::new();
+        query.insert_range(seq_key(100)..seq_key(200));
+        let (proof, absence) = merk.prove_with_absence(query).expect("prove failed");
+        assert_eq!(absence, (false, true));
+        let map = crate::verify(&proof, merk.root_hash()).expect("verify failed");
+        assert_eq!(map.range(seq_key(100).as_slice()..).count(), 0);
+
+        let mut query = Query::new();
+        query.insert_range(seq_key(10)..seq_key(20));
+        let (_, absence) = merk.prove_with_absence(query).expect("prove failed");
+        assert_eq!(absence, (false, false));
+
+        let mut query = Query::new();
+        query.insert_range_to(..seq_key(5));
+        let (_, absence) = merk.prove_with_absence(query).expect("prove failed");
+        assert_eq!(absence, (true, false));
+    }
+
+    #[test]
+    fn max_value_size() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.set_max_value_size(60);
+
+        merk.apply(&make_batch_seq(0..10), &[])
+            .expect("apply failed");
+        let hash = merk.root_hash();
+
+        let batch = [
+            (seq_key(10), Op::Put(vec![1; 60])),
+            (seq_key(11), Op::Put(vec![2; 61])),
+        ];
+        match merk.apply(&batch, &[]) {
+            Err(Error::ValueTooLarge { key, size, max }) => {
+                assert_eq!((key, size, max), (seq_key(11), 61, 60));
+            }
+            _ => panic!("expected batch to be rejected"),
+        }
+        assert_eq!(merk.root_hash(), hash);
+        assert!(merk.get(&seq_key(10)).unwrap().is_none());
+
+        merk.apply(&batch[..1], &[]).expect("apply failed");
+        assert_eq!(merk.get(&seq_key(10)).unwrap(), Some(vec![1; 60]));
+
+        // appends are checked against the size of the resulting value
+        merk.apply(&[(seq_key(20), Op::Append(vec![3; 40]))], &[])
+            .expect("apply failed");
+        merk.apply(&[(seq_key(20), Op::Append(vec![4; 20]))], &[])
+            .expect("apply failed");
+        let hash = merk.root_hash();
+        let batch = [
+            (seq_key(20), Op::Append(vec![5])),
+            (seq_key(21), Op::Append(vec![6; 10])),
+        ];
+        match merk.apply(&batch, &[]) {
+            Err(Error::ValueTooLarge { key, size, max }) => {
+                assert_eq!((key, size, max), (seq_key(20), 61, 60));
+            }
+            _ => panic!("expected batch to be rejected"),
+        }
+        assert_eq!(merk.root_hash(), hash);
+        assert_eq!(merk.get(&seq_key(20)).unwrap().map(|v| v.len()), Some(60));
+    }
+
+    #[test]
+    fn key_validator() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.set_key_validator(Box::new(|key| {
+            if key.len() != 32 {
+                return Err(Error::Key(format!(
+                    "Expected 32-byte key, got {}",
+                    key.len()
+                )));
+            }
+            Ok(())
+        }));
+
+        merk.apply(&[(vec![1; 32], Op::Put(vec![1]))], &[])
+            .expect("apply failed");
+        let hash = merk.root_hash();
+
+        let batch = [
+            (vec![2; 32], Op::Put(vec![2])),
+            (vec![3; 31], Op::Put(vec![3])),
+        ];
+        match merk.apply(&batch, &[]) {
+            Err(Error::Key(message)) => assert!(message.contains("got 31")),
+            _ => panic!("expected batch to be rejected"),
+        }
+        assert!(merk.apply_parallel(&batch, &[]).is_err());
+        assert_eq!(merk.root_hash(), hash);
+        assert!(merk.get(&[2; 32]).unwrap().is_none());
+
+        merk.clear_key_validator();
+        merk.apply(&batch, &[]).expect("apply failed");
+        assert_eq!(merk.get(&[3; 31]).unwrap(), Some(vec![3]));
+    }
+
+    #[test]
+    fn apply_with_merges() {
+        fn increment() -> super::MergeFn {
+            Box::new(|value| {
+                let n = value.map_or(0, |value| {
+                    let mut bytes = [0; 8];
+                    bytes.copy_from_slice(value);
+                    u64::from_be_bytes(bytes)
+                });
+                Some((n + 1).to_be_bytes().to_vec())
+            })
+        }
+
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(
+            &[
+                (vec![1], Op::Put(5u64.to_be_bytes().to_vec())),
+                (vec![2], Op::Put(7u64.to_be_bytes().to_vec())),
+            ],
+            &[],
+        )
+        .expect("apply failed");
+
+        merk.apply_with_merges(&[
+            (vec![3], increment()),
+            (vec![1], increment()),
+            (vec![2], Box::new(|_| None)),
+            (vec![4], Box::new(|_| None)),
+        ])
+        .expect("apply_with_merges failed");
+
+        assert_eq!(merk.get(&[1]).unwrap(), Some(6u64.to_be_bytes().to_vec()));
+        assert_eq!(merk.get(&[2]).unwrap(), None);
+        assert_eq!(merk.get(&[3]).unwrap(), Some(1u64.to_be_bytes().to_vec()));
+        assert_eq!(merk.get(&[4]).unwrap(), None);
+
+        assert!(merk
+            .apply_with_merges(&[(vec![1], increment()), (vec![1], increment())])
+            .is_err());
+    }
+
+    #[test]
+    fn apply_key_too_long() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&[(vec![1; 255], Op::Put(vec![1]))], &[])
+            .expect("apply failed");
+
+        let res = merk.apply(&[(vec![2; 256], Op::Put(vec![2]))], &[]);
+        assert!(matches!(res, Err(Error::KeyTooLong(256, 255))));
+        assert_eq!(merk.get(&[1; 255]).unwrap(), Some(vec![1]));
+    }
+
+    #[test]
+    fn max_key_length() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.set_max_key_length(8);
+
+        merk.apply(&[(vec![1; 8], Op::Put(vec![1]))], &[])
+            .expect("apply failed");
+        let hash = merk.root_hash();
+
+        let batch = [
+            (vec![0; 4], Op::Put(vec![0])),
+            (vec![2; 9], Op::Put(vec![2])),
+        ];
+        let res = merk.apply(&batch, &[]);
+        assert!(matches!(res, Err(Error::KeyTooLong(9, 8))));
+        assert_eq!(merk.root_hash(), hash);
+        assert!(merk.get(&[0; 4]).unwrap().is_none());
+
+        merk.set_max_key_length(u8::MAX);
+        merk.apply(&batch, &[]).expect("apply failed");
+        assert_eq!(merk.get(&[2; 9]).unwrap(), Some(vec![2]));
+    }
+
+    #[test]
+    fn open_on_db() {
+        use std::sync::Arc;
+
+        let path = TempMerk::create_path();
+
+        let opts = Merk::default_db_opts();
+        let mut cfs = Merk::column_families_with_prefix(b"merk_").unwrap();
+        cfs.push(rocksdb::ColumnFamilyDescriptor::new(
+            "app",
+            Merk::default_db_opts(),
+        ));
+        let db = Arc::new(rocksdb::DB::open_cf_descriptors(&opts, &path, cfs).unwrap());
+
+        let app_cf = db.cf_handle("app").unwrap();
+        db.put([1], [10]).unwrap();
+        db.put_cf(app_cf, [2], [20]).unwrap();
+
+        assert!(Merk::open_on_db(db.clone(), b"other_").is_err());
+
+        {
+            let mut merk = Merk::open_on_db(db.clone(), b"merk_").unwrap();
+            merk.apply(&make_batch_seq(0..100), &[(vec![2], Op::Put(vec![0]))])
+                .expect("apply failed");
+            merk.apply(&make_del_batch_seq(0..50), &[(vec![2], Op::Delete)])
+                .expect("apply failed");
+
+            assert_eq!(merk.iter().count(), 50);
+            assert_eq!(merk.get(&seq_key(75)).unwrap(), Some(put_entry_value()));
+            let snapshot = merk.snapshot().unwrap();
+            assert_eq!(snapshot.get(&seq_key(75)).unwrap(), Some(put_entry_value()));
+            assert_eq!(snapshot.root_hash(), merk.root_hash());
+            drop(snapshot);
+
+            assert!(merk.destroy().is_err());
+        }
+
+        assert_eq!(db.get([1]).unwrap(), Some(vec![10]));
+        assert_eq!(db.get_cf(app_cf, [2]).unwrap(), Some(vec![20]));
+        assert_eq!(db.iterator(rocksdb::IteratorMode::Start).count(), 1);
+        assert_eq!(
+            db.iterator_cf(app_cf, rocksdb::IteratorMode::Start).count(),
+            1
+        );
+
+        let merk = Merk::open_on_db(db.clone(), b"merk_").unwrap();
+        assert_eq!(merk.get(&seq_key(75)).unwrap(), Some(put_entry_value()));
+        drop(merk);
+
+        drop(db);
+        rocksdb::DB::destroy(&opts, &path).unwrap();
+    }
+
+    #[test]
+    fn iter_rev() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        assert_eq!(merk.iter_rev().count(), 0);
+
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+
+        let forward = merk
+            .iter()
+            .collect::<crate::Result<Vec<_>>>()
+            .expect("iteration failed");
+        let mut reverse = merk
+            .iter_rev()
+            .collect::<crate::Result<Vec<_>>>()
+            .expect("iteration failed");
+        assert_eq!(forward.len(), 100);
+        reverse.reverse();
+        assert_eq!(forward, reverse);
+
+        // consuming from both ends meets in the middle without repeating
+        let mut iter = merk.iter();
+        let mut keys = vec![];
+        while let Some(entry) = iter.next() {
+            keys.push(entry.unwrap().0);
+            if let Some(entry) = iter.next_back() {
+                keys.push(entry.unwrap().0);
+            }
+        }
+        keys.sort();
+        assert_eq!(keys, (0..100).map(seq_key).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn get_many() {
+        let path = TempMerk::create_path();
+
+        {
+            let mut merk = Merk::open(&path).unwrap();
+            assert_eq!(merk.get_many(&[vec![1]]).unwrap(), vec![None]);
+            merk.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+        }
+
+        // after reopening, only the root node is in memory so most lookups
+        // fall back to the backing store
+        let mut merk = TempMerk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(10_000..10_100), &[]).unwrap();
+
+        let keys = vec![
+            seq_key(9_999),
+            vec![1, 2, 3],
+            seq_key(0),
+            seq_key(10_050),
+            seq_key(5_000),
+            seq_key(20_000),
+            seq_key(0),
+            vec![],
+        ];
+        let expected = keys
+            .iter()
+            .map(|key| merk.get(key).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(expected.iter().filter(|value| value.is_some()).count(), 5);
+        assert_eq!(merk.get_many(&keys).unwrap(), expected);
+    }
+
+    #[test]
+    fn open_existing() {
+        let path = TempMerk::create_path();
+
+        assert!(Merk::open_existing(&path).is_err());
+        assert!(!std::path::Path::new(&path).exists());
+
+        {
+            let (mut merk, created) = Merk::open_or_create(&path).unwrap();
+            assert!(created);
+            merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        }
+
+        let hash = {
+            let (merk, created) = Merk::open_or_create(&path).unwrap();
+            assert!(!created);
+            merk.root_hash()
+        };
+
+        let merk = Merk::open_existing(&path).unwrap();
+        assert_eq!(merk.root_hash(), hash);
+        drop(merk);
+        TempMerk::open(&path).unwrap();
+    }
+
+    #[test]
+    fn apply_unsorted_batch_error() {
+        let mut merk = TempMerk::new().unwrap();
+
+        let batch = vec![
+            (vec![1], Op::Put(vec![1])),
+            (vec![3], Op::Put(vec![3])),
+            (vec![2, 0xab], Op::Put(vec![2])),
+        ];
+        let err = merk.apply(&batch, &[]).unwrap_err().to_string();
+        assert!(err.contains("unsorted at index 2"), "{}", err);
+        assert!(err.contains("02ab"), "{}", err);
+
+        let batch = vec![(vec![1], Op::Put(vec![1])), (vec![1], Op::Delete)];
+        let err = merk.apply(&batch, &[]).unwrap_err().to_string();
+        assert!(err.contains("duplicate at index 1"), "{}", err);
+
+        assert_eq!(merk.root_hash(), NULL_HASH);
+    }
+
+    #[cfg(feature = "keccak")]
+    #[test]
+    fn keccak_hash_algorithm() {
+        use crate::proofs::{query::verify, verify_with_algorithm};
+        use crate::tree::HashAlgorithm;
+
+        let path = TempMerk::create_path();
+        let batch = make_batch_seq(0..100);
+
+        let default_hash = {
+            let mut merk = TempMerk::new().unwrap();
+            merk.apply(&batch, &[]).unwrap();
+            merk.root_hash()
+        };
+
+        let hash = {
+            let mut merk = Merk::open_with_hash_algorithm(&path, HashAlgorithm::Keccak256).unwrap();
+            merk.apply(&batch, &[]).unwrap();
+            merk.root_hash()
+        };
+        assert_ne!(hash, default_hash);
+
+        assert!(Merk::open_with_hash_algorithm(&path, HashAlgorithm::Sha512_256).is_err());
+
+        // the algorithm is persisted, so a plain open uses it
+        let mut merk = TempMerk::open(&path).unwrap();
+        assert_eq!(merk.hash_algorithm(), HashAlgorithm::Keccak256);
+        assert_eq!(merk.root_hash(), hash);
+        merk.apply(&make_del_batch_seq(0..10), &[]).unwrap();
+        merk.apply(&make_batch_seq(100..110), &[]).unwrap();
+        let hash = merk.root_hash();
+
+        let mut query = Query::new();
+        query.insert_key(seq_key(5));
+        query.insert_range(seq_key(20)..seq_key(30));
+        let proof = merk.prove(query).unwrap();
+
+        assert!(verify(&proof, hash).is_err());
+        let map = verify_with_algorithm(&proof, hash, HashAlgorithm::Keccak256).unwrap();
+        assert_eq!(map.get(&seq_key(5)).unwrap(), None);
+        assert_eq!(map.get(&seq_key(25)).unwrap(), Some(&put_entry_value()[..]));
+    }
+
+    #[test]
+    fn open_existing_empty() {
+        let path = TempMerk::create_path();
+
+        drop(Merk::open(&path).unwrap());
+        assert!(Merk::open_existing(&path).is_err());
+
+        let (_, created) = Merk::open_or_create(&path).unwrap();
+        assert!(created);
+        TempMerk::open(&path).unwrap();
+    }
+
+    #[test]
+    fn contains_key() {
+        let path = TempMerk::create_path();
+
+        {
+            let mut merk = Merk::open(&path).unwrap();
+            assert!(!merk.contains_key(&seq_key(0)).unwrap());
+            merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+            assert!(merk.contains_key(&seq_key(0)).unwrap());
+            assert!(!merk.contains_key(&seq_key(1_000)).unwrap());
+        }
+
+        // after reopening, only the root node is in memory
+        let merk = TempMerk::open(&path).unwrap();
+        let root_key = merk.use_tree(|maybe_tree| maybe_tree.unwrap().key().to_vec());
+        merk.use_tree(|maybe_tree| {
+            let tree = maybe_tree.unwrap();
+            assert_eq!(tree.contains_key(&root_key), Some(true));
+            assert_eq!(tree.contains_key(&seq_key(0)), None);
+        });
+
+        assert!(merk.contains_key(&root_key).unwrap());
+        assert!(merk.contains_key(&seq_key(0)).unwrap());
+        assert!(merk.contains_key(&seq_key(999)).unwrap());
+        assert!(!merk.contains_key(&seq_key(1_000)).unwrap());
+        assert!(!merk.contains_key(&[1, 2, 3]).unwrap());
+    }
+
+    #[test]
+    fn prune_levels() {
+        fn count_links(tree: &Tree, references: &mut usize, loaded: &mut usize) {
+            for left in [true, false] {
+                match tree.link(left) {
+                    Some(Link::Reference { .. }) => *references += 1,
+                    Some(_) => {
+                        *loaded += 1;
+                        count_links(tree.child(left).unwrap(), references, loaded);
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+        merk.set_prune_levels(1);
+        merk.apply(&make_batch_seq(0..10_000), &[])
+            .expect("apply failed");
+
+        let (mut references, mut loaded) = (0, 0);
+        merk.use_tree(|maybe_tree| count_links(maybe_tree.unwrap(), &mut references, &mut loaded));
+        assert_eq!(loaded, 2);
+        assert_eq!(references, 4);
+
+        merk.set_prune_levels(0);
+        merk.apply(&make_batch_seq(10_000..10_010), &[])
+            .expect("apply failed");
+        let (mut references, mut loaded) = (0, 0);
+        merk.use_tree(|maybe_tree| count_links(maybe_tree.unwrap(), &mut references, &mut loaded));
+        assert_eq!(loaded, 0);
+        assert_eq!(references, 2);
+
+        merk.set_prune_levels(u8::MAX);
+        merk.apply(&make_batch_seq(10_010..10_020), &[])
+            .expect("apply failed");
+        let (mut references, mut loaded) = (0, 0);
+        merk.use_tree(|maybe_tree| count_links(maybe_tree.unwrap(), &mut references, &mut loaded));
+        assert!(loaded > 2);
+        assert_eq!(merk.get(&seq_key(5_000)).unwrap(), Some(put_entry_value()));
+    }
+
+    #[test]
+    fn commit_capacity() {
+        assert_eq!(default_commit_capacity(1), 1);
+        assert_eq!(default_commit_capacity(4), 15);
+        assert_eq!(default_commit_capacity(14), 10_000);
+        assert_eq!(default_commit_capacity(u8::MAX), 10_000);
+
+        let tree = make_tree_seq(100);
+        let strategy = LevelPrune::new(0);
+        let capacity = default_commit_capacity(tree.height());
+        let committer = MerkCommitter::new(&strategy, capacity);
+        assert!(committer.batch.capacity() >= 100);
+        assert!(committer.batch.capacity() < 10_000);
+
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.set_commit_capacity(Some(1));
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+        merk.set_commit_capacity(None);
+        merk.apply(&make_batch_seq(100..200), &[])
+            .expect("apply failed");
+        assert_eq!(merk.iter().count(), 200);
+    }
+
+    #[test]
+    fn inorder_commit() {
+        let mut expected = TempMerk::new().expect("failed to open merk");
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.set_inorder_commit(true);
+
+        for i in 0..5 {
+            let batch = make_batch_seq(i * 200..i * 200 + 500);
+            expected.apply(&batch, &[]).expect("apply failed");
+            merk.apply(&batch, &[]).expect("apply failed");
+            assert_eq!(merk.root_hash(), expected.root_hash());
+        }
+
+        let deletes = make_del_batch_seq(100..400);
+        expected.apply(&deletes, &[]).expect("apply failed");
+        merk.apply(&deletes, &[]).expect("apply failed");
+        assert_eq!(merk.root_hash(), expected.root_hash());
+        assert_invariants(&merk);
+
+        let collect_nodes = |merk: &Merk| {
+            let mut nodes = vec![];
+            let mut iter = merk.raw_iter();
+            iter.seek_to_first();
+            while iter.valid() {
+                nodes.push((iter.key().unwrap().to_vec(), iter.value().unwrap().to_vec()));
+                iter.next();
+            }
+            nodes
+        };
+        assert_eq!(collect_nodes(&merk), collect_nodes(&expected));
+    }
+
+    #[test]
+    fn custom_prune_strategy() {
+        struct PrefixPrune(Vec<u8>);
+
+        impl PruneStrategy for PrefixPrune {
+            fn should_prune(&self, tree: &Tree) -> (bool, bool) {
+                let prune = |left| {
+                    tree.link(left)
+                        .is_none_or(|link| !link.key().starts_with(&self.0))
+                };
+                (prune(true), prune(false))
+            }
+        }
+
+        fn check_links(tree: &Tree, prefix: &[u8], loaded: &mut usize) {
+            for left in [true, false] {
+                match tree.link(left) {
+                    Some(Link::Reference { key, .. }) => assert!(!key.starts_with(prefix)),
+                    Some(link) => {
+                        assert!(link.key().starts_with(prefix));
+                        *loaded += 1;
+                        check_links(tree.child(left).unwrap(), prefix, loaded);
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+        merk.set_prune_strategy(PrefixPrune(vec![1]));
+
+        // mostly prefixed keys, so the root and upper levels have the prefix
+        let mut batch: Vec<_> = (0..10u8).map(|n| (vec![0, n], Op::Put(vec![n]))).collect();
+        batch.extend((0..100u8).map(|n| (vec![1, n], Op::Put(vec![n]))));
+        batch.extend((0..10u8).map(|n| (vec![2, n], Op::Put(vec![n]))));
+        merk.apply(&batch, &[]).expect("apply failed");
 
-    pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<Merk> {
-        Checkpoint::new(&self.db)?.create_checkpoint(&path)?;
-        Merk::open(path)
+        let mut loaded = 0;
+        merk.use_tree(|maybe_tree| {
+            let tree = maybe_tree.unwrap();
+            assert!(tree.key().starts_with(&[1]));
+            check_links(tree, &[1], &mut loaded);
+        });
+        assert!(loaded >= 80);
     }
 
-    pub fn snapshot(&self) -> Result<Snapshot> {
-        Ok(Snapshot::new(self.db.snapshot(), load_root(&self.db)?))
-    }
+    #[test]
+    fn delete_range() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..500), &[])
+            .expect("apply failed");
 
-    pub fn db(&self) -> &DB {
-        &self.db
-    }
+        merk.delete_range(seq_key(100)..seq_key(200), &[])
+            .expect("delete_range failed");
 
-    fn source(&self) -> MerkSource {
-        MerkSource { db: &self.db }
-    }
+        merk.use_tree(|maybe_tree| assert_tree_invariants(maybe_tree.unwrap()));
+        assert_eq!(merk.len().unwrap(), 400);
+        for n in 0..500 {
+            let value = merk.get(&seq_key(n)).expect("get failed");
+            assert_eq!(value.is_some(), !(100..200).contains(&n));
+        }
 
-    fn use_tree<T>(&self, f: impl FnOnce(Option<&Tree>) -> T) -> T {
-        let tree = self.tree.read().unwrap();
-        f(tree.as_ref())
+        // empty range is a no-op
+        let root_hash = merk.root_hash();
+        merk.delete_range(seq_key(100)..seq_key(200), &[])
+            .expect("delete_range failed");
+        assert_eq!(merk.root_hash(), root_hash);
     }
 
-    fn use_tree_mut<T>(&self, f: impl FnOnce(Option<&mut Tree>) -> T) -> T {
-        let mut tree_slot = self.tree.write().unwrap();
-        let mut tree = tree_slot.take();
-        let res = f(tree.as_mut());
-        *tree_slot = tree;
-        res
+    #[test]
+    fn first_and_last_key_empty() {
+        let merk = TempMerk::new().expect("failed to open merk");
+        assert_eq!(merk.first_key().unwrap(), None);
+        assert_eq!(merk.last_key().unwrap(), None);
     }
 
-    pub(crate) fn write(&mut self, batch: WriteBatch) -> Result<()> {
-        let mut opts = rocksdb::WriteOptions::default();
-        opts.set_sync(false);
-        // TODO: disable WAL once we can ensure consistency with transactions
-        self.db.write_opt(batch, &opts)?;
-        Ok(())
-    }
+    #[test]
+    fn first_and_last_key() {
+        let path = TempMerk::create_path();
 
-    pub(crate) fn set_root_key(&mut self, key: Vec<u8>) -> Result<()> {
-        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
-        let mut batch = WriteBatch::default();
-        batch.put_cf(internal_cf, ROOT_KEY_KEY, key);
-        self.write(batch)
-    }
+        {
+            let mut merk = Merk::open(&path).unwrap();
+            merk.apply(&make_batch_seq(10..500), &[]).unwrap();
+            assert_eq!(merk.first_key().unwrap(), Some(seq_key(10)));
+            assert_eq!(merk.last_key().unwrap(), Some(seq_key(499)));
+        }
 
-    pub(crate) fn fetch_node(&self, key: &[u8]) -> Result<Option<Tree>> {
-        self.source().fetch_by_key(key)
+        // after reopening, only the root node is in memory so the edges are
+        // read from the backing store
+        let merk = TempMerk::open(&path).unwrap();
+        merk.use_tree(|maybe_tree| {
+            let tree = maybe_tree.unwrap();
+            assert!(tree.child(true).is_none());
+            assert!(tree.child(false).is_none());
+        });
+        assert_eq!(merk.first_key().unwrap(), Some(seq_key(10)));
+        assert_eq!(merk.last_key().unwrap(), Some(seq_key(499)));
     }
 
-    pub(crate) fn load_root(&mut self) -> Result<()> {
-        let root = load_root(&self.db)?;
-        *self.tree.write().unwrap() = root;
-        Ok(())
+    #[test]
+    fn compute_root_after() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        let batch = make_batch_seq(0..100);
+        let expected = merk.compute_root_after(&batch).unwrap();
+        assert_eq!(merk.root_hash(), NULL_HASH);
+        merk.apply(&batch, &[]).unwrap();
+        assert_eq!(merk.root_hash(), expected);
+
+        let mut batch = make_del_batch_seq(20..40);
+        batch.extend(make_batch_seq(100..150));
+        let root_hash = merk.root_hash();
+        let expected = merk.compute_root_after(&batch).unwrap();
+        assert_ne!(expected, root_hash);
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.len().unwrap(), 100);
+        merk.apply(&batch, &[]).unwrap();
+        assert_eq!(merk.root_hash(), expected);
+
+        let mut batch = make_del_batch_seq(0..20);
+        batch.extend(make_del_batch_seq(40..150));
+        assert_eq!(merk.compute_root_after(&batch).unwrap(), NULL_HASH);
     }
-}
 
-#[derive(Clone)]
-pub struct MerkSource<'a> {
-    db: &'a rocksdb::DB,
-}
+    #[test]
+    fn apply_transactional() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply_transactional(&make_batch_seq(0..100), &[], |_| Ok(()))
+            .expect("apply failed");
+        let root_hash = merk.root_hash();
 
-impl<'a> Fetch for MerkSource<'a> {
-    fn fetch_by_key(&self, key: &[u8]) -> Result<Option<Tree>> {
-        Ok(self
-            .db
-            .get_pinned(key)?
-            .map(|bytes| Tree::decode(key.to_vec(), &bytes)))
-    }
-}
+        let aux = vec![(vec![1], Op::Put(vec![2]))];
+        let res =
+            merk.apply_transactional(&make_batch_seq(100..200), &aux, |_| Err(Error::Unknown));
+        assert!(matches!(res, Err(Error::Unknown)));
 
-struct MerkCommitter {
-    batch: Vec<(Vec<u8>, Option<Vec<u8>>)>,
-    height: u8,
-    levels: u8,
-}
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.len().unwrap(), 100);
+        assert_eq!(merk.get(&seq_key(150)).unwrap(), None);
+        assert_eq!(merk.get_aux(&[1]).unwrap(), None);
+        assert_invariants(&merk);
 
-impl MerkCommitter {
-    fn new(height: u8, levels: u8) -> Self {
-        MerkCommitter {
-            batch: Vec::with_capacity(10000),
-            height,
-            levels,
-        }
+        // the store is still usable afterward
+        merk.apply_transactional(&make_batch_seq(100..200), &aux, |_| Ok(()))
+            .expect("apply failed");
+        assert_eq!(merk.len().unwrap(), 200);
+        assert_eq!(merk.get_aux(&[1]).unwrap(), Some(vec![2]));
+        assert_invariants(&merk);
     }
-}
 
-impl Commit for MerkCommitter {
-    fn write(&mut self, tree: &Tree) -> Result<()> {
-        let mut buf = Vec::with_capacity(tree.encoding_length());
-        tree.encode_into(&mut buf);
-        self.batch.push((tree.key().to_vec(), Some(buf)));
-        Ok(())
-    }
+    #[test]
+    fn sync_writes_survive_reopen() {
+        let path = TempMerk::create_path();
 
-    fn prune(&self, tree: &Tree) -> (bool, bool) {
-        // keep N top levels of tree
-        let prune = (self.height - tree.height()) >= self.levels;
-        (prune, prune)
-    }
-}
+        let root_hash = {
+            let mut merk = Merk::open(&path).unwrap();
+            merk.set_write_config(WriteConfig {
+                sync: true,
+                disable_wal: false,
+            });
+            merk.apply(&make_batch_seq(0..100), &[(vec![1], Op::Put(vec![2]))])
+                .unwrap();
+            merk.root_hash()
+        };
 
-pub fn get<F: Fetch>(tree: &Tree, source: F, key: &[u8]) -> Result<Option<Vec<u8>>> {
-    Ok(match tree.get_value(key)? {
-        GetResult::Found(value) => Some(value),
-        GetResult::NotFound => None,
-        GetResult::Pruned => source.fetch_by_key(key)?.map(|node| node.value().to_vec()),
-    })
-}
+        let merk = TempMerk::open(&path).unwrap();
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.len().unwrap(), 100);
+        assert_eq!(merk.get_aux(&[1]).unwrap(), Some(vec![2]));
+    }
 
-fn root_hash(maybe_tree: Option<&Tree>) -> Hash {
-    maybe_tree.map_or(NULL_HASH, |tree| tree.hash())
-}
+    #[test]
+    fn apply_parallel() {
+        let mut serial = TempMerk::new().expect("failed to open merk");
+        let mut parallel = TempMerk::new().expect("failed to open merk");
+
+        let batch = make_batch_rand(50_000, 0);
+        serial.apply(&batch, &[]).expect("apply failed");
+        parallel.apply_parallel(&batch, &[]).expect("apply failed");
+        assert_eq!(parallel.root_hash(), serial.root_hash());
+
+        let mut batch = make_batch_rand(50_000, 1);
+        batch.extend(make_del_batch_rand(10_000, 0));
+        batch.sort_by(|a, b| a.0.cmp(&b.0));
+        batch.dedup_by(|a, b| a.0 == b.0);
+        serial.apply(&batch, &[]).expect("apply failed");
+        parallel.apply_parallel(&batch, &[]).expect("apply failed");
+        assert_eq!(parallel.root_hash(), serial.root_hash());
+        assert_eq!(parallel.len().unwrap(), serial.len().unwrap());
+        assert_invariants(&parallel);
+
+        // delete the root key
+        let root_key = parallel.use_tree(|maybe_tree| maybe_tree.unwrap().key().to_vec());
+        let batch = vec![(root_key.clone(), Op::Delete)];
+        serial.apply(&batch, &[]).expect("apply failed");
+        parallel.apply_parallel(&batch, &[]).expect("apply failed");
+        assert_eq!(parallel.root_hash(), serial.root_hash());
+        assert_eq!(parallel.get(&root_key).unwrap(), None);
+        assert_invariants(&parallel);
+    }
 
-fn prove<Q, I, F>(maybe_tree: Option<&mut Tree>, source: F, query: I) -> Result<Vec<u8>>
-where
-    Q: Into<QueryItem>,
-    I: IntoIterator<Item = Q>,
-    F: Fetch + Send + Clone,
-{
-    let query_vec: Vec<QueryItem> = query.into_iter().map(Into::into).collect();
+    #[test]
+    fn open_without_snapshots_cf() {
+        let path = TempMerk::create_path();
+        let secondary_path = TempMerk::create_path();
+
+        // a database with the column families from before named snapshots
+        let hash = {
+            let cfs = vec![
+                rocksdb::ColumnFamilyDescriptor::new("aux", Merk::default_db_opts()),
+                rocksdb::ColumnFamilyDescriptor::new("internal", Merk::default_db_opts()),
+            ];
+            let opts = Merk::default_db_opts();
+            let db = rocksdb::DB::open_cf_descriptors(&opts, &path, cfs).unwrap();
+            let mut merk = Merk::open_on_db(Arc::new(db), b"").unwrap();
+            merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+            assert!(merk.snapshot_named(b"a").is_err());
+            assert_eq!(merk.snapshot_root_hash(b"a").unwrap(), None);
+            merk.root_hash()
+        };
 
-    let tree =
-        maybe_tree.ok_or_else(|| Error::Proof("Cannot create proof for empty tree".into()))?;
+        let merk = Merk::open_readonly(&path).unwrap();
+        assert_eq!(merk.root_hash(), hash);
+        assert_eq!(merk.get(&seq_key(5)).unwrap(), Some(put_entry_value()));
+        assert!(merk.open_snapshot(b"a").is_err());
+        drop(merk);
 
-    let mut ref_walker = RefWalker::new(tree, source);
-    let (proof, _) = ref_walker.create_proof(query_vec.as_slice())?;
+        let merk = Merk::open_as_secondary(&path, &secondary_path).unwrap();
+        assert_eq!(merk.root_hash(), hash);
+        drop(merk);
+        std::fs::remove_dir_all(&secondary_path).ok();
 
-    let mut bytes = Vec::with_capacity(128);
-    encode_into(proof.iter(), &mut bytes);
-    Ok(bytes)
-}
+        // opening the store for writing adds the column family
+        let merk = TempMerk::open(&path).unwrap();
+        merk.snapshot_named(b"a").unwrap();
+        assert_eq!(merk.snapshot_root_hash(b"a").unwrap(), Some(hash));
+    }
 
-fn load_root(db: &DB) -> Result<Option<Tree>> {
-    let internal_cf = db.cf_handle(INTERNAL_CF_NAME).unwrap();
-    db.get_pinned_cf(internal_cf, ROOT_KEY_KEY)?
-        .map(|key| MerkSource { db }.fetch_by_key_expect(key.to_vec().as_slice()))
-        .transpose()
-}
+    #[test]
+    fn named_snapshots() {
+        fn put_all(merk: &mut Merk, value: u8) {
+            let batch: Vec<_> = (0..10)
+                .map(|n| (seq_key(n), Op::Put(vec![value])))
+                .collect();
+            merk.apply(&batch, &[]).expect("apply failed");
+        }
 
-#[cfg(test)]
-mod test {
-    use super::{Merk, MerkSource, RefWalker};
-    use crate::test_utils::*;
-    use crate::Op;
-    use std::thread;
+        let path = TempMerk::create_path();
 
-    // TODO: Close and then reopen test
+        {
+            let mut merk = Merk::open(&path).unwrap();
+            put_all(&mut merk, 1);
+            merk.snapshot_named(b"a").unwrap();
+            let hash_a = merk.root_hash();
+            put_all(&mut merk, 2);
+            merk.snapshot_named(b"b").unwrap();
+            let hash_b = merk.root_hash();
+            put_all(&mut merk, 3);
+
+            assert!(merk.snapshot_named(b"a").is_err());
+            assert!(merk.open_snapshot(b"c").is_err());
+            assert_eq!(merk.snapshot_root_hash(b"a").unwrap(), Some(hash_a));
+            assert_eq!(merk.snapshot_root_hash(b"b").unwrap(), Some(hash_b));
+            assert_eq!(merk.snapshot_root_hash(b"c").unwrap(), None);
+            // only the root hash is persisted
+            let record = merk.db.get_cf(merk.snapshots_cf().unwrap(), b"b");
+            assert_eq!(record.unwrap(), Some(hash_b.to_vec()));
+
+            let a = merk.open_snapshot(b"a").unwrap();
+            let b = merk.open_snapshot(b"b").unwrap();
+            assert_eq!(a.root_hash(), hash_a);
+            assert_eq!(b.root_hash(), hash_b);
+            assert_eq!(a.get(&seq_key(5)).unwrap(), Some(vec![1]));
+            assert_eq!(b.get(&seq_key(5)).unwrap(), Some(vec![2]));
+            assert_eq!(merk.get(&seq_key(5)).unwrap(), Some(vec![3]));
+            drop((a, b));
+
+            merk.remove_snapshot(b"a").unwrap();
+            assert!(merk.open_snapshot(b"a").is_err());
+            merk.snapshot_named(b"c").unwrap();
+        }
 
-    fn assert_invariants(merk: &TempMerk) {
-        merk.use_tree(|maybe_tree| {
-            let tree = maybe_tree.expect("expected tree");
-            assert_tree_invariants(tree);
-        })
+        // after reopening, only the snapshot of the current state is available
+        let merk = TempMerk::open(&path).unwrap();
+        assert!(merk.open_snapshot(b"b").is_err());
+        assert!(merk.snapshot_root_hash(b"b").unwrap().is_some());
+        let c = merk.open_snapshot(b"c").unwrap();
+        assert_eq!(c.get(&seq_key(5)).unwrap(), Some(vec![3]));
     }
 
     #[test]
-    fn simple_insert_apply() {
-        let batch_size = 20;
-
-        let path = thread::current().name().unwrap().to_owned();
-        let mut merk = TempMerk::open(path).expect("failed to open merk");
+    fn get_aux_many() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        assert_eq!(
+            merk.get_aux_many(&[]).unwrap(),
+            Vec::<Option<Vec<u8>>>::new()
+        );
 
-        let batch = make_batch_seq(0..batch_size);
-        merk.apply(&batch, &[]).expect("apply failed");
+        let aux: Vec<_> = (0..10u8).map(|n| (vec![n], Op::Put(vec![n * 2]))).collect();
+        merk.apply(&[], &aux).unwrap();
 
-        assert_invariants(&merk);
+        let keys = vec![vec![9], vec![20], vec![0], vec![5], vec![], vec![0]];
         assert_eq!(
-            merk.root_hash(),
-            [
-                29, 99, 91, 248, 54, 96, 47, 252, 39, 203, 208, 163, 199, 30, 34, 251, 247, 34,
-                241, 203, 17, 252, 127, 44, 155, 83, 22, 54, 117, 85, 252, 200
+            merk.get_aux_many(&keys).unwrap(),
+            vec![
+                Some(vec![18]),
+                None,
+                Some(vec![0]),
+                Some(vec![10]),
+                None,
+                Some(vec![0])
             ]
         );
     }
 
     #[test]
-    fn insert_uncached() {
-        let batch_size = 20;
-
-        let path = thread::current().name().unwrap().to_owned();
-        let mut merk = TempMerk::open(path).expect("failed to open merk");
+    fn aux_iter() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        assert_eq!(merk.aux_iter().count(), 0);
+
+        let aux: Vec<_> = (0..10u8).map(|n| (vec![n], Op::Put(vec![n * 2]))).collect();
+        merk.apply(&make_batch_seq(0..10), &aux).unwrap();
+        merk.apply(&[], &[(vec![4], Op::Delete)]).unwrap();
+
+        let entries = merk.aux_iter().collect::<Result<Vec<_>>>().unwrap();
+        let expected: Vec<_> = (0..10u8)
+            .filter(|n| *n != 4)
+            .map(|n| (vec![n], vec![n * 2]))
+            .collect();
+        assert_eq!(entries, expected);
 
-        let batch = make_batch_seq(0..batch_size);
-        merk.apply(&batch, &[]).expect("apply failed");
-        assert_invariants(&merk);
+        let entries = merk
+            .aux_range(vec![3]..vec![7])
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            entries,
+            vec![(vec![3], vec![6]), (vec![5], vec![10]), (vec![6], vec![12])]
+        );
 
-        let batch = make_batch_seq(batch_size..(batch_size * 2));
-        merk.apply(&batch, &[]).expect("apply failed");
-        assert_invariants(&merk);
+        assert_eq!(merk.aux_range(vec![20]..vec![30]).count(), 0);
     }
 
     #[test]
-    fn insert_rand() {
-        let tree_size = 40;
-        let batch_size = 4;
+    fn aux_fingerprint() {
+        let mut a = TempMerk::new().expect("failed to open merk");
+        let mut b = TempMerk::new().expect("failed to open merk");
+        assert_eq!(a.aux_fingerprint().unwrap(), NULL_HASH);
+
+        let aux: Vec<_> = (0..10u8).map(|n| (vec![n], Op::Put(vec![n * 2]))).collect();
+        a.apply(&make_batch_seq(0..10), &aux).unwrap();
+        b.apply(&[], &aux).unwrap();
+        assert_eq!(a.aux_fingerprint().unwrap(), b.aux_fingerprint().unwrap());
+        assert_ne!(a.aux_fingerprint().unwrap(), NULL_HASH);
+
+        b.apply(&[], &[(vec![4], Op::Put(vec![0]))]).unwrap();
+        assert_ne!(a.aux_fingerprint().unwrap(), b.aux_fingerprint().unwrap());
+
+        b.apply(&[], &[(vec![4], Op::Put(vec![8]))]).unwrap();
+        assert_eq!(a.aux_fingerprint().unwrap(), b.aux_fingerprint().unwrap());
+    }
 
-        let path = thread::current().name().unwrap().to_owned();
-        let mut merk = TempMerk::open(path).expect("failed to open merk");
+    #[test]
+    fn stats() {
+        let path = TempMerk::create_path();
+
+        let expected = TreeStats {
+            // a perfectly balanced tree of 1000 nodes
+            height: 10,
+            node_count: 1000,
+            key_bytes: 1000 * 8,
+            value_bytes: 1000 * put_entry_value().len() as u64,
+        };
 
-        for i in 0..(tree_size / batch_size) {
-            println!("i:{i}");
-            let batch = make_batch_rand(batch_size, i);
-            merk.apply(&batch, &[]).expect("apply failed");
+        {
+            let mut merk = Merk::open(&path).unwrap();
+            assert_eq!(merk.stats().unwrap(), TreeStats::default());
+            merk.apply(&make_batch_seq(0..1000), &[]).unwrap();
+            assert_eq!(merk.stats().unwrap(), expected);
         }
+
+        // after reopening, only the root node is in memory
+        let merk = TempMerk::open(&path).unwrap();
+        assert_eq!(merk.stats().unwrap(), expected);
     }
 
     #[test]
-    fn actual_deletes() {
-        let path = thread::current().name().unwrap().to_owned();
-        let mut merk = TempMerk::open(path).expect("failed to open merk");
+    fn balance_histogram() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        assert_eq!(merk.balance_histogram().unwrap(), [0, 0, 0]);
 
-        let batch = make_batch_rand(10, 1);
-        merk.apply(&batch, &[]).expect("apply failed");
-
-        let key = batch.first().unwrap().0.clone();
-        merk.apply(&[(key.clone(), Op::Delete)], &[]).unwrap();
+        merk.apply(&make_batch_rand(1000, 0), &[]).unwrap();
+        merk.apply(&make_batch_rand(1000, 1), &[]).unwrap();
+        merk.apply(&make_del_batch_rand(500, 0), &[]).unwrap();
 
-        let value = merk.db.get(key.as_slice()).unwrap();
-        assert!(value.is_none());
+        let histogram = merk.balance_histogram().unwrap();
+        assert_eq!(
+            histogram.iter().sum::<u64>(),
+            merk.stats().unwrap().node_count
+        );
+        assert_eq!(histogram.iter().sum::<u64>(), 1500);
+        assert!(histogram[1] > 0);
     }
 
     #[test]
-    fn aux_data() {
+    fn len_after_deletes() {
         let path = thread::current().name().unwrap().to_owned();
         let mut merk = TempMerk::open(path).expect("failed to open merk");
-        merk.apply(&[], &[(vec![1, 2, 3], Op::Put(vec![4, 5, 6]))])
+        merk.apply(&make_batch_seq(0..500), &[])
             .expect("apply failed");
-        let val = merk.get_aux(&[1, 2, 3]).unwrap();
-        assert_eq!(val, Some(vec![4, 5, 6]));
-    }
-
-    #[test]
-    fn simulated_crash() {
-        let path = thread::current().name().unwrap().to_owned();
-        let mut merk = CrashMerk::open(path).expect("failed to open merk");
-
-        merk.apply(
-            &[(vec![0], Op::Put(vec![1]))],
-            &[(vec![2], Op::Put(vec![3]))],
-        )
-        .expect("apply failed");
-
-        // make enough changes so that main column family gets auto-flushed
-        for i in 0..250 {
-            merk.apply(&make_batch_seq(i * 2_000..(i + 1) * 2_000), &[])
-                .expect("apply failed");
-        }
-
-        unsafe {
-            merk.crash().unwrap();
-        }
-
-        assert_eq!(merk.get_aux(&[2]).unwrap(), Some(vec![3]));
-        merk.destroy().unwrap();
+        merk.apply(&make_del_batch_seq(100..200), &[])
+            .expect("apply failed");
+        assert_eq!(merk.len().unwrap(), 400);
     }
 
     #[test]
@@ -742,6 +4675,141 @@ mod test {
         assert_eq!(merk.get(&[2]).unwrap(), Some(vec![0]));
     }
 
+    #[test]
+    fn checkpoint_consistent() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(&path).expect("failed to open merk");
+
+        let batch = make_batch_seq(0..100);
+        let aux = [(vec![1], Op::Put(vec![2]))];
+        merk.apply(&batch, &aux).expect("apply failed");
+
+        for (i, &consistent) in [false, true].iter().enumerate() {
+            let checkpoint_path = format!("{path}.checkpoint{i}");
+            let checkpoint = if consistent {
+                merk.checkpoint_consistent(&checkpoint_path)
+            } else {
+                merk.checkpoint(&checkpoint_path)
+            }
+            .expect("checkpoint failed");
+
+            assert_eq!(checkpoint.root_hash(), merk.root_hash());
+            assert_eq!(checkpoint.iter().count(), 100);
+            assert_eq!(
+                checkpoint.get(&seq_key(99)).unwrap(),
+                merk.get(&seq_key(99)).unwrap()
+            );
+            assert_eq!(checkpoint.get_aux(&[1]).unwrap(), Some(vec![2]));
+            checkpoint.destroy().unwrap();
+        }
+    }
+
+    #[test]
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    fn open_compressed() {
+        use super::Compression;
+
+        let path = thread::current().name().unwrap().to_owned();
+        let batch = make_batch_rand(500, 1);
+        let aux = [(vec![1], Op::Put(vec![2; 1000]))];
+
+        let mut expected = TempMerk::new().expect("failed to open merk");
+        expected.apply(&batch, &aux).expect("apply failed");
+
+        let compressions = [
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd,
+        ];
+        for (i, &compression) in compressions.iter().enumerate() {
+            let merk_path = format!("{path}{i}");
+            let mut merk =
+                Merk::open_compressed(&merk_path, compression).expect("failed to open merk");
+            merk.apply(&batch, &aux).expect("apply failed");
+            merk.flush().unwrap();
+            assert_eq!(merk.root_hash(), expected.root_hash());
+
+            // reopen so values are read from the backing store
+            drop(merk);
+            let merk = Merk::open_compressed(&merk_path, compression).expect("failed to open merk");
+            assert_eq!(merk.root_hash(), expected.root_hash());
+            for (key, op) in batch.iter() {
+                let value = match op {
+                    Op::Put(value) => value,
+                    _ => unreachable!(),
+                };
+                assert_eq!(merk.get(key).unwrap().as_ref(), Some(value));
+            }
+            assert_eq!(merk.get_aux(&[1]).unwrap(), Some(vec![2; 1000]));
+            assert_eq!(
+                merk.iter().map(|entry| entry.unwrap()).collect::<Vec<_>>(),
+                expected
+                    .iter()
+                    .map(|entry| entry.unwrap())
+                    .collect::<Vec<_>>()
+            );
+            merk.destroy().unwrap();
+        }
+    }
+
+    #[test]
+    fn keys() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        assert_eq!(merk.keys().count(), 0);
+
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+        let keys: Vec<_> = merk.keys().map(|key| key.unwrap()).collect();
+        let expected: Vec<_> = (0..100).map(seq_key).collect();
+        assert_eq!(keys, expected);
+
+        merk.apply(&make_del_batch_seq(0..100), &[])
+            .expect("apply failed");
+        assert_eq!(merk.keys().count(), 0);
+    }
+
+    #[test]
+    fn fork() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..100), &[(vec![1], Op::Put(vec![1]))])
+            .expect("apply failed");
+        let root_hash = merk.root_hash();
+
+        let mut fork = merk.fork().expect("fork failed");
+        let fork_path = fork.path.clone();
+        assert!(fork_path.exists());
+        assert_eq!(fork.root_hash(), root_hash);
+
+        fork.apply(&make_del_batch_seq(0..50), &[(vec![1], Op::Delete)])
+            .expect("apply failed");
+        fork.apply(&make_batch_seq(100..150), &[])
+            .expect("apply failed");
+        assert_ne!(fork.root_hash(), root_hash);
+        assert_eq!(fork.get(&seq_key(0)).unwrap(), None);
+        assert_eq!(fork.get_aux(&[1]).unwrap(), None);
+
+        // the original is unchanged
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.iter().count(), 100);
+        assert_eq!(merk.get(&seq_key(120)).unwrap(), None);
+        assert_eq!(merk.get_aux(&[1]).unwrap(), Some(vec![1]));
+
+        // forks can be forked, and each is deleted when dropped
+        let nested = fork.fork().expect("fork failed");
+        assert_eq!(nested.iter().count(), 100);
+        let nested_path = nested.path.clone();
+        drop(nested);
+        assert!(!nested_path.exists());
+        drop(fork);
+        assert!(!fork_path.exists());
+
+        let fork = merk.fork().expect("fork failed");
+        let fork_path = fork.path.clone();
+        fork.destroy().expect("destroy failed");
+        assert!(!fork_path.exists());
+    }
+
     #[test]
     fn checkpoint_iterator() {
         let path = thread::current().name().unwrap().to_owned();
@@ -0,0 +1,319 @@
+//! Provides `MultiApply`, which applies batches to several `Merk` instances
+//! atomically.
+
+use std::sync::Arc;
+
+use rocksdb::WriteBatch;
+
+use super::{Merk, AUX_CF_NAME, INTERNAL_CF_NAME, ROOT_KEY_KEY};
+use crate::tree::{Batch, Walker};
+use crate::Result;
+
+/// Applies batches to several `Merk` instances (e.g. the shards of a sharded
+/// store) so that either all of them are applied or none are.
+///
+/// Committing happens in two phases. First, each batch is applied to its
+/// instance's in-memory tree and the resulting writes are prepared without
+/// being written. If any of these steps fails, the roots of all instances are
+/// reloaded from disk and nothing is written. Then the prepared writes are
+/// written:
+///
+/// - If all instances share one RocksDB instance (see `Merk::open_on_db`), the
+///   writes are combined into a single atomic write batch.
+/// - Otherwise each instance is written in turn. If a write fails, the
+///   instances which were already written are restored to their previous state
+///   and the roots of all instances are reloaded.
+///
+/// # Example
+/// ```
+/// # let mut a = merk::test_utils::TempMerk::new().unwrap();
+/// # let mut b = merk::test_utils::TempMerk::new().unwrap();
+/// use merk::{MultiApply, Op};
+///
+/// let batch_a = [(vec![1], Op::Put(vec![1]))];
+/// let batch_b = [(vec![2], Op::Put(vec![2]))];
+///
+/// let mut multi = MultiApply::new();
+/// multi.add(&mut a, &batch_a, &[]);
+/// multi.add(&mut b, &batch_b, &[]);
+/// multi.commit().unwrap();
+/// ```
+#[derive(Default)]
+pub struct MultiApply<'a> {
+    entries: Vec<Entry<'a>>,
+}
+
+struct Entry<'a> {
+    merk: &'a mut Merk,
+    batch: &'a Batch,
+    aux: &'a Batch,
+}
+
+/// The changes prepared for one instance.
+struct Prepared {
+    changes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    deleted: Vec<Vec<u8>>,
+}
+
+impl<'a> MultiApply<'a> {
+    /// Creates an empty `MultiApply`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a batch of operations and a batch of auxiliary operations to be
+    /// applied to `merk`. As with `Merk::apply`, the keys in each batch must
+    /// be sorted and unique.
+    pub fn add(&mut self, merk: &'a mut Merk, batch: &'a Batch, aux: &'a Batch) -> &mut Self {
+        self.entries.push(Entry { merk, batch, aux });
+        self
+    }
+
+    /// Applies all the added batches, either to all of their instances or (if
+    /// an error is returned) to none of them.
+    pub fn commit(mut self) -> Result<()> {
+        for entry in self.entries.iter() {
+            entry.merk.validate_batch(entry.batch)?;
+        }
+
+        let shared_db = self.entries.windows(2).all(|pair| {
+            let (a, b) = (&pair[0].merk.db, &pair[1].merk.db);
+            Arc::ptr_eq(a, b)
+        });
+
+        // one batch per instance, or a single batch for a shared database
+        let mut batches: Vec<WriteBatch> = vec![];
+        let mut prepared = Vec::with_capacity(self.entries.len());
+        for entry in self.entries.iter_mut() {
+            if !shared_db || batches.is_empty() {
+                batches.push(WriteBatch::default());
+            }
+
+            match entry.prepare(batches.last_mut().unwrap()) {
+                Ok(entry_prepared) => prepared.push(entry_prepared),
+                Err(err) => {
+                    self.reload_roots()?;
+                    return Err(err);
+                }
+            }
+        }
+
+        let res = if shared_db {
+            match (self.entries.first_mut(), batches.pop()) {
+                (Some(entry), Some(batch)) => entry.merk.write(batch),
+                _ => Ok(()),
+            }
+        } else {
+            self.write_each(batches, &prepared)
+        };
+        if let Err(err) = res {
+            self.reload_roots()?;
+            return Err(err);
+        }
+
+        for (entry, prepared) in self.entries.iter_mut().zip(prepared) {
+            entry
+                .merk
+                .notify_apply_observer(entry.batch, &prepared.deleted)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the prepared batches to their separate instances in order. If a
+    /// write fails, the instances which were already written are restored.
+    fn write_each(&mut self, batches: Vec<WriteBatch>, prepared: &[Prepared]) -> Result<()> {
+        let undo_batches = self
+            .entries
+            .iter()
+            .zip(prepared)
+            .map(|(entry, prepared)| entry.undo_batch(&prepared.changes))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut undo_batches = undo_batches.into_iter();
+        for (index, batch) in batches.into_iter().enumerate() {
+            if let Err(err) = self.entries[index].merk.write(batch) {
+                for (entry, undo) in self.entries[..index].iter_mut().zip(&mut undo_batches) {
+                    entry.merk.write(undo)?;
+                }
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reloads the in-memory trees of all instances from their backing
+    /// stores, discarding any changes which were applied in memory.
+    fn reload_roots(&mut self) -> Result<()> {
+        for entry in self.entries.iter_mut() {
+            entry.merk.load_root()?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Entry<'a> {
+    /// Applies the batch to the in-memory tree, then adds the resulting writes
+    /// to `batch`.
+    fn prepare(&mut self, batch: &mut WriteBatch) -> Result<Prepared> {
+        let merk = &mut *self.merk;
+
+        let deleted_keys = {
+            let mut tree = merk.tree.write().unwrap();
+            let maybe_walker = tree.take().map(|tree| Walker::new(tree, merk.source()));
+            let (maybe_tree, deleted_keys) =
+                Walker::apply_to(maybe_walker, self.batch, merk.source())?;
+            *tree = maybe_tree;
+            deleted_keys
+        };
+
+        let deleted = deleted_keys.iter().cloned().collect();
+        let changes = merk.prepare_commit(deleted_keys, self.aux, batch)?;
+
+        Ok(Prepared { changes, deleted })
+    }
+
+    /// Creates a batch which restores the current values of all the keys
+    /// written by the prepared commit with the given changes.
+    fn undo_batch(&self, changes: &[(Vec<u8>, Option<Vec<u8>>)]) -> Result<WriteBatch> {
+        let merk = &*self.merk;
+        let mut batch = WriteBatch::default();
+
+        let mut restore = |cf, key: &[u8]| -> Result<()> {
+            match merk.db.get_cf(cf, key)? {
+                Some(value) => batch.put_cf(cf, key, value),
+                None => batch.delete_cf(cf, key),
+            }
+            Ok(())
+        };
+
+        for (key, _) in changes {
+            restore(merk.tree_cf(), key)?;
+        }
+        for (key, _) in self.aux {
+            restore(merk.cf(AUX_CF_NAME), key)?;
+        }
+        restore(merk.cf(INTERNAL_CF_NAME), ROOT_KEY_KEY)?;
+
+        Ok(batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::Op;
+
+    #[test]
+    fn multi_apply() {
+        let mut a = TempMerk::new().unwrap();
+        let mut b = TempMerk::new().unwrap();
+        a.apply(&make_batch_seq(0..100), &[]).unwrap();
+        b.apply(&make_batch_seq(100..200), &[]).unwrap();
+
+        let batch_a = make_batch_seq(200..300);
+        let batch_b = make_del_batch_seq(100..150);
+        let aux = [(vec![1], Op::Put(vec![2]))];
+        let mut multi = MultiApply::new();
+        multi.add(&mut a, &batch_a, &aux).add(&mut b, &batch_b, &[]);
+        multi.commit().unwrap();
+
+        assert_eq!(a.len().unwrap(), 200);
+        assert_eq!(b.len().unwrap(), 50);
+        assert_eq!(a.get_aux(&[1]).unwrap(), Some(vec![2]));
+    }
+
+    #[test]
+    fn multi_apply_shared_db() {
+        let path = TempMerk::create_path();
+        let opts = Merk::default_db_opts();
+        let mut cfs = Merk::column_families_with_prefix(b"a_").unwrap();
+        cfs.extend(Merk::column_families_with_prefix(b"b_").unwrap());
+        let db = Arc::new(rocksdb::DB::open_cf_descriptors(&opts, &path, cfs).unwrap());
+
+        let mut a = Merk::open_on_db(db.clone(), b"a_").unwrap();
+        let mut b = Merk::open_on_db(db.clone(), b"b_").unwrap();
+
+        let batch_a = make_batch_seq(0..100);
+        let batch_b = make_batch_seq(100..200);
+        let mut multi = MultiApply::new();
+        multi.add(&mut a, &batch_a, &[]).add(&mut b, &batch_b, &[]);
+        multi.commit().unwrap();
+        let (hash_a, hash_b) = (a.root_hash(), b.root_hash());
+
+        b.set_key_validator(Box::new(|_| Err(crate::Error::Key("rejected".into()))));
+        let batch_a = make_batch_seq(200..300);
+        let mut multi = MultiApply::new();
+        multi.add(&mut a, &batch_a, &[]).add(&mut b, &batch_b, &[]);
+        assert!(multi.commit().is_err());
+        drop((a, b));
+
+        let a = Merk::open_on_db(db.clone(), b"a_").unwrap();
+        let b = Merk::open_on_db(db.clone(), b"b_").unwrap();
+        assert_eq!(a.root_hash(), hash_a);
+        assert_eq!(b.root_hash(), hash_b);
+        assert_eq!(b.get(&seq_key(150)).unwrap(), Some(put_entry_value()));
+        drop((a, b, db));
+
+        rocksdb::DB::destroy(&opts, &path).unwrap();
+    }
+
+    #[test]
+    fn multi_apply_prepare_failure() {
+        let mut a = TempMerk::new().unwrap();
+        let mut b = TempMerk::new().unwrap();
+        a.apply(&make_batch_seq(0..100), &[]).unwrap();
+        b.apply(&make_batch_seq(100..200), &[]).unwrap();
+        let (hash_a, hash_b) = (a.root_hash(), b.root_hash());
+
+        // the second instance rejects its batch
+        b.set_key_validator(Box::new(|_| Err(crate::Error::Key("rejected".into()))));
+
+        let batch_a = make_batch_seq(200..300);
+        let batch_b = make_batch_seq(300..400);
+        let mut multi = MultiApply::new();
+        multi.add(&mut a, &batch_a, &[]).add(&mut b, &batch_b, &[]);
+        assert!(multi.commit().is_err());
+
+        assert_eq!(a.root_hash(), hash_a);
+        assert_eq!(b.root_hash(), hash_b);
+        assert_eq!(a.len().unwrap(), 100);
+        assert!(a.get(&seq_key(200)).unwrap().is_none());
+    }
+
+    #[test]
+    fn multi_apply_write_failure() {
+        let mut a = TempMerk::new().unwrap();
+        a.apply(&make_batch_seq(0..100), &[]).unwrap();
+        a.flush().unwrap();
+        let hash_a = a.root_hash();
+
+        // writes to a read-only instance fail after the first instance has
+        // already been written
+        let path = TempMerk::create_path();
+        let mut b = Merk::open(&path).unwrap();
+        b.apply(&make_batch_seq(100..200), &[]).unwrap();
+        drop(b);
+        let mut b = Merk::open_readonly(&path).unwrap();
+        let hash_b = b.root_hash();
+
+        let batch_a = make_batch_seq(50..300);
+        let batch_b = make_del_batch_seq(100..150);
+        let aux = [(vec![1], Op::Put(vec![2]))];
+        let mut multi = MultiApply::new();
+        multi.add(&mut a, &batch_a, &aux).add(&mut b, &batch_b, &[]);
+        assert!(multi.commit().is_err());
+
+        assert_eq!(a.root_hash(), hash_a);
+        assert_eq!(b.root_hash(), hash_b);
+        assert_eq!(a.len().unwrap(), 100);
+        assert!(a.get(&seq_key(200)).unwrap().is_none());
+        assert_eq!(a.get_aux(&[1]).unwrap(), None);
+        drop(a);
+        drop(b);
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
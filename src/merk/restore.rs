@@ -5,14 +5,16 @@ use super::Merk;
 use crate::{
     merk::MerkSource,
     proofs::{
-        chunk::{verify_leaf, verify_trunk, MIN_TRUNK_HEIGHT},
+        chunk::{verify_chunk_at, verify_trunk, MIN_TRUNK_HEIGHT},
         tree::{Child, Tree as ProofTree},
-        Decoder, Node,
+        Decoder, Node, Op, ReadDecoder,
     },
     tree::{Link, RefWalker, Tree},
     Error, Hash, Result,
 };
-use rocksdb::WriteBatch;
+use ed::{Decode, Encode};
+use rocksdb::{ColumnFamily, WriteBatch};
+use std::io::Read;
 use std::iter::Peekable;
 use std::path::Path;
 
@@ -20,12 +22,16 @@ use std::path::Path;
 /// replicate an entire Merk tree. It expects the chunks to be processed in
 /// order, retrying the last chunk if verification fails.
 pub struct Restorer {
-    leaf_hashes: Option<Peekable<std::vec::IntoIter<Hash>>>,
+    /// The trunk's boundary hashes, one per leaf chunk.
+    leaf_hashes: Option<Vec<Hash>>,
+    processed_leaves: usize,
     parent_keys: Option<Peekable<std::vec::IntoIter<Vec<u8>>>>,
     trunk_height: Option<usize>,
     merk: Merk,
     expected_root_hash: Hash,
     stated_length: usize,
+    expected_count: Option<u64>,
+    restored_count: u64,
 }
 
 impl Restorer {
@@ -54,10 +60,147 @@ impl Restorer {
             trunk_height: None,
             merk: Merk::open(db_path)?,
             leaf_hashes: None,
+            processed_leaves: 0,
             parent_keys: None,
+            expected_count: None,
+            restored_count: 0,
         })
     }
 
+    /// Continues a restore which was interrupted, using the Merk at `db_path`
+    /// which was being written to and the state returned by
+    /// `Restorer::save_state`. Chunks should then be processed starting with
+    /// the first one which had not been processed when the state was saved.
+    pub fn resume_from_state<P: AsRef<Path>>(
+        db_path: P,
+        expected_root_hash: Hash,
+        state: &[u8],
+    ) -> Result<Self> {
+        if !db_path.as_ref().exists() {
+            return Err(Error::Path("The given path does not exist".into()));
+        }
+
+        let mut input = state;
+        let state_root_hash: Hash = Decode::decode(&mut input)?;
+        if state_root_hash != expected_root_hash {
+            return Err(Error::HashMismatch(expected_root_hash, state_root_hash));
+        }
+        let stated_length: u64 = Decode::decode(&mut input)?;
+
+        let has_trunk: bool = Decode::decode(&mut input)?;
+        let (trunk_height, leaf_hashes, processed_leaves, parent_keys) = if has_trunk {
+            let trunk_height: u64 = Decode::decode(&mut input)?;
+
+            let leaf_hashes_len: u32 = Decode::decode(&mut input)?;
+            let leaf_hashes = (0..leaf_hashes_len)
+                .map(|_| Decode::decode(&mut input))
+                .collect::<ed::Result<Vec<Hash>>>()?;
+            let processed_leaves: u32 = Decode::decode(&mut input)?;
+            if processed_leaves > leaf_hashes_len {
+                return Err(Error::ChunkProcessing(
+                    "Restore state has more processed chunks than leaf hashes".into(),
+                ));
+            }
+
+            let parent_keys_len: u32 = Decode::decode(&mut input)?;
+            let parent_keys = (0..parent_keys_len)
+                .map(|_| {
+                    let len: u16 = Decode::decode(&mut input)?;
+                    let mut key = vec![0; len as usize];
+                    input.read_exact(key.as_mut_slice())?;
+                    Ok(key)
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            (
+                Some(trunk_height as usize),
+                Some(leaf_hashes),
+                processed_leaves as usize,
+                Some(parent_keys.into_iter().peekable()),
+            )
+        } else {
+            (None, None, 0, None)
+        };
+
+        let expected_count: Option<u64> = Decode::decode(&mut input)?;
+        let restored_count: u64 = Decode::decode(&mut input)?;
+
+        if !input.is_empty() {
+            return Err(Error::ChunkProcessing(
+                "Unexpected trailing bytes in restore state".into(),
+            ));
+        }
+
+        Ok(Self {
+            expected_root_hash,
+            stated_length: stated_length as usize,
+            trunk_height,
+            merk: Merk::open(db_path)?,
+            leaf_hashes,
+            processed_leaves,
+            parent_keys,
+            expected_count,
+            restored_count,
+        })
+    }
+
+    /// Serializes the progress of the restore, so that it can be continued by
+    /// passing the state to `Restorer::resume_from_state` if the process is
+    /// interrupted. The state includes the hashes of the leaf chunks along with
+    /// the number which have been processed, the keys of the nodes the
+    /// remaining chunks will be attached to, and the number of entries
+    /// restored so far.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        self.expected_root_hash.encode_into(&mut bytes).unwrap();
+        (self.stated_length as u64).encode_into(&mut bytes).unwrap();
+
+        match (&self.leaf_hashes, &self.parent_keys) {
+            (Some(leaf_hashes), Some(parent_keys)) => {
+                self.encode_trunk_state(leaf_hashes, parent_keys.clone(), &mut bytes)
+            }
+            _ => false.encode_into(&mut bytes).unwrap(),
+        };
+
+        self.expected_count.encode_into(&mut bytes).unwrap();
+        self.restored_count.encode_into(&mut bytes).unwrap();
+
+        bytes
+    }
+
+    /// Encodes the part of the state which is only known once the trunk has
+    /// been processed.
+    fn encode_trunk_state(
+        &self,
+        leaf_hashes: &[Hash],
+        parent_keys: Peekable<std::vec::IntoIter<Vec<u8>>>,
+        bytes: &mut Vec<u8>,
+    ) {
+        true.encode_into(bytes).unwrap();
+        (self.trunk_height.unwrap() as u64)
+            .encode_into(bytes)
+            .unwrap();
+
+        (leaf_hashes.len() as u32).encode_into(bytes).unwrap();
+        for hash in leaf_hashes {
+            hash.encode_into(bytes).unwrap();
+        }
+        (self.processed_leaves as u32).encode_into(bytes).unwrap();
+
+        (parent_keys.len() as u32).encode_into(bytes).unwrap();
+        for key in parent_keys {
+            (key.len() as u16).encode_into(bytes).unwrap();
+            bytes.extend_from_slice(&key);
+        }
+    }
+
+    /// Sets the number of entries the restored tree is expected to contain,
+    /// e.g. as stated by the peer or known from the original store. `finalize`
+    /// then returns an error if a different number of entries was restored.
+    pub fn expect_count(&mut self, count: u64) {
+        self.expected_count = Some(count);
+    }
+
     /// Verifies a chunk and writes it to the working RocksDB instance. Expects
     /// to be called for each chunk in order. Returns the number of remaining
     /// chunks.
@@ -73,6 +216,37 @@ impl Restorer {
         }
     }
 
+    /// Verifies a chunk like `process_chunk`, but also checks that `index` (as
+    /// numbered by `ChunkProducer`) is the index of the next chunk to be
+    /// processed, so a peer which sent a different chunk than requested can
+    /// be identified.
+    pub fn process_chunk_at(&mut self, index: usize, chunk_bytes: &[u8]) -> Result<usize> {
+        let expected_index = match self.remaining_chunks() {
+            None => 0,
+            Some(remaining) => self.stated_length - remaining,
+        };
+        if index != expected_index {
+            return Err(Error::ChunkProcessing(format!(
+                "Expected chunk {expected_index}, got chunk {index}"
+            )));
+        }
+
+        self.process_chunk(chunk_bytes)
+    }
+
+    /// Verifies a chunk like `process_chunk`, but decodes its operations from
+    /// `reader` as they are needed rather than requiring the whole chunk to be
+    /// in memory. The verified nodes are still collected before being written.
+    /// Returns the number of remaining chunks.
+    pub fn process_chunk_reader(&mut self, reader: impl Read) -> Result<usize> {
+        let ops = ReadDecoder::new(reader);
+
+        match self.leaf_hashes {
+            None => self.process_trunk(ops),
+            Some(_) => self.process_leaf(ops),
+        }
+    }
+
     /// Consumes the `Restorer` and returns the newly-created, fully-populated
     /// Merk instance. This method will return an error if called before
     /// processing all chunks (e.g. `restorer.remaining_chunks()` is not equal
@@ -84,6 +258,15 @@ impl Restorer {
             ));
         }
 
+        if let Some(expected_count) = self.expected_count {
+            if self.restored_count != expected_count {
+                return Err(Error::ChunkProcessing(format!(
+                    "Expected {} restored entries, but got {}",
+                    expected_count, self.restored_count
+                )));
+            }
+        }
+
         if self.trunk_height.unwrap() >= MIN_TRUNK_HEIGHT {
             self.rewrite_trunk_child_heights()?;
         }
@@ -98,13 +281,17 @@ impl Restorer {
     /// the first chunk is processed, this method will return `None` since we do
     /// not yet have enough information to know about the number of chunks.
     pub fn remaining_chunks(&self) -> Option<usize> {
-        self.leaf_hashes.as_ref().map(|lh| lh.len())
+        self.leaf_hashes
+            .as_ref()
+            .map(|lh| lh.len() - self.processed_leaves)
     }
 
     /// Writes the data contained in `tree` (extracted from a verified chunk
     /// proof) to the RocksDB.
     fn write_chunk(&mut self, tree: ProofTree) -> Result<()> {
         let mut batch = WriteBatch::default();
+        let tree_cf = self.merk.tree_cf();
+        let mut count = 0;
 
         tree.visit_refs(&mut |proof_node| {
             let (key, mut node) = match &proof_node.node {
@@ -120,10 +307,14 @@ impl Restorer {
             *node.slot_mut(false) = proof_node.right.as_ref().map(Child::as_link);
 
             let bytes = node.encode();
-            batch.put(key, bytes);
+            batch.put_cf(tree_cf, key, bytes);
+            count += 1;
         });
 
-        self.merk.write(batch)
+        self.merk.write(batch)?;
+        self.restored_count += count;
+
+        Ok(())
     }
 
     /// Verifies the trunk then writes its data to the RocksDB.
@@ -131,7 +322,7 @@ impl Restorer {
     /// The trunk contains a height proof which lets us verify the total number
     /// of expected chunks is the same as `stated_length` as passed into
     /// `Restorer::new()`. We also verify the expected root hash at this step.
-    fn process_trunk(&mut self, ops: Decoder) -> Result<usize> {
+    fn process_trunk<I: Iterator<Item = Result<Op>>>(&mut self, ops: I) -> Result<usize> {
         let (trunk, height) = verify_trunk(ops)?;
 
         if trunk.hash()? != self.expected_root_hash {
@@ -147,9 +338,7 @@ impl Restorer {
             let leaf_hashes = trunk
                 .layer(trunk_height)
                 .map(|node| node.hash())
-                .collect::<Result<Vec<_>>>()?
-                .into_iter()
-                .peekable();
+                .collect::<Result<Vec<_>>>()?;
             self.leaf_hashes = Some(leaf_hashes);
 
             let parent_keys = trunk
@@ -168,7 +357,7 @@ impl Restorer {
             assert_eq!(self.remaining_chunks_unchecked(), chunks_remaining);
             chunks_remaining
         } else {
-            self.leaf_hashes = Some(vec![].into_iter().peekable());
+            self.leaf_hashes = Some(vec![]);
             self.parent_keys = Some(vec![].into_iter().peekable());
             0
         };
@@ -187,18 +376,19 @@ impl Restorer {
 
     /// Verifies a leaf chunk then writes it to the RocksDB. This needs to be
     /// called in order, retrying the last chunk for any failed verifications.
-    fn process_leaf(&mut self, ops: Decoder) -> Result<usize> {
-        let leaf_hashes = self.leaf_hashes.as_mut().unwrap();
-        let leaf_hash = leaf_hashes
-            .peek()
-            .expect("Received more chunks than expected");
+    fn process_leaf<I: Iterator<Item = Result<Op>>>(&mut self, ops: I) -> Result<usize> {
+        let index = self.processed_leaves + 1;
+        let leaf_hashes = self.leaf_hashes.as_ref().unwrap();
+        assert!(
+            index <= leaf_hashes.len(),
+            "Received more chunks than expected"
+        );
 
-        let leaf = verify_leaf(ops, *leaf_hash)?;
+        let leaf = verify_chunk_at(ops, leaf_hashes, index)?;
         self.rewrite_parent_link(&leaf)?;
         self.write_chunk(leaf)?;
 
-        let leaf_hashes = self.leaf_hashes.as_mut().unwrap();
-        leaf_hashes.next();
+        self.processed_leaves += 1;
 
         Ok(self.remaining_chunks_unchecked())
     }
@@ -223,7 +413,9 @@ impl Restorer {
         };
 
         let parent_bytes = parent.encode();
-        self.merk.db.put(parent_key, parent_bytes)?;
+        self.merk
+            .db
+            .put_cf(self.merk.tree_cf(), parent_key, parent_bytes)?;
 
         if !is_left_child {
             let parent_keys = self.parent_keys.as_mut().unwrap();
@@ -238,6 +430,7 @@ impl Restorer {
             mut node: RefWalker<MerkSource>,
             remaining_depth: usize,
             batch: &mut WriteBatch,
+            tree_cf: &ColumnFamily,
         ) -> Result<(u8, u8)> {
             if remaining_depth == 0 {
                 return Ok(node.tree().child_heights());
@@ -247,17 +440,17 @@ impl Restorer {
                 Tree::decode(node.tree().key().to_vec(), node.tree().encode().as_slice());
 
             let left_child = node.walk(true)?.unwrap();
-            let left_child_heights = recurse(left_child, remaining_depth - 1, batch)?;
+            let left_child_heights = recurse(left_child, remaining_depth - 1, batch, tree_cf)?;
             let left_height = left_child_heights.0.max(left_child_heights.1) + 1;
             *cloned_node.link_mut(true).unwrap().child_heights_mut() = left_child_heights;
 
             let right_child = node.walk(false)?.unwrap();
-            let right_child_heights = recurse(right_child, remaining_depth - 1, batch)?;
+            let right_child_heights = recurse(right_child, remaining_depth - 1, batch, tree_cf)?;
             let right_height = right_child_heights.0.max(right_child_heights.1) + 1;
             *cloned_node.link_mut(false).unwrap().child_heights_mut() = right_child_heights;
 
             let bytes = cloned_node.encode();
-            batch.put(node.tree().key(), bytes);
+            batch.put_cf(tree_cf, node.tree().key(), bytes);
 
             Ok((left_height, right_height))
         }
@@ -271,7 +464,7 @@ impl Restorer {
         self.merk.use_tree_mut(|maybe_tree| {
             let tree = maybe_tree.unwrap();
             let walker = RefWalker::new(tree, self.merk.source());
-            recurse(walker, depth, &mut batch)
+            recurse(walker, depth, &mut batch, self.merk.tree_cf())
         })?;
 
         self.merk.write(batch)?;
@@ -283,7 +476,7 @@ impl Restorer {
     /// panic if called before processing the first chunk (since that chunk
     /// gives us the information to know how many chunks to expect).
     pub fn remaining_chunks_unchecked(&self) -> usize {
-        self.leaf_hashes.as_ref().unwrap().len()
+        self.leaf_hashes.as_ref().unwrap().len() - self.processed_leaves
     }
 }
 
@@ -337,9 +530,15 @@ mod tests {
     use super::*;
     use crate::test_utils::*;
     use crate::tree::{Batch, Op};
+    use std::io::Cursor;
     use std::path::PathBuf;
 
     fn restore_test(batches: &[&Batch], expected_nodes: usize) {
+        restore_test_with(batches, expected_nodes, false);
+        restore_test_with(batches, expected_nodes, true);
+    }
+
+    fn restore_test_with(batches: &[&Batch], expected_nodes: usize, use_reader: bool) {
         let mut original = TempMerk::new().unwrap();
         for batch in batches {
             original.apply(batch, &[]).unwrap();
@@ -354,13 +553,18 @@ mod tests {
         }
 
         let mut restorer = Merk::restore(&path, original.root_hash(), chunks.len()).unwrap();
+        restorer.expect_count(expected_nodes as u64);
 
         assert_eq!(restorer.remaining_chunks(), None);
 
         let mut expected_remaining = chunks.len();
         for chunk in chunks {
             let chunk = chunk.unwrap();
-            let remaining = restorer.process_chunk(chunk.as_slice()).unwrap();
+            let remaining = if use_reader {
+                restorer.process_chunk_reader(Cursor::new(chunk)).unwrap()
+            } else {
+                restorer.process_chunk(chunk.as_slice()).unwrap()
+            };
 
             expected_remaining -= 1;
             assert_eq!(remaining, expected_remaining);
@@ -375,6 +579,99 @@ mod tests {
         std::fs::remove_dir_all(&path).unwrap();
     }
 
+    #[test]
+    fn resume_restore() {
+        let mut original = TempMerk::new().unwrap();
+        original.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+        original.flush().unwrap();
+
+        let chunks = original
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+
+        let path: PathBuf = std::thread::current().name().unwrap().into();
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+
+        // resume before processing the trunk
+        let mut restorer = Merk::restore(&path, original.root_hash(), chunks.len()).unwrap();
+        restorer.expect_count(10_000);
+        let state = restorer.save_state();
+        drop(restorer);
+        let mut restorer =
+            Restorer::resume_from_state(&path, original.root_hash(), &state).unwrap();
+        assert_eq!(restorer.remaining_chunks(), None);
+
+        let half = chunks.len() / 2;
+        for chunk in &chunks[..half] {
+            restorer.process_chunk(chunk).unwrap();
+        }
+        let state = restorer.save_state();
+        drop(restorer);
+
+        assert!(Restorer::resume_from_state(&path, [1; 32], &state).is_err());
+        let mut restorer =
+            Restorer::resume_from_state(&path, original.root_hash(), &state).unwrap();
+        assert_eq!(restorer.remaining_chunks(), Some(chunks.len() - half));
+
+        // chunks must still be processed in order
+        assert!(restorer.process_chunk(&chunks[half + 1]).is_err());
+        assert!(restorer
+            .process_chunk_at(half + 1, &chunks[half + 1])
+            .is_err());
+        let err = restorer
+            .process_chunk_at(half, &chunks[half + 1])
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("Chunk {half} did not match")));
+
+        for chunk in &chunks[half..] {
+            restorer.process_chunk(chunk).unwrap();
+        }
+
+        let restored = restorer.finalize().unwrap();
+        assert_eq!(restored.root_hash(), original.root_hash());
+        assert_raw_db_entries_eq(&restored, &original, 10_000);
+
+        drop(restored);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn restore_wrong_count() {
+        let mut original = TempMerk::new().unwrap();
+        original.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+        original.flush().unwrap();
+
+        let path: PathBuf = std::thread::current().name().unwrap().into();
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+
+        let chunks = original.chunks().unwrap();
+        let mut restorer = Merk::restore(&path, original.root_hash(), chunks.len()).unwrap();
+        restorer.expect_count(10_001);
+        for chunk in chunks {
+            restorer.process_chunk(chunk.unwrap().as_slice()).unwrap();
+        }
+
+        let err = restorer
+            .finalize()
+            .err()
+            .expect("expected finalize to fail");
+        assert_eq!(
+            err.to_string(),
+            "Chunk Processing Error: Expected 10001 restored entries, but got 10000"
+        );
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
     #[test]
     fn restore_10000() {
         restore_test(&[&make_batch_seq(0..10_000)], 10_000);
@@ -7,10 +7,12 @@
 
 use std::cell::Cell;
 
+use rocksdb::ColumnFamily;
+
 use crate::{
-    proofs::query::QueryItem,
-    tree::{Fetch, RefWalker, Tree, NULL_HASH},
-    Hash, Result,
+    proofs::query::{Query, QueryItem},
+    tree::{Fetch, HashAlgorithm, RefWalker, Tree, NULL_HASH},
+    Error, Hash, Result,
 };
 
 /// A read-only view of the database state at a particular point in time.
@@ -27,6 +29,9 @@ pub struct Snapshot<'a> {
     /// Whether the underlying RocksDB snapshot should be dropped when the
     /// `Snapshot` is dropped.
     should_drop_ss: bool,
+    /// The column family containing the tree nodes, along with its name, or
+    /// `None` if the nodes are in the default column family.
+    cf: Option<(&'a ColumnFamily, String)>,
 }
 
 impl<'a> Snapshot<'a> {
@@ -38,9 +43,17 @@ impl<'a> Snapshot<'a> {
             ss: Some(db),
             tree: Cell::new(tree),
             should_drop_ss: true,
+            cf: None,
         }
     }
 
+    /// Reads the tree nodes from the given column family (with the given name)
+    /// instead of the default column family.
+    pub(crate) fn with_cf(mut self, cf: &'a ColumnFamily, name: String) -> Self {
+        self.cf = Some((cf, name));
+        self
+    }
+
     /// Converts the [Snapshot] into a [StaticSnapshot], an alternative which
     /// has easier (but more dangerous) lifetime requirements.
     pub fn staticize(mut self) -> StaticSnapshot {
@@ -49,6 +62,7 @@ impl<'a> Snapshot<'a> {
             tree: Cell::new(self.tree.take()),
             inner: ss.inner,
             should_drop: false,
+            cf_name: self.cf.take().map(|(_, name)| name),
         }
     }
 
@@ -74,7 +88,25 @@ impl<'a> Snapshot<'a> {
         Q: Into<QueryItem>,
         I: IntoIterator<Item = Q>,
     {
-        self.use_tree_mut(move |maybe_tree| super::prove(maybe_tree, self.source(), query))
+        self.use_tree_mut(move |maybe_tree| {
+            super::prove(maybe_tree, self.source(), query, None, None, true)
+        })
+    }
+
+    /// Proves the given `Query` against the tree at the time the snapshot was
+    /// created, applying the query's offset and limit.
+    pub fn prove_query(&self, query: Query) -> Result<Vec<u8>> {
+        let (limit, offset, left_to_right) = (query.limit(), query.offset(), query.left_to_right());
+        self.use_tree_mut(move |maybe_tree| {
+            super::prove(
+                maybe_tree,
+                self.source(),
+                query,
+                limit,
+                offset,
+                left_to_right,
+            )
+        })
     }
 
     /// Walks the tree at the time the snapshot was created, fetching the child
@@ -92,17 +124,28 @@ impl<'a> Snapshot<'a> {
     /// Returns an iterator over the keys and values in the backing store from
     /// the time the snapshot was created.
     pub fn raw_iter(&self) -> rocksdb::DBRawIterator {
-        self.ss.as_ref().unwrap().raw_iterator()
+        let ss = self.ss.as_ref().unwrap();
+        match self.cf {
+            Some((cf, _)) => ss.raw_iterator_cf(cf),
+            None => ss.raw_iterator(),
+        }
     }
 
     /// A data source which can be used to fetch values from the backing store,
     /// from the time the snapshot was created.
     fn source(&self) -> SnapshotSource {
-        SnapshotSource(self.ss.as_ref().unwrap())
+        let hash_algorithm = self.use_tree(|maybe_tree| {
+            maybe_tree.map_or_else(Default::default, |tree| tree.hash_algorithm())
+        });
+        SnapshotSource(
+            self.ss.as_ref().unwrap(),
+            self.cf.as_ref().map(|(cf, _)| *cf),
+            hash_algorithm,
+        )
     }
 
     /// Uses the tree, and then puts it back.
-    fn use_tree<T>(&self, f: impl FnOnce(Option<&Tree>) -> T) -> T {
+    pub(crate) fn use_tree<T>(&self, f: impl FnOnce(Option<&Tree>) -> T) -> T {
         let tree = self.tree.take();
         let res = f(tree.as_ref());
         self.tree.set(tree);
@@ -131,14 +174,29 @@ impl<'a> Drop for Snapshot<'a> {
 ///
 /// This implements [Fetch] and should be used with a type such as [RefWalker].
 #[derive(Clone)]
-pub struct SnapshotSource<'a>(&'a rocksdb::Snapshot<'a>);
+pub struct SnapshotSource<'a>(
+    &'a rocksdb::Snapshot<'a>,
+    Option<&'a ColumnFamily>,
+    HashAlgorithm,
+);
 
 impl<'a> Fetch for SnapshotSource<'a> {
     fn fetch_by_key(&self, key: &[u8]) -> Result<Option<Tree>> {
-        Ok(self
-            .0
-            .get(key)?
-            .map(|bytes| Tree::decode(key.to_vec(), &bytes)))
+        let bytes = match self.1 {
+            Some(cf) => self.0.get_cf(cf, key)?,
+            None => self.0.get(key)?,
+        };
+        bytes
+            .map(|bytes| {
+                Tree::try_decode(key.to_vec(), &bytes)
+                    .map(|tree| tree.with_hash_algorithm(self.2))
+                    .map_err(|err| Error::Corruption(key.to_vec(), err.to_string()))
+            })
+            .transpose()
+    }
+
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        self.2
     }
 }
 
@@ -163,6 +221,9 @@ pub struct StaticSnapshot {
     /// Used to detect whether the `StaticSnapshot` was set to manually drop
     /// before its [Drop::drop] implementation was called.
     pub should_drop: bool,
+    /// The name of the column family containing the tree nodes, or `None` if
+    /// the nodes are in the default column family.
+    cf_name: Option<String>,
 }
 
 /// An equivalent struct to the [rocksdb::Snapshot] struct within the `rocksdb`
@@ -208,6 +269,10 @@ impl StaticSnapshot {
             ss: Some(db_ss),
             tree: self.clone_tree(),
             should_drop_ss: false,
+            cf: self
+                .cf_name
+                .as_ref()
+                .map(|name| (db.cf_handle(name).unwrap(), name.clone())),
         }
     }
 
@@ -229,12 +294,12 @@ impl StaticSnapshot {
 
     /// Clones the root node of the Merk tree into a new [Tree].
     fn clone_tree(&self) -> Cell<Option<Tree>> {
-        let tree = self.tree.take().unwrap();
-        let tree_clone = Cell::new(Some(Tree::decode(
-            tree.key().to_vec(),
-            tree.encode().as_slice(),
-        )));
-        self.tree.set(Some(tree));
+        let tree = self.tree.take();
+        let tree_clone = Cell::new(tree.as_ref().map(|tree| {
+            Tree::decode(tree.key().to_vec(), tree.encode().as_slice())
+                .with_hash_algorithm(tree.hash_algorithm())
+        }));
+        self.tree.set(tree);
         tree_clone
     }
 }
@@ -253,6 +318,7 @@ impl Clone for StaticSnapshot {
             tree: self.clone_tree(),
             inner: self.inner,
             should_drop: self.should_drop,
+            cf_name: self.cf_name.clone(),
         }
     }
 }
@@ -262,7 +328,8 @@ mod tests {
     use std::mem::transmute;
 
     use super::RocksDBSnapshot;
-    use crate::test_utils::TempMerk;
+    use crate::proofs::{query::verify, Query};
+    use crate::test_utils::*;
 
     #[test]
     fn rocksdb_snapshot_struct_format() {
@@ -277,4 +344,34 @@ mod tests {
 
         assert_eq!(exptected_db_ptr, db_ptr);
     }
+
+    #[test]
+    fn prove_after_mutation() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        let old_hash = merk.root_hash();
+
+        let ss = merk.snapshot().unwrap().staticize();
+
+        merk.apply(&make_del_batch_seq(0..10), &[]).unwrap();
+        merk.apply(&make_batch_seq(100..150), &[]).unwrap();
+        assert_ne!(merk.root_hash(), old_hash);
+
+        let snapshot = unsafe { ss.with_db(merk.db()) };
+        assert_eq!(snapshot.root_hash(), old_hash);
+
+        let mut query = Query::new();
+        query.insert_key(seq_key(5));
+        query.insert_range(seq_key(90)..seq_key(120));
+        let proof = snapshot.prove(query).unwrap();
+
+        assert!(verify(&proof, merk.root_hash()).is_err());
+        let map = verify(&proof, old_hash).unwrap();
+        assert_eq!(map.get(&seq_key(5)).unwrap(), Some(&put_entry_value()[..]));
+        assert_eq!(map.get(&seq_key(99)).unwrap(), Some(&put_entry_value()[..]));
+        assert_eq!(map.get(&seq_key(100)).unwrap(), None);
+
+        drop(snapshot);
+        unsafe { ss.drop(merk.db()) };
+    }
 }
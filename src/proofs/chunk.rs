@@ -1,14 +1,10 @@
 #[cfg(feature = "full")]
-use {
-    super::tree::{execute, Tree as ProofTree},
-    crate::tree::Hash,
-    crate::tree::Tree,
-    rocksdb::DBRawIterator,
-};
-
-use super::{Node, Op};
+use {crate::tree::Tree, rocksdb::DBRawIterator};
+
+use super::tree::{execute, Tree as ProofTree};
+use super::{Decoder, Node, Op};
 use crate::error::{Error, Result};
-use crate::tree::{Fetch, RefWalker};
+use crate::tree::{Fetch, Hash, RefWalker};
 
 /// The minimum number of layers the trunk will be guaranteed to have before
 /// splitting into multiple chunks.
@@ -43,6 +39,16 @@ where
         }
     }
 
+    /// Generates a proof of the top `depth` levels of the tree, containing KV
+    /// nodes for all nodes above `depth` and Hash nodes for the nodes at
+    /// `depth` (a depth of 0 only contains the root hash). Unlike the trunk
+    /// chunk, this does not include a proof of the tree's height.
+    pub fn create_depth_proof(&mut self, depth: usize) -> Result<Vec<Op>> {
+        let mut proof = Vec::with_capacity(2usize.pow(depth.min(16) as u32) * 3);
+        self.traverse_for_trunk(&mut proof, depth, false)?;
+        Ok(proof)
+    }
+
     /// Traverses down the left edge of the tree and pushes ops to the proof, to
     /// act as a proof of the height of the tree. This is the first step in
     /// generating a trunk proof.
@@ -190,6 +196,37 @@ pub(crate) fn verify_leaf<I: Iterator<Item = Result<Op>>>(
     Ok(tree)
 }
 
+/// Verifies the leaf chunk with the given chunk index (as numbered by
+/// `ChunkProducer`, where index 0 is the trunk) against the verified trunk's
+/// boundary hashes: the hashes of the trunk's nodes at the trunk height, in key
+/// order, as returned by `ProofTree::layer`. The expected hash is looked up by
+/// the index, so a chunk is only accepted at its own position. Errors identify
+/// the chunk which failed so a peer which sent the wrong chunk can be
+/// detected.
+#[cfg(feature = "full")]
+pub fn verify_chunk_at<I: Iterator<Item = Result<Op>>>(
+    ops: I,
+    boundary_hashes: &[Hash],
+    index: usize,
+) -> Result<ProofTree> {
+    let expected_hash = match index.checked_sub(1).and_then(|i| boundary_hashes.get(i)) {
+        Some(hash) => *hash,
+        None => {
+            return Err(Error::ChunkProcessing(format!(
+                "Chunk {index} is not a leaf chunk, expected an index from 1 to {}",
+                boundary_hashes.len()
+            )))
+        }
+    };
+
+    verify_leaf(ops, expected_hash).map_err(|err| match err {
+        Error::HashMismatch(expected, actual) => Error::ChunkProcessing(format!(
+            "Chunk {index} did not match expected hash\n\tExpected: {expected:?}\n\tActual: {actual:?}"
+        )),
+        err => err,
+    })
+}
+
 /// Verifies a trunk chunk proof by executing its operators. Ensures the
 /// resulting tree contains a valid height proof, the trunk is the correct
 /// height, and all of its inner nodes are not abridged. Returns the tree and
@@ -266,6 +303,45 @@ pub(crate) fn verify_trunk<I: Iterator<Item = Result<Op>>>(ops: I) -> Result<(Pr
     Ok((tree, height))
 }
 
+/// Verifies a proof created by `Merk::prove_trunk` against the expected root
+/// hash. Checks that every node above `depth` contains its key and value and
+/// that the proof does not extend below `depth`, then returns the tree
+/// reconstructed from the proof.
+pub fn verify_trunk_depth(bytes: &[u8], expected_hash: Hash, depth: usize) -> Result<ProofTree> {
+    fn verify_depth(tree: &ProofTree, remaining_depth: usize) -> Result<()> {
+        if remaining_depth == 0 {
+            return match tree.node {
+                Node::Hash(_) => Ok(()),
+                _ => Err(Error::UnexpectedNode(
+                    "Expected nodes at trunk depth to be Hash nodes".into(),
+                )),
+            };
+        }
+
+        if !matches!(tree.node, Node::KV(_, _)) {
+            return Err(Error::UnexpectedNode(
+                "Expected nodes above trunk depth to contain keys and values".into(),
+            ));
+        }
+        for child in tree.left.iter().chain(tree.right.iter()) {
+            verify_depth(&child.tree, remaining_depth - 1)?;
+        }
+
+        Ok(())
+    }
+
+    let tree = execute(Decoder::new(bytes), false, |_| Ok(()))?;
+
+    let root_hash = tree.hash()?;
+    if root_hash != expected_hash {
+        return Err(Error::HashMismatch(expected_hash, root_hash));
+    }
+
+    verify_depth(&tree, depth)?;
+
+    Ok(tree)
+}
+
 #[cfg(test)]
 mod tests {
     use std::usize;
@@ -289,7 +365,7 @@ mod tests {
             match node {
                 Node::Hash(_) => counts.hash += 1,
                 Node::KVHash(_) => counts.kvhash += 1,
-                Node::KV(_, _) => counts.kv += 1,
+                Node::KV(_, _) | Node::KVDigest(_, _) => counts.kv += 1,
             };
         });
 
@@ -441,8 +517,8 @@ mod tests {
         let chunk = verify_leaf(
             ops,
             [
-                222, 93, 128, 149, 117, 136, 34, 175, 204, 82, 228, 113, 242, 144, 152, 190, 210,
-                27, 195, 34, 24, 196, 210, 99, 250, 119, 219, 114, 52, 167, 191, 249,
+                34, 244, 155, 200, 204, 63, 238, 96, 88, 239, 90, 160, 190, 143, 35, 9, 246, 10,
+                110, 111, 167, 174, 153, 244, 104, 137, 134, 235, 108, 205, 46, 184,
             ],
         )
         .unwrap();
@@ -457,8 +533,8 @@ mod tests {
         let chunk = verify_leaf(
             ops,
             [
-                128, 158, 92, 80, 118, 253, 48, 241, 74, 154, 213, 187, 92, 243, 154, 28, 164, 235,
-                156, 122, 174, 226, 84, 170, 233, 166, 27, 79, 100, 10, 88, 184,
+                57, 131, 97, 227, 158, 152, 189, 213, 134, 85, 118, 166, 241, 51, 211, 250, 44,
+                148, 96, 126, 121, 205, 230, 112, 83, 69, 45, 164, 142, 165, 204, 249,
             ],
         )
         .unwrap();
@@ -467,4 +543,47 @@ mod tests {
         assert_eq!(counts.hash, 0);
         assert_eq!(counts.kvhash, 0);
     }
+
+    #[test]
+    fn verify_chunk_at_mismatch() {
+        let mut merk = TempMerk::new().unwrap();
+        let batch = make_batch_seq(0..10_000);
+        merk.apply(batch.as_slice(), &[]).unwrap();
+
+        let chunks = merk
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+
+        let ops = super::super::Decoder::new(chunks[0].as_slice());
+        let (trunk, height) = verify_trunk(ops).unwrap();
+        let boundary_hashes = trunk
+            .layer(height / 2)
+            .map(|node| node.hash().unwrap())
+            .collect::<Vec<_>>();
+
+        let ops = super::super::Decoder::new(chunks[3].as_slice());
+        verify_chunk_at(ops, &boundary_hashes, 3).unwrap();
+
+        // chunk 5 sent in place of chunk 3
+        let ops = super::super::Decoder::new(chunks[5].as_slice());
+        let err = verify_chunk_at(ops, &boundary_hashes, 3).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Chunk 3 did not match expected hash"));
+
+        // chunk 3 claimed to be chunk 5
+        let ops = super::super::Decoder::new(chunks[3].as_slice());
+        let err = verify_chunk_at(ops, &boundary_hashes, 5).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Chunk 5 did not match expected hash"));
+
+        for index in [0, boundary_hashes.len() + 1] {
+            let ops = super::super::Decoder::new(chunks[3].as_slice());
+            assert!(verify_chunk_at(ops, &boundary_hashes, index).is_err());
+        }
+    }
 }
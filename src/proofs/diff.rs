@@ -0,0 +1,207 @@
+//! Incremental proofs, for updating the data proven by an earlier proof after
+//! the tree has changed without re-sending values which did not change.
+//!
+//! A diff proof is a regular proof in which `Push(KV)` operators for entries
+//! whose values are unchanged since the old state are replaced by a reference
+//! to the entry's key. The verifier fills in the values from the `Map` it got
+//! from verifying the old proof, then verifies the result against the new root
+//! hash as usual, so a diff proof is exactly as trustworthy as a full proof.
+
+#[cfg(feature = "full")]
+use {
+    super::encoding::encode_into,
+    crate::{proofs::query::Query, Merk},
+};
+
+use super::query::{Map, MapBuilder};
+use super::tree::execute;
+use super::{Node, Op};
+use crate::error::{Error, Result};
+use crate::tree::Hash;
+
+/// The variant byte of an operator which pushes a `Node::KV` with a value
+/// taken from the verifier's old `Map`. This is not a valid proof operator
+/// variant, so it can not be confused with one.
+const UNCHANGED_KV_VARIANT: u8 = 0x20;
+
+/// Creates a diff proof of `query` against the current state of `new_merk`,
+/// relative to the state with root hash `old_hash`. Verify it with
+/// `apply_diff`, passing the `Map` from verifying a proof of the same query
+/// against `old_hash`.
+///
+/// The old state must either be the current state or have been kept with
+/// `Merk::snapshot_named`, otherwise an error is returned.
+#[cfg(feature = "full")]
+pub fn diff_proof(old_hash: Hash, new_merk: &Merk, query: &Query) -> Result<Vec<u8>> {
+    let old_snapshot = if old_hash == new_merk.root_hash() {
+        None
+    } else {
+        let snapshot = new_merk
+            .named_snapshot_by_root_hash(old_hash)
+            .ok_or_else(|| Error::Proof(format!("No snapshot with root hash {old_hash:?}")))?;
+        Some(snapshot)
+    };
+
+    let mut bytes = Vec::with_capacity(128);
+    for op in new_merk.query_proof_ops(query)? {
+        if let Op::Push(Node::KV(key, value)) = &op {
+            let unchanged = match &old_snapshot {
+                None => true,
+                Some(snapshot) => snapshot.get(key)?.as_ref() == Some(value),
+            };
+            if unchanged {
+                bytes.extend_from_slice(&[UNCHANGED_KV_VARIANT, key.len() as u8]);
+                bytes.extend_from_slice(key);
+                continue;
+            }
+        }
+
+        encode_into(std::iter::once(&op), &mut bytes);
+    }
+
+    Ok(bytes)
+}
+
+/// Verifies a diff proof created by `diff_proof` against the new root hash,
+/// returning the data it proves. Values of unchanged entries are taken from
+/// `old_map`, which must contain every such entry (e.g. because it was
+/// verified from a proof of the same query against the old root hash).
+pub fn apply_diff(old_map: &Map, diff: &[u8], new_hash: Hash) -> Result<Map> {
+    let ops = DiffDecoder {
+        bytes: diff,
+        old_map,
+    };
+    let mut map_builder = MapBuilder::new();
+
+    let root = execute(ops, true, |node| map_builder.insert(node))?;
+
+    if root.hash()? != new_hash {
+        return Err(Error::HashMismatch(new_hash, root.hash()?));
+    }
+
+    Ok(map_builder.build())
+}
+
+/// Decodes the operators of a diff proof, resolving references to unchanged
+/// entries using the old `Map`.
+struct DiffDecoder<'a> {
+    bytes: &'a [u8],
+    old_map: &'a Map,
+}
+
+impl<'a> DiffDecoder<'a> {
+    fn decode_unchanged_kv(&mut self) -> Result<Op> {
+        let key_length = *self
+            .bytes
+            .get(1)
+            .ok_or_else(|| Error::Proof("Unexpected end of diff proof".into()))?
+            as usize;
+        let key = self
+            .bytes
+            .get(2..2 + key_length)
+            .ok_or_else(|| Error::Proof("Unexpected end of diff proof".into()))?;
+        self.bytes = &self.bytes[2 + key_length..];
+
+        let value = self.old_map.get(key)?.ok_or_else(|| {
+            Error::Proof(format!(
+                "Diff proof references key {key:?} which is absent from the old map"
+            ))
+        })?;
+
+        Ok(Op::Push(Node::KV(key.to_vec(), value.to_vec())))
+    }
+}
+
+impl<'a> Iterator for DiffDecoder<'a> {
+    type Item = Result<Op>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let variant = *self.bytes.first()?;
+
+        if variant == UNCHANGED_KV_VARIANT {
+            return Some(self.decode_unchanged_kv());
+        }
+
+        Some(Op::decode(self.bytes).inspect(|op| self.bytes = &self.bytes[op.encoding_length()..]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::proofs::query::verify;
+    use crate::test_utils::*;
+    use crate::Op as BatchOp;
+
+    fn make_query() -> Query {
+        let mut query = Query::new();
+        query.insert_range(seq_key(20)..seq_key(40));
+        query
+    }
+
+    #[test]
+    fn diff_single_leaf() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+        merk.snapshot_named(b"old").expect("snapshot failed");
+
+        let old_hash = merk.root_hash();
+        let old_proof = merk.prove_query(make_query()).expect("prove failed");
+        let old_map = verify(&old_proof, old_hash).expect("verify failed");
+
+        // find a leaf in the queried range
+        let key = merk.walk(|maybe_walker| {
+            let walker = maybe_walker.expect("expected tree");
+            let mut node = walker.tree();
+            while let Some(child) = node.child(seq_key(30).as_slice() < node.key()) {
+                node = child;
+            }
+            assert!(node.link(true).is_none() && node.link(false).is_none());
+            node.key().to_vec()
+        });
+        assert!(make_query().contains_key(&key));
+        merk.apply(&[(key.clone(), BatchOp::Put(vec![1, 2, 3]))], &[])
+            .expect("apply failed");
+        let new_hash = merk.root_hash();
+
+        let diff = diff_proof(old_hash, &merk, &make_query()).expect("diff failed");
+        let full_proof = merk.prove_query(make_query()).expect("prove failed");
+        // each unchanged entry omits its value and the value's length
+        let unchanged = 19 * (2 + put_entry_value().len());
+        assert_eq!(diff.len(), full_proof.len() - unchanged);
+
+        let new_map = apply_diff(&old_map, &diff, new_hash).expect("apply_diff failed");
+        let expected = verify(&full_proof, new_hash).expect("verify failed");
+        assert_eq!(new_map.get(&key).unwrap(), Some(&[1, 2, 3][..]));
+        let (start, end) = (seq_key(20), seq_key(40));
+        let range = || start.as_slice()..end.as_slice();
+        assert!(new_map
+            .range(range())
+            .map(|entry| entry.unwrap())
+            .eq(expected.range(range()).map(|entry| entry.unwrap())));
+
+        // the diff can only be applied on top of the old data
+        assert!(apply_diff(&old_map, &diff, old_hash).is_err());
+        let empty_map = verify(&merk.prove_query(Query::new()).unwrap(), new_hash);
+        assert!(apply_diff(&empty_map.unwrap(), &diff, new_hash).is_err());
+
+        // unknown old states are rejected
+        assert!(diff_proof([1; 32], &merk, &make_query()).is_err());
+    }
+
+    #[test]
+    fn diff_unchanged() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+
+        let hash = merk.root_hash();
+        let proof = merk.prove_query(make_query()).expect("prove failed");
+        let old_map = verify(&proof, hash).expect("verify failed");
+
+        let diff = diff_proof(hash, &merk, &make_query()).expect("diff failed");
+        let map = apply_diff(&old_map, &diff, hash).expect("apply_diff failed");
+        assert_eq!(map.get(&seq_key(30)).unwrap(), Some(&put_entry_value()[..]));
+    }
+}
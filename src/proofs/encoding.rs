@@ -1,4 +1,4 @@
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 
 use ed::{Decode, Encode, Terminated};
 
@@ -26,6 +26,13 @@ impl Encode for Op {
                 (value.len() as u16).encode_into(dest)?;
                 dest.write_all(value)?;
             }
+            Op::Push(Node::KVDigest(key, value_hash)) => {
+                debug_assert!(key.len() < 256);
+
+                dest.write_all(&[0x04, key.len() as u8])?;
+                dest.write_all(key)?;
+                dest.write_all(value_hash)?;
+            }
             Op::Parent => dest.write_all(&[0x10])?,
             Op::Child => dest.write_all(&[0x11])?,
         };
@@ -37,6 +44,7 @@ impl Encode for Op {
             Op::Push(Node::Hash(_)) => 1 + HASH_LENGTH,
             Op::Push(Node::KVHash(_)) => 1 + HASH_LENGTH,
             Op::Push(Node::KV(key, value)) => 4 + key.len() + value.len(),
+            Op::Push(Node::KVDigest(key, _)) => 2 + key.len() + HASH_LENGTH,
             Op::Parent => 1,
             Op::Child => 1,
         })
@@ -69,6 +77,16 @@ impl Decode for Op {
 
                 Op::Push(Node::KV(key, value))
             }
+            0x04 => {
+                let key_len: u8 = Decode::decode(&mut input)?;
+                let mut key = vec![0; key_len as usize];
+                input.read_exact(key.as_mut_slice())?;
+
+                let mut value_hash = [0; HASH_LENGTH];
+                input.read_exact(&mut value_hash)?;
+
+                Op::Push(Node::KVDigest(key, value_hash))
+            }
             0x10 => Op::Parent,
             0x11 => Op::Child,
             byte => {
@@ -85,7 +103,7 @@ impl Op {
         Ok(Encode::encode_into(self, dest)?)
     }
 
-    fn encoding_length(&self) -> usize {
+    pub(crate) fn encoding_length(&self) -> usize {
         Encode::encoding_length(self).unwrap()
     }
 
@@ -100,6 +118,12 @@ pub fn encode_into<'a, T: Iterator<Item = &'a Op>>(ops: T, output: &mut Vec<u8>)
     }
 }
 
+/// Decodes all of the operations in a proof, without verifying it. Useful for
+/// inspecting a proof, e.g. printing its operations for debugging.
+pub fn decode_ops(bytes: &[u8]) -> Result<Vec<Op>> {
+    Decoder::new(bytes).collect()
+}
+
 pub struct Decoder<'a> {
     offset: usize,
     bytes: &'a [u8],
@@ -131,9 +155,43 @@ impl<'a> Iterator for Decoder<'a> {
     }
 }
 
+/// Decodes proof operations from a reader one at a time, so the proof does not
+/// need to be held in memory all at once.
+pub struct ReadDecoder<R: Read> {
+    input: R,
+}
+
+impl<R: Read> ReadDecoder<R> {
+    pub fn new(input: R) -> Self {
+        ReadDecoder { input }
+    }
+}
+
+impl<R: Read> Iterator for ReadDecoder<R> {
+    type Item = Result<Op>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // read the variant byte first, so we can stop cleanly at the end of
+        // the input
+        let mut variant = [0];
+        loop {
+            match self.input.read(&mut variant) {
+                Ok(0) => return None,
+                Ok(_) => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+
+        let input = (&variant[..]).chain(&mut self.input);
+        Some(Decode::decode(input).map_err(Into::into))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::super::{Node, Op};
+    use super::{decode_ops, encode_into, ReadDecoder};
     use crate::tree::HASH_LENGTH;
 
     #[test]
@@ -180,6 +238,19 @@ mod test {
         assert_eq!(bytes, vec![0x03, 3, 1, 2, 3, 0, 3, 4, 5, 6]);
     }
 
+    #[test]
+    fn encode_push_kvdigest() {
+        let op = Op::Push(Node::KVDigest(vec![1, 2, 3], [123; HASH_LENGTH]));
+        assert_eq!(op.encoding_length(), 37);
+
+        let mut bytes = vec![];
+        op.encode_into(&mut bytes).unwrap();
+        assert_eq!(&bytes[..5], &[0x04, 3, 1, 2, 3]);
+        assert_eq!(&bytes[5..], &[123; HASH_LENGTH]);
+
+        assert_eq!(Op::decode(&bytes[..]).expect("decode failed"), op);
+    }
+
     #[test]
     fn encode_parent() {
         let op = Op::Parent;
@@ -254,4 +325,49 @@ mod test {
         let bytes = [0x88];
         assert!(Op::decode(&bytes[..]).is_err());
     }
+
+    #[test]
+    fn decode_ops_proof() {
+        // a proof of key [5] in a tree with keys [3], [5] and [7]
+        let bytes = [
+            vec![0x03, 1, 3, 0, 1, 3],
+            vec![0x03, 1, 5, 0, 1, 5],
+            vec![0x10],
+            vec![0x01],
+            vec![7; HASH_LENGTH],
+            vec![0x11],
+        ]
+        .concat();
+
+        let ops = decode_ops(&bytes).unwrap();
+        assert_eq!(ops.len(), 5);
+        assert_eq!(ops[0], Op::Push(Node::KV(vec![3], vec![3])));
+        assert_eq!(ops[4], Op::Child);
+
+        let mut encoded = vec![];
+        encode_into(ops.iter(), &mut encoded);
+        assert_eq!(encoded, bytes);
+
+        assert!(decode_ops(&bytes[..bytes.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn read_decoder() {
+        let ops = vec![
+            Op::Push(Node::KV(vec![1, 2, 3], vec![4, 5, 6])),
+            Op::Push(Node::Hash([123; HASH_LENGTH])),
+            Op::Child,
+        ];
+        let mut bytes = vec![];
+        encode_into(ops.iter(), &mut bytes);
+
+        let decoded = ReadDecoder::new(bytes.as_slice())
+            .collect::<crate::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(decoded, ops);
+
+        // truncated input
+        let mut decoder = ReadDecoder::new(&bytes[..5]);
+        assert!(decoder.next().unwrap().is_err());
+    }
 }
@@ -0,0 +1,159 @@
+#[cfg(feature = "full")]
+use {
+    super::Op,
+    crate::tree::{Fetch, RefWalker},
+};
+
+use super::tree::execute;
+use super::{Decoder, Node};
+use crate::error::{Error, Result};
+use crate::tree::{Hash, NULL_HASH};
+
+#[cfg(feature = "full")]
+impl<'a, S> RefWalker<'a, S>
+where
+    S: Fetch + Sized + Send + Clone,
+{
+    /// Generates a proof of the number of nodes in the tree by traversing the
+    /// entire tree. Every node is included as a `Node::KVHash`, so the proof
+    /// reveals the structure of the tree but none of its keys or values.
+    pub fn create_len_proof(&mut self) -> Result<Vec<Op>> {
+        let mut proof = Vec::with_capacity(128);
+        self.traverse_for_len(&mut proof)?;
+        Ok(proof)
+    }
+
+    /// Traverses down the tree and adds KV hash push ops for all nodes.
+    fn traverse_for_len(&mut self, proof: &mut Vec<Op>) -> Result<()> {
+        // traverse left
+        let has_left_child = self.tree().link(true).is_some();
+        if has_left_child {
+            let mut left = self.walk(true)?.unwrap();
+            left.traverse_for_len(proof)?;
+        }
+
+        // add this node's kv hash
+        proof.push(Op::Push(self.to_kvhash_node()));
+
+        if has_left_child {
+            proof.push(Op::Parent);
+        }
+
+        // traverse right
+        if let Some(mut right) = self.walk(false)? {
+            right.traverse_for_len(proof)?;
+            proof.push(Op::Child);
+        }
+
+        Ok(())
+    }
+}
+
+/// Verifies an encoded proof created by `Merk::len_proof` against the expected
+/// root hash, and returns the number of entries in the tree.
+///
+/// The proof must include every node of the tree, so proofs containing
+/// abridged subtrees (`Node::Hash`) are rejected. An empty proof is valid for
+/// an empty tree.
+pub fn verify_len(bytes: &[u8], expected_hash: Hash) -> Result<u64> {
+    if bytes.is_empty() {
+        if expected_hash != NULL_HASH {
+            return Err(Error::HashMismatch(expected_hash, NULL_HASH));
+        }
+
+        return Ok(0);
+    }
+
+    let ops = Decoder::new(bytes);
+    let mut count = 0;
+
+    let root = execute(ops, true, |node| {
+        match node {
+            Node::Hash(_) => {
+                return Err(Error::Proof(
+                    "Length proof must not contain abridged subtrees".into(),
+                ))
+            }
+            Node::KVHash(_) | Node::KV(_, _) | Node::KVDigest(_, _) => count += 1,
+        }
+
+        Ok(())
+    })?;
+
+    if root.hash()? != expected_hash {
+        return Err(Error::HashMismatch(expected_hash, root.hash()?));
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::encoding::encode_into;
+    use super::*;
+    use crate::test_utils::*;
+    use crate::tree::{PanicSource, RefWalker};
+
+    #[test]
+    fn len_proof_10k() {
+        let mut tree = make_tree_seq(10_000);
+        let root_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let proof = walker.create_len_proof().unwrap();
+        assert!(proof
+            .iter()
+            .all(|op| !matches!(op, Op::Push(Node::Hash(_)) | Op::Push(Node::KV(..)))));
+
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+        // includes the initial node created by `make_tree_seq`
+        assert_eq!(verify_len(bytes.as_slice(), root_hash).unwrap(), 10_001);
+    }
+
+    #[test]
+    fn len_proof_mismatched_hash() {
+        let mut tree = make_tree_seq(10);
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let proof = walker.create_len_proof().unwrap();
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+        assert!(matches!(
+            verify_len(bytes.as_slice(), [42; 32]),
+            Err(Error::HashMismatch(..))
+        ));
+    }
+
+    #[test]
+    fn len_proof_rejects_abridged() {
+        let mut tree = make_tree_seq(10);
+        let root_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        // a trunk-style proof with the right subtree abridged still matches the
+        // root hash, but hides the number of nodes in the subtree
+        let right_hash = *walker.tree().link(false).unwrap().hash();
+        let mut proof = vec![];
+        walker
+            .walk(true)
+            .unwrap()
+            .unwrap()
+            .traverse_for_len(&mut proof)
+            .unwrap();
+        proof.push(Op::Push(walker.to_kvhash_node()));
+        proof.push(Op::Parent);
+        proof.push(Op::Push(Node::Hash(right_hash)));
+        proof.push(Op::Child);
+
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+        assert!(verify_len(bytes.as_slice(), root_hash).is_err());
+    }
+
+    #[test]
+    fn len_proof_empty() {
+        assert_eq!(verify_len(&[], NULL_HASH).unwrap(), 0);
+        assert!(verify_len(&[], [42; 32]).is_err());
+    }
+}
@@ -1,12 +1,25 @@
 pub mod chunk;
+pub mod diff;
 pub mod encoding;
+pub mod len;
 pub mod query;
 pub mod tree;
 
+use std::fmt;
+
 use crate::tree::Hash;
 
-pub use encoding::{encode_into, Decoder};
-pub use query::Query;
+pub use chunk::verify_trunk_depth;
+pub use diff::apply_diff;
+#[cfg(feature = "full")]
+pub use diff::diff_proof;
+pub use encoding::{decode_ops, encode_into, Decoder, ReadDecoder};
+pub use len::verify_len;
+pub use query::{
+    execute_proof_counted, execute_proof_with_bounds, verify_into, verify_predecessor,
+    verify_query_strict, verify_range_empty, verify_sectioned, verify_streaming, verify_successor,
+    verify_sum, verify_to_tree, verify_with_algorithm, verify_with_limits, Query,
+};
 pub use tree::Tree;
 
 /// A proof operator, executed to verify the data in a Merkle proof.
@@ -38,4 +51,72 @@ pub enum Node {
 
     /// Represents the key and value of a tree node.
     KV(Vec<u8>, Vec<u8>),
+
+    /// Represents the key and the hash of the value of a tree node. Used for
+    /// nodes which are only included to prove the bounds of a query, so
+    /// their (potentially large) values do not need to be sent.
+    KVDigest(Vec<u8>, Hash),
+}
+
+/// The number of leading bytes of a hash shown when displaying a proof.
+const DISPLAY_HASH_BYTES: usize = 4;
+
+/// Formats bytes as a lowercase hex string.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Formats a hash as a truncated hex string, e.g. `0a1b2c3d..`.
+fn short_hash(hash: &Hash) -> String {
+    format!("{}..", hex(&hash[..DISPLAY_HASH_BYTES]))
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Node::Hash(hash) => write!(f, "Hash({})", short_hash(hash)),
+            Node::KVHash(kv_hash) => write!(f, "KVHash({})", short_hash(kv_hash)),
+            Node::KV(key, value) => write!(f, "KV({}, {})", hex(key), hex(value)),
+            Node::KVDigest(key, value_hash) => {
+                write!(f, "KVDigest({}, {})", hex(key), short_hash(value_hash))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Push(node) => write!(f, "Push({node})"),
+            Op::Parent => write!(f, "Parent"),
+            Op::Child => write!(f, "Child"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Node, Op};
+
+    #[test]
+    fn display_ops() {
+        assert_eq!(
+            Op::Push(Node::Hash([0xab; 32])).to_string(),
+            "Push(Hash(abababab..))"
+        );
+        assert_eq!(
+            Op::Push(Node::KVHash([1; 32])).to_string(),
+            "Push(KVHash(01010101..))"
+        );
+        assert_eq!(
+            Op::Push(Node::KV(vec![1, 2], vec![0xff])).to_string(),
+            "Push(KV(0102, ff))"
+        );
+        assert_eq!(
+            Op::Push(Node::KVDigest(vec![3], [2; 32])).to_string(),
+            "Push(KVDigest(03, 02020202..))"
+        );
+        assert_eq!(Op::Parent.to_string(), "Parent");
+        assert_eq!(Op::Child.to_string(), "Child");
+    }
 }
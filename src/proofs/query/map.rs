@@ -18,27 +18,31 @@ impl MapBuilder {
         })
     }
 
-    /// Adds the node's data to the uncerlying `Map` (if node is type `KV`), or
-    /// makes a note of non-contiguous data (if node is type `KVHash` or
-    /// `Hash`).
+    /// Adds the node's data to the uncerlying `Map` (if node is type `KV`, or
+    /// only its key if node is type `KVDigest`), or makes a note of
+    /// non-contiguous data (if node is type `KVHash` or `Hash`).
     pub fn insert(&mut self, node: &Node) -> Result<()> {
-        match node {
-            Node::KV(key, value) => {
-                if let Some((prev_key, _)) = self.0.entries.last_key_value() {
-                    if key <= prev_key {
-                        return Err(Error::Key(
-                            "Expected nodes to be in increasing key order".into(),
-                        ));
-                    }
-                }
+        let (key, value) = match node {
+            Node::KV(key, value) => (key, Some(value)),
+            Node::KVDigest(key, _) => (key, None),
+            _ => {
+                self.0.right_edge = false;
+                return Ok(());
+            }
+        };
 
-                let value = (self.0.right_edge, value.clone());
-                self.0.entries.insert(key.clone(), value);
-                self.0.right_edge = true;
+        if let Some((prev_key, _)) = self.0.entries.last_key_value() {
+            if key <= prev_key {
+                return Err(Error::Key(
+                    "Expected nodes to be in increasing key order".into(),
+                ));
             }
-            _ => self.0.right_edge = false,
         }
 
+        let value = (self.0.right_edge, value.cloned());
+        self.0.entries.insert(key.clone(), value);
+        self.0.right_edge = true;
+
         Ok(())
     }
 
@@ -55,7 +59,9 @@ impl MapBuilder {
 /// or iterating over ranges using the `range` method.
 #[derive(Clone, Debug)]
 pub struct Map {
-    entries: BTreeMap<Vec<u8>, (bool, Vec<u8>)>,
+    /// Entries are `None` for keys which were only proven as a `KVDigest`
+    /// (without their value).
+    entries: BTreeMap<Vec<u8>, (bool, Option<Vec<u8>>)>,
     right_edge: bool,
 }
 
@@ -64,10 +70,13 @@ impl Map {
     /// exist in the tree. If the proof does not include the data and also does
     /// not prove that the key is absent in the tree (meaning the proof is not
     /// valid), an error will be returned.
+    ///
+    /// A key which is present with an empty value returns `Some(&[])`, which is
+    /// distinct from `None`.
     pub fn get<'a>(&'a self, key: &'a [u8]) -> Result<Option<&'a [u8]>> {
         // if key is in proof just get from entries
         if let Some((_, value)) = self.entries.get(key) {
-            return Ok(Some(value.as_slice()));
+            return value.as_deref().map(Some).ok_or(Error::MissingData);
         }
 
         // otherwise, use range which only includes exact key match to check
@@ -124,7 +133,13 @@ impl Map {
         entries.extend(other.entries);
         for (key, (contiguous, val)) in entries.iter_mut() {
             if let Some(shadowed) = self.entries.get(key) {
-                assert_eq!(val, &shadowed.1, "Maps have different values",);
+                // keys proven without their value in one map take the value
+                // from the other
+                if val.is_none() {
+                    *val = shadowed.1.clone();
+                } else if shadowed.1.is_some() {
+                    assert_eq!(val, &shadowed.1, "Maps have different values",);
+                }
                 *contiguous = *contiguous || shadowed.0;
             }
         }
@@ -135,6 +150,26 @@ impl Map {
         }
     }
 
+    /// Returns `true` if the proof shows that `key` is absent from the tree:
+    /// the key is not in the map, and the entries on either side of it (or the
+    /// tree edges) are contiguous, so there is no unproven data in between.
+    ///
+    /// Unlike interpreting `Ok(None)` from `get`, this never treats a key as
+    /// absent by accident, and returns `false` (rather than an error) when the
+    /// proof does not cover the key.
+    pub fn proves_absence(&self, key: &[u8]) -> bool {
+        !self.entries.contains_key(key) && self.contiguous_right(key)
+    }
+
+    /// Consumes the `Map` and returns its entries. Keys which were only proven
+    /// as bounds of the query (without their values) map to `None`.
+    pub(crate) fn into_entries(self) -> BTreeMap<Vec<u8>, Option<Vec<u8>>> {
+        self.entries
+            .into_iter()
+            .map(|(key, (_, value))| (key, value))
+            .collect()
+    }
+
     /// Returns `true` if the [Map] can verify that there is no unproven data
     /// between `key` and the node to its right (or the global tree edge).
     ///
@@ -188,12 +223,12 @@ pub struct Range<'a> {
     iter: Peekable<InnerRange<'a>>,
 }
 
-type InnerRange<'a> = btree_map::Range<'a, Vec<u8>, (bool, Vec<u8>)>;
+type InnerRange<'a> = btree_map::Range<'a, Vec<u8>, (bool, Option<Vec<u8>>)>;
 
 impl<'a> Range<'a> {
     fn yield_entry_if_contiguous(
         &mut self,
-        entry: (&'a Vec<u8>, &'a (bool, Vec<u8>)),
+        entry: (&'a Vec<u8>, &'a (bool, Option<Vec<u8>>)),
         contiguous: bool,
         forward: bool,
     ) -> Option<Result<(&'a [u8], &'a [u8])>> {
@@ -207,10 +242,30 @@ impl<'a> Range<'a> {
 
     fn yield_entry(
         &mut self,
-        entry: (&'a Vec<u8>, &'a (bool, Vec<u8>)),
+        entry: (&'a Vec<u8>, &'a (bool, Option<Vec<u8>>)),
         forward: bool,
     ) -> Option<Result<(&'a [u8], &'a [u8])>> {
         let (key, (_, value)) = entry;
+        // the key was only proven as a boundary, without its value
+        let value = match value {
+            Some(value) => value,
+            None => {
+                self.done = true;
+                return Some(Err(Error::MissingData));
+            }
+        };
+
+        // once an inclusive bound has been reached there are no more entries
+        // in the range, so the rest of the proof does not need to be checked
+        let at_bound = if forward {
+            &self.bounds.1
+        } else {
+            &self.bounds.0
+        };
+        if let Bound::Included(bound) = at_bound {
+            self.done = bound == key;
+        }
+
         if forward {
             self.bounds.0 = Bound::Excluded(key.clone());
         } else {
@@ -385,8 +440,14 @@ mod tests {
 
         let map = builder.build();
         let mut entries = map.entries.iter();
-        assert_eq!(entries.next(), Some((&vec![1, 2, 3], &(true, vec![1]))));
-        assert_eq!(entries.next(), Some((&vec![1, 2, 4], &(false, vec![2]))));
+        assert_eq!(
+            entries.next(),
+            Some((&vec![1, 2, 3], &(true, Some(vec![1]))))
+        );
+        assert_eq!(
+            entries.next(),
+            Some((&vec![1, 2, 4], &(false, Some(vec![2]))))
+        );
         assert_eq!(entries.next(), None);
         assert!(map.right_edge);
     }
@@ -425,6 +486,66 @@ mod tests {
         assert!(map.get(&[1, 2, 3, 4]).unwrap().is_none());
     }
 
+    #[test]
+    fn map_digest_absence_proof() {
+        let mut builder = MapBuilder::new();
+        builder
+            .insert(&Node::KVDigest(vec![1, 2, 3], [0; HASH_LENGTH]))
+            .unwrap();
+        builder.insert(&Node::KV(vec![1, 2, 4], vec![2])).unwrap();
+        builder
+            .insert(&Node::KVDigest(vec![1, 2, 5], [0; HASH_LENGTH]))
+            .unwrap();
+
+        let map = builder.build();
+        assert!(map.get(&[1, 2, 3, 4]).unwrap().is_none());
+        assert_eq!(map.get(&[1, 2, 4]).unwrap().unwrap(), vec![2]);
+        assert!(map.get(&[1, 2, 3]).is_err());
+
+        let range: Vec<_> = map.range(&[1, 2, 3, 1][..]..&[1, 2, 5][..]).collect();
+        assert_eq!(range.len(), 1);
+        assert_eq!(range[0].as_ref().unwrap(), &(&[1, 2, 4][..], &[2][..]));
+        assert!(map.range(..).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn map_proves_absence() {
+        let mut builder = MapBuilder::new();
+        builder.insert(&Node::KV(vec![1], vec![1])).unwrap();
+        builder
+            .insert(&Node::KVDigest(vec![3], [0; HASH_LENGTH]))
+            .unwrap();
+        builder.insert(&Node::Hash([0; HASH_LENGTH])).unwrap();
+        builder.insert(&Node::KV(vec![5], vec![5])).unwrap();
+        builder.insert(&Node::KV(vec![7], vec![7])).unwrap();
+
+        let map = builder.build();
+        // gaps between contiguous entries and the tree edges
+        assert!(map.proves_absence(&[0]));
+        assert!(map.proves_absence(&[2]));
+        assert!(map.proves_absence(&[6]));
+        assert!(map.proves_absence(&[8]));
+        // keys which are present, even if only proven by their digest
+        assert!(!map.proves_absence(&[1]));
+        assert!(!map.proves_absence(&[3]));
+        assert!(!map.proves_absence(&[5]));
+        // the region between 3 and 5 was not included in the proof
+        assert!(!map.proves_absence(&[4]));
+        assert!(!map.proves_absence(&[3, 0]));
+    }
+
+    #[test]
+    fn map_proves_absence_abridged_edges() {
+        let mut builder = MapBuilder::new();
+        builder.insert(&Node::Hash([0; HASH_LENGTH])).unwrap();
+        builder.insert(&Node::KV(vec![5], vec![5])).unwrap();
+        builder.insert(&Node::Hash([0; HASH_LENGTH])).unwrap();
+
+        let map = builder.build();
+        assert!(!map.proves_absence(&[4]));
+        assert!(!map.proves_absence(&[6]));
+    }
+
     #[test]
     #[should_panic(expected = "MissingData")]
     fn range_abridged() {
@@ -518,6 +639,24 @@ mod tests {
         range.next().unwrap().unwrap();
     }
 
+    #[test]
+    fn range_inclusive_end_abridged() {
+        let mut builder = MapBuilder::new();
+        builder.insert(&Node::KV(vec![1, 2, 3], vec![1])).unwrap();
+        builder.insert(&Node::KV(vec![1, 2, 4], vec![2])).unwrap();
+        builder.insert(&Node::Hash([0; HASH_LENGTH])).unwrap();
+
+        let map = builder.build();
+        let mut range = map.range(&[1u8, 2, 3][..]..=&[1u8, 2, 4][..]);
+        assert_eq!(range.next().unwrap().unwrap(), (&[1, 2, 3][..], &[1][..]));
+        assert_eq!(range.next().unwrap().unwrap(), (&[1, 2, 4][..], &[2][..]));
+        assert!(range.next().is_none());
+
+        let mut range = map.range(&[1u8, 2, 4][..]..=&[1u8, 2, 4][..]).rev();
+        assert_eq!(range.next().unwrap().unwrap(), (&[1, 2, 4][..], &[2][..]));
+        assert!(range.next().is_none());
+    }
+
     #[test]
     fn range_ok_rev() {
         let mut builder = MapBuilder::new();
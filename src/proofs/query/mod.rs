@@ -3,21 +3,42 @@ mod map;
 #[cfg(feature = "full")]
 use {super::Op, std::collections::LinkedList};
 
-use super::tree::execute;
+use super::tree::{execute, execute_with_algorithm, execute_with_limits, Tree};
 use super::{Decoder, Node};
 use crate::error::{Error, Result};
-use crate::tree::{Fetch, Hash, Link, RefWalker};
+use crate::tree::{Fetch, Hash, HashAlgorithm, Link, RefWalker};
+use ed::{Decode, Encode, Terminated};
 use std::cmp::{max, min, Ordering};
-use std::collections::BTreeSet;
-use std::ops::RangeInclusive;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryInto;
+use std::io::{Read, Write};
+use std::ops::{Bound, RangeFrom, RangeFull, RangeInclusive, RangeTo, RangeToInclusive};
 
 pub use map::*;
 
+/// The generated proof operators, left and right absence flags, and the
+/// remaining limit and offset returned when creating a proof.
+#[cfg(feature = "full")]
+type ProofAbsenceLimitOffset = (LinkedList<Op>, (bool, bool), Option<usize>, Option<usize>);
+
 /// `Query` represents one or more keys or ranges of keys, which can be used to
 /// resolve a proof which will include all of the requested values.
-#[derive(Default)]
 pub struct Query {
     items: BTreeSet<QueryItem>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    left_to_right: bool,
+}
+
+impl Default for Query {
+    fn default() -> Self {
+        Query {
+            items: BTreeSet::new(),
+            limit: None,
+            offset: None,
+            left_to_right: true,
+        }
+    }
 }
 
 impl Query {
@@ -26,14 +47,86 @@ impl Query {
         Default::default()
     }
 
+    /// Creates a query for the given individual keys (see `insert_key`).
+    pub fn from_keys(keys: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        let mut query = Query::new();
+        for key in keys {
+            query.insert_key(key);
+        }
+        query
+    }
+
+    /// Creates a query for all keys starting with any of the given prefixes
+    /// (see `insert_prefix`). Overlapping prefixes are merged, so e.g. the
+    /// prefixes `[1]` and `[1, 2]` result in a single range.
+    pub fn from_prefixes(prefixes: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        let mut query = Query::new();
+        for prefix in prefixes {
+            query.insert_prefix(prefix);
+        }
+        query
+    }
+
     pub(crate) fn len(&self) -> usize {
         self.items.len()
     }
 
+    /// Returns `true` if the given key is covered by any of the query's items,
+    /// either as a single-key item or within a range.
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.items.iter().any(|item| item.contains(key))
+    }
+
+    /// Returns `true` if the query contains no items.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
     pub(crate) fn iter(&self) -> impl Iterator<Item = &QueryItem> {
         self.items.iter()
     }
 
+    /// Returns the maximum number of entries which will be included in a proof
+    /// for this query, or `None` if the query is unlimited.
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Sets the maximum number of entries which will be included in a proof
+    /// for this query. Once the limit has been reached, the remainder of the
+    /// tree will be abridged in the proof.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = Some(limit);
+    }
+
+    /// Returns the number of matching entries which will be skipped before
+    /// entries are included in a proof for this query, or `None` if no entries
+    /// are skipped.
+    pub fn offset(&self) -> Option<usize> {
+        self.offset
+    }
+
+    /// Sets the number of matching entries which will be skipped before entries
+    /// are included in a proof for this query. Skipped entries are abridged in
+    /// the proof, and do not count towards the limit.
+    pub fn set_offset(&mut self, offset: usize) {
+        self.offset = Some(offset);
+    }
+
+    /// Returns `true` if the query's offset and limit are applied starting
+    /// from the lowest keys, or `false` if they are applied starting from the
+    /// highest keys.
+    pub fn left_to_right(&self) -> bool {
+        self.left_to_right
+    }
+
+    /// Reverses the direction of the query, so that its offset and limit are
+    /// applied starting from the highest keys and verified values are returned
+    /// in descending key order.
+    pub fn set_reverse(&mut self) {
+        self.left_to_right = false;
+    }
+
     /// Adds an individual key to the query, so that its value (or its absence)
     /// in the tree will be included in the resulting proof.
     ///
@@ -68,6 +161,71 @@ impl Query {
         self.insert_item(range);
     }
 
+    /// Adds a range with no upper bound to the query, so that all the entries
+    /// in the tree with keys greater than or equal to the start of the range
+    /// will be included in the resulting proof.
+    ///
+    /// If a range including the range already exists in the query, this will
+    /// have no effect. If the query already includes a range that overlaps with
+    /// the range, the ranges will be merged together.
+    pub fn insert_range_from(&mut self, range: RangeFrom<Vec<u8>>) {
+        let range = QueryItem::RangeFrom(range);
+        self.insert_item(range);
+    }
+
+    /// Adds a range with no lower bound to the query, so that all the entries
+    /// in the tree with keys less than the end of the range will be included in
+    /// the resulting proof.
+    ///
+    /// If a range including the range already exists in the query, this will
+    /// have no effect. If the query already includes a range that overlaps with
+    /// the range, the ranges will be merged together.
+    pub fn insert_range_to(&mut self, range: RangeTo<Vec<u8>>) {
+        let range = QueryItem::RangeTo(range);
+        self.insert_item(range);
+    }
+
+    /// Adds an inclusive range with no lower bound to the query, so that all
+    /// the entries in the tree with keys less than or equal to the end of the
+    /// range will be included in the resulting proof.
+    ///
+    /// If a range including the range already exists in the query, this will
+    /// have no effect. If the query already includes a range that overlaps with
+    /// the range, the ranges will be merged together.
+    pub fn insert_range_to_inclusive(&mut self, range: RangeToInclusive<Vec<u8>>) {
+        let range = QueryItem::RangeToInclusive(range);
+        self.insert_item(range);
+    }
+
+    /// Adds a range covering the entire keyspace to the query, so that every
+    /// entry in the tree will be included in the resulting proof.
+    ///
+    /// Any items already in the query will be merged into the full range.
+    pub fn insert_range_full(&mut self) {
+        let range = QueryItem::RangeFull(RangeFull);
+        self.insert_item(range);
+    }
+
+    /// Adds all keys starting with `prefix` to the query, so that every entry
+    /// in the tree with a key starting with the prefix will be included in the
+    /// resulting proof.
+    ///
+    /// The prefix is added as the range from `prefix` up to (but not including)
+    /// its lexicographic successor, the smallest key which is greater than all
+    /// keys starting with `prefix`. The successor is computed by removing any
+    /// trailing `0xff` bytes and incrementing the last remaining byte, e.g. the
+    /// successor of `[1, 2, 0xff]` is `[1, 3]`. If the prefix only contains
+    /// `0xff` bytes there is no successor, so the range has no upper bound. An
+    /// empty prefix adds the full range.
+    pub fn insert_prefix(&mut self, prefix: Vec<u8>) {
+        let item = match prefix_successor(prefix.as_slice()) {
+            Some(end) => QueryItem::Range(prefix..end),
+            None if prefix.is_empty() => QueryItem::RangeFull(RangeFull),
+            None => QueryItem::RangeFrom(prefix..),
+        };
+        self.insert_item(item);
+    }
+
     /// Adds the `QueryItem` to the query, first checking to see if it collides
     /// with any existing ranges or keys. All colliding items will be removed
     /// then merged together so that the query includes the minimum number of
@@ -83,12 +241,83 @@ impl Query {
 
         self.items.insert(item);
     }
+
+    /// Adds all of the items of `other` to the query, merging any colliding
+    /// items together as in `insert_item`. The limit, offset, and direction of
+    /// `other` are ignored.
+    pub fn merge(&mut self, other: Query) {
+        self.extend(other);
+    }
+
+    /// Removes an individual key from the query, so that it will no longer be
+    /// included in the resulting proof. If the key falls inside a range, the
+    /// range is split into the portions before and after the key.
+    ///
+    /// Returns `true` if the key was covered by the query.
+    pub fn remove_key(&mut self, key: &[u8]) -> bool {
+        self.remove_item(QueryItem::Key(key.to_vec()))
+    }
+
+    /// Removes a range from the query, so that none of the keys in the range
+    /// will be included in the resulting proof. Ranges which partially overlap
+    /// with the removed range are shortened or split.
+    ///
+    /// Returns `true` if any part of the range was covered by the query.
+    pub fn remove_range(&mut self, range: &std::ops::Range<Vec<u8>>) -> bool {
+        if range.start >= range.end {
+            return false;
+        }
+
+        self.remove_item(QueryItem::Range(range.clone()))
+    }
+
+    /// Encodes the query into bytes, which can be decoded with
+    /// `Query::decode`. Returns an error if any bound of the query's items is
+    /// longer than `u16::MAX` bytes.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        Ok(Encode::encode(self)?)
+    }
+
+    /// Decodes a query from bytes created with `Query::encode`.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        Ok(Decode::decode(bytes)?)
+    }
+
+    /// Removes all the keys covered by `removed` from the query, keeping the
+    /// portions of any colliding items which are outside of it.
+    fn remove_item(&mut self, removed: QueryItem) -> bool {
+        let mut remaining = vec![];
+        let mut any_removed = false;
+
+        // `items.take` will remove the first item which collides
+        while let Some(existing) = self.items.take(&removed) {
+            any_removed = true;
+            remaining.extend(existing.difference(&removed));
+        }
+
+        // the remaining portions can not collide with each other or any other
+        // items, so they do not need to be merged
+        self.items.extend(remaining);
+
+        any_removed
+    }
+}
+
+impl Extend<QueryItem> for Query {
+    fn extend<T: IntoIterator<Item = QueryItem>>(&mut self, iter: T) {
+        for item in iter {
+            self.insert_item(item);
+        }
+    }
 }
 
 impl<Q: Into<QueryItem>> From<Vec<Q>> for Query {
     fn from(other: Vec<Q>) -> Self {
         let items = other.into_iter().map(Into::into).collect();
-        Query { items }
+        Query {
+            items,
+            ..Default::default()
+        }
     }
 }
 
@@ -107,47 +336,139 @@ impl IntoIterator for Query {
     }
 }
 
+/// Returns the smallest key which is greater than all keys starting with
+/// `prefix`, or `None` if there is no such key (the prefix is empty or only
+/// contains `0xff` bytes).
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|byte| *byte != 0xff)?;
+    let mut successor = prefix[..=last].to_vec();
+    successor[last] += 1;
+    Some(successor)
+}
+
 /// A `QueryItem` represents a key or range of keys to be included in a proof.
 #[derive(Clone, Debug)]
 pub enum QueryItem {
     Key(Vec<u8>),
     Range(std::ops::Range<Vec<u8>>),
     RangeInclusive(RangeInclusive<Vec<u8>>),
+    RangeFrom(RangeFrom<Vec<u8>>),
+    RangeTo(RangeTo<Vec<u8>>),
+    RangeToInclusive(RangeToInclusive<Vec<u8>>),
+    RangeFull(RangeFull),
 }
 
 impl QueryItem {
-    pub fn lower_bound(&self) -> &[u8] {
+    /// Returns the lower bound of the item, or `None` if the item has no lower
+    /// bound (it extends to the left edge of the keyspace). Lower bounds are
+    /// always inclusive.
+    pub fn lower_bound(&self) -> Option<&[u8]> {
         match self {
-            QueryItem::Key(key) => key.as_slice(),
-            QueryItem::Range(range) => range.start.as_ref(),
-            QueryItem::RangeInclusive(range) => range.start().as_ref(),
+            QueryItem::Key(key) => Some(key.as_slice()),
+            QueryItem::Range(range) => Some(range.start.as_ref()),
+            QueryItem::RangeInclusive(range) => Some(range.start().as_ref()),
+            QueryItem::RangeFrom(range) => Some(range.start.as_ref()),
+            QueryItem::RangeTo(_) => None,
+            QueryItem::RangeToInclusive(_) => None,
+            QueryItem::RangeFull(_) => None,
         }
     }
 
-    pub fn upper_bound(&self) -> (&[u8], bool) {
+    /// Returns the upper bound of the item and whether or not it is inclusive.
+    /// The bound is `None` if the item has no upper bound (it extends to the
+    /// right edge of the keyspace).
+    pub fn upper_bound(&self) -> (Option<&[u8]>, bool) {
         match self {
-            QueryItem::Key(key) => (key.as_slice(), true),
-            QueryItem::Range(range) => (range.end.as_ref(), false),
-            QueryItem::RangeInclusive(range) => (range.end().as_ref(), true),
+            QueryItem::Key(key) => (Some(key.as_slice()), true),
+            QueryItem::Range(range) => (Some(range.end.as_ref()), false),
+            QueryItem::RangeInclusive(range) => (Some(range.end().as_ref()), true),
+            QueryItem::RangeFrom(_) => (None, true),
+            QueryItem::RangeTo(range) => (Some(range.end.as_ref()), false),
+            QueryItem::RangeToInclusive(range) => (Some(range.end.as_ref()), true),
+            QueryItem::RangeFull(_) => (None, true),
         }
     }
 
     pub fn contains(&self, key: &[u8]) -> bool {
-        let (bound, inclusive) = self.upper_bound();
-        return key >= self.lower_bound() && (key < bound || (key == bound && inclusive));
+        let above_lower = self.lower_bound().is_none_or(|bound| key >= bound);
+        let below_upper = match self.upper_bound() {
+            (None, _) => true,
+            (Some(bound), inclusive) => key < bound || (key == bound && inclusive),
+        };
+        above_lower && below_upper
+    }
+
+    /// Returns the portions of the item which are not covered by `removed`,
+    /// assuming the items collide. There may be a portion before and a portion
+    /// after `removed`.
+    fn difference(&self, removed: &QueryItem) -> Vec<QueryItem> {
+        let mut portions = vec![];
+
+        // the portion before the removed item ends (exclusively) at its lower
+        // bound
+        if let Some(removed_start) = removed.lower_bound() {
+            match self.lower_bound() {
+                None => portions.push(QueryItem::RangeTo(..removed_start.to_vec())),
+                Some(start) if start < removed_start => {
+                    portions.push(QueryItem::Range(start.to_vec()..removed_start.to_vec()))
+                }
+                Some(_) => {}
+            }
+        }
+
+        // the portion after the removed item starts after its upper bound. an
+        // inclusive upper bound is made exclusive by appending a zero byte,
+        // which results in the next possible key
+        if let (Some(removed_end), inclusive) = removed.upper_bound() {
+            let mut start = removed_end.to_vec();
+            if inclusive {
+                start.push(0);
+            }
+
+            match self.upper_bound() {
+                (None, _) => portions.push(QueryItem::RangeFrom(start..)),
+                (Some(end), false) if start.as_slice() < end => {
+                    portions.push(QueryItem::Range(start..end.to_vec()))
+                }
+                (Some(end), true) if start.as_slice() < end => {
+                    portions.push(QueryItem::RangeInclusive(start..=end.to_vec()))
+                }
+                (Some(end), true) if start.as_slice() == end => {
+                    portions.push(QueryItem::Key(start))
+                }
+                _ => {}
+            }
+        }
+
+        portions
     }
 
     fn merge(self, other: QueryItem) -> QueryItem {
         // TODO: don't copy into new vecs
-        let start = min(self.lower_bound(), other.lower_bound()).to_vec();
-        let end = max(self.upper_bound(), other.upper_bound());
-        if end.1 {
-            QueryItem::RangeInclusive(RangeInclusive::new(start, end.0.to_vec()))
-        } else {
-            QueryItem::Range(std::ops::Range {
+        // `None` sorts before `Some`, which is the correct order for unbounded
+        // lower bounds
+        let start = min(self.lower_bound(), other.lower_bound()).map(|k| k.to_vec());
+        let end = match (self.upper_bound(), other.upper_bound()) {
+            ((None, _), _) | (_, (None, _)) => None,
+            ((Some(a), a_inclusive), (Some(b), b_inclusive)) => {
+                Some(max((a, a_inclusive), (b, b_inclusive)))
+            }
+        };
+
+        match (start, end) {
+            (Some(start), Some((end, true))) => {
+                QueryItem::RangeInclusive(RangeInclusive::new(start, end.to_vec()))
+            }
+            (Some(start), Some((end, false))) => QueryItem::Range(std::ops::Range {
                 start,
-                end: end.0.to_vec(),
-            })
+                end: end.to_vec(),
+            }),
+            (Some(start), None) => QueryItem::RangeFrom(RangeFrom { start }),
+            (None, Some((end, false))) => QueryItem::RangeTo(RangeTo { end: end.to_vec() }),
+            (None, Some((end, true))) => {
+                QueryItem::RangeToInclusive(RangeToInclusive { end: end.to_vec() })
+            }
+            (None, None) => QueryItem::RangeFull(RangeFull),
         }
     }
 }
@@ -168,8 +489,17 @@ impl Eq for QueryItem {}
 
 impl Ord for QueryItem {
     fn cmp(&self, other: &QueryItem) -> Ordering {
-        let cmp_lu = self.lower_bound().cmp(other.upper_bound().0);
-        let cmp_ul = self.upper_bound().0.cmp(other.lower_bound());
+        // an unbounded lower side is always less than the other item's upper
+        // bound, and an unbounded upper side is always greater than the other
+        // item's lower bound
+        let cmp_lu = match (self.lower_bound(), other.upper_bound().0) {
+            (Some(lower), Some(upper)) => lower.cmp(upper),
+            _ => Ordering::Less,
+        };
+        let cmp_ul = match (self.upper_bound().0, other.lower_bound()) {
+            (Some(upper), Some(lower)) => upper.cmp(lower),
+            _ => Ordering::Greater,
+        };
         let self_inclusive = self.upper_bound().1;
         let other_inclusive = other.upper_bound().1;
 
@@ -208,6 +538,151 @@ impl From<Vec<u8>> for QueryItem {
     }
 }
 
+/// Writes a query item bound, prefixed with its length as a `u16`. Returns an
+/// error if the bound is longer than `u16::MAX` bytes.
+fn encode_bound<W: Write>(bound: &[u8], dest: &mut W) -> ed::Result<()> {
+    let len: u16 = bound.len().try_into().map_err(|_| {
+        ed::Error::IOError(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Query item bound of {} bytes is too long", bound.len()),
+        ))
+    })?;
+
+    len.encode_into(dest)?;
+    dest.write_all(bound)?;
+    Ok(())
+}
+
+/// Reads a query item bound written by `encode_bound`.
+fn decode_bound<R: Read>(mut input: R) -> ed::Result<Vec<u8>> {
+    let len: u16 = Decode::decode(&mut input)?;
+    let mut bound = vec![0; len as usize];
+    input.read_exact(bound.as_mut_slice())?;
+    Ok(bound)
+}
+
+impl Encode for QueryItem {
+    fn encode_into<W: Write>(&self, dest: &mut W) -> ed::Result<()> {
+        match self {
+            QueryItem::Key(key) => {
+                dest.write_all(&[0x00])?;
+                encode_bound(key, dest)?;
+            }
+            QueryItem::Range(range) => {
+                dest.write_all(&[0x01])?;
+                encode_bound(&range.start, dest)?;
+                encode_bound(&range.end, dest)?;
+            }
+            QueryItem::RangeInclusive(range) => {
+                dest.write_all(&[0x02])?;
+                encode_bound(range.start(), dest)?;
+                encode_bound(range.end(), dest)?;
+            }
+            QueryItem::RangeFrom(range) => {
+                dest.write_all(&[0x03])?;
+                encode_bound(&range.start, dest)?;
+            }
+            QueryItem::RangeTo(range) => {
+                dest.write_all(&[0x04])?;
+                encode_bound(&range.end, dest)?;
+            }
+            QueryItem::RangeFull(_) => dest.write_all(&[0x05])?,
+            QueryItem::RangeToInclusive(range) => {
+                dest.write_all(&[0x06])?;
+                encode_bound(&range.end, dest)?;
+            }
+        };
+        Ok(())
+    }
+
+    fn encoding_length(&self) -> ed::Result<usize> {
+        Ok(match self {
+            QueryItem::Key(key) => 3 + key.len(),
+            QueryItem::Range(range) => 5 + range.start.len() + range.end.len(),
+            QueryItem::RangeInclusive(range) => 5 + range.start().len() + range.end().len(),
+            QueryItem::RangeFrom(range) => 3 + range.start.len(),
+            QueryItem::RangeTo(range) => 3 + range.end.len(),
+            QueryItem::RangeToInclusive(range) => 3 + range.end.len(),
+            QueryItem::RangeFull(_) => 1,
+        })
+    }
+}
+
+impl Decode for QueryItem {
+    fn decode<R: Read>(mut input: R) -> ed::Result<Self> {
+        let variant: u8 = Decode::decode(&mut input)?;
+
+        Ok(match variant {
+            0x00 => QueryItem::Key(decode_bound(&mut input)?),
+            0x01 => {
+                let start = decode_bound(&mut input)?;
+                let end = decode_bound(&mut input)?;
+                QueryItem::Range(start..end)
+            }
+            0x02 => {
+                let start = decode_bound(&mut input)?;
+                let end = decode_bound(&mut input)?;
+                QueryItem::RangeInclusive(start..=end)
+            }
+            0x03 => QueryItem::RangeFrom(decode_bound(&mut input)?..),
+            0x04 => QueryItem::RangeTo(..decode_bound(&mut input)?),
+            0x05 => QueryItem::RangeFull(..),
+            0x06 => QueryItem::RangeToInclusive(..=decode_bound(&mut input)?),
+            byte => {
+                return Err(ed::Error::UnexpectedByte(byte));
+            }
+        })
+    }
+}
+
+impl Terminated for QueryItem {}
+
+impl Encode for Query {
+    fn encode_into<W: Write>(&self, dest: &mut W) -> ed::Result<()> {
+        debug_assert!(self.items.len() <= u32::MAX as usize);
+
+        (self.items.len() as u32).encode_into(dest)?;
+        for item in self.items.iter() {
+            item.encode_into(dest)?;
+        }
+        self.limit.map(|limit| limit as u64).encode_into(dest)?;
+        self.offset.map(|offset| offset as u64).encode_into(dest)?;
+        self.left_to_right.encode_into(dest)?;
+        Ok(())
+    }
+
+    fn encoding_length(&self) -> ed::Result<usize> {
+        let mut length = 4;
+        for item in self.items.iter() {
+            length += item.encoding_length()?;
+        }
+        length += self.limit.map(|limit| limit as u64).encoding_length()?;
+        length += self.offset.map(|offset| offset as u64).encoding_length()?;
+        length += self.left_to_right.encoding_length()?;
+        Ok(length)
+    }
+}
+
+impl Decode for Query {
+    fn decode<R: Read>(mut input: R) -> ed::Result<Self> {
+        let mut query = Query::new();
+
+        let len: u32 = Decode::decode(&mut input)?;
+        for _ in 0..len {
+            let item: QueryItem = Decode::decode(&mut input)?;
+            query.insert_item(item);
+        }
+
+        let limit: Option<u64> = Decode::decode(&mut input)?;
+        query.limit = limit.map(|limit| limit as usize);
+        let offset: Option<u64> = Decode::decode(&mut input)?;
+        query.offset = offset.map(|offset| offset as usize);
+        query.left_to_right = Decode::decode(&mut input)?;
+
+        Ok(query)
+    }
+}
+
 impl Link {
     /// Creates a `Node::Hash` from this link. Panics if the link is of variant
     /// `Link::Modified` since its hash has not yet been computed.
@@ -234,6 +709,16 @@ where
         Node::KV(self.tree().key().to_vec(), self.tree().value().to_vec())
     }
 
+    /// Creates a `Node::KVDigest` from the key and the hash of the value of the
+    /// root node.
+    #[cfg(feature = "full")]
+    pub(crate) fn to_kvdigest_node(&self) -> Node {
+        Node::KVDigest(
+            self.tree().key().to_vec(),
+            self.tree().hash_algorithm().value_hash(self.tree().value()),
+        )
+    }
+
     /// Creates a `Node::KVHash` from the hash of the key/value pair of the root
     /// node.
     pub(crate) fn to_kvhash_node(&self) -> Node {
@@ -249,56 +734,82 @@ where
     /// containing the generated proof operators, and a tuple representing if
     /// any keys were queried were less than the left edge or greater than the
     /// right edge, respectively.
-    #[cfg(feature = "full")]
+    #[cfg(all(test, feature = "full"))]
     pub(crate) fn create_proof(
         &mut self,
         query: &[QueryItem],
     ) -> Result<(LinkedList<Op>, (bool, bool))> {
+        let (proof, absence, ..) = self.create_full_proof(query, None, None, true)?;
+        Ok((proof, absence))
+    }
+
+    /// Similar to `create_proof`, but skips the first `offset` queried entries
+    /// and stops including queried entries once `limit` of them have been
+    /// added to the proof (skipped entries and the rest of the tree are
+    /// abridged). If `left_to_right` is `false`, the offset and limit are
+    /// applied starting from the highest keys. Also returns the remaining limit
+    /// and offset after creating the proof.
+    #[cfg(feature = "full")]
+    pub(crate) fn create_full_proof(
+        &mut self,
+        query: &[QueryItem],
+        limit: Option<usize>,
+        offset: Option<usize>,
+        left_to_right: bool,
+    ) -> Result<ProofAbsenceLimitOffset> {
         // TODO: don't copy into vec, support comparing QI to byte slice
         let node_key = QueryItem::Key(self.tree().key().to_vec());
         let search = query.binary_search_by(|key| key.cmp(&node_key));
 
-        let (left_items, right_items) = match search {
-            Ok(index) => {
-                let item = &query[index];
-                let left_bound = item.lower_bound();
-                let right_bound = item.upper_bound().0;
+        let (left_items, right_items) = split_query(query, search, self.tree().key());
 
-                // if range starts before this node's key, include it in left
-                // child's query
-                let left_query = if left_bound < self.tree().key() {
-                    &query[..=index]
-                } else {
-                    &query[..index]
-                };
+        // the child which comes first in the query's direction is visited
+        // first, so that the offset and limit are applied in order
+        let (first_items, second_items) = if left_to_right {
+            (left_items, right_items)
+        } else {
+            (right_items, left_items)
+        };
 
-                // if range ends after this node's key, include it in right
-                // child's query
-                let right_query = if right_bound > self.tree().key() {
-                    &query[index..]
-                } else {
-                    &query[index + 1..]
-                };
+        let (first_proof, first_absence, mut limit, mut offset) =
+            self.create_child_proof(left_to_right, first_items, limit, offset, left_to_right)?;
 
-                (left_query, right_query)
+        // queried nodes are included unless they are skipped by the offset or
+        // past the limit
+        let queried = match search {
+            Ok(_) if limit == Some(0) => false,
+            Ok(_) if offset.is_some_and(|offset| offset > 0) => {
+                offset = offset.map(|offset| offset - 1);
+                false
+            }
+            Ok(_) => {
+                limit = limit.map(|limit| limit - 1);
+                true
             }
-            Err(index) => (&query[..index], &query[index..]),
+            Err(_) => false,
+        };
+
+        let (second_proof, second_absence, limit, offset) =
+            self.create_child_proof(!left_to_right, second_items, limit, offset, left_to_right)?;
+
+        let ((mut proof, left_absence), (mut right_proof, right_absence)) = if left_to_right {
+            ((first_proof, first_absence), (second_proof, second_absence))
+        } else {
+            ((second_proof, second_absence), (first_proof, first_absence))
         };
 
-        let (mut proof, left_absence) = self.create_child_proof(true, left_items)?;
-        let (mut right_proof, right_absence) = self.create_child_proof(false, right_items)?;
+        // other nodes are only included as boundaries proving the absence of
+        // queried keys, which only requires their key
+        let boundary = !queried && search.is_err() && (left_absence.1 || right_absence.0);
 
         let (has_left, has_right) = (!proof.is_empty(), !right_proof.is_empty());
 
-        proof.push_back(match search {
-            Ok(_) => Op::Push(self.to_kv_node()),
-            Err(_) => {
-                if left_absence.1 || right_absence.0 {
-                    Op::Push(self.to_kv_node())
-                } else {
-                    Op::Push(self.to_kvhash_node())
-                }
-            }
+        proof.push_back(if queried {
+            Op::Push(self.to_kv_node())
+        } else if boundary {
+            Op::Push(self.to_kvdigest_node())
+        } else {
+            Op::Push(self.to_kvhash_node())
         });
 
         if has_left {
@@ -310,7 +821,7 @@ where
             proof.push_back(Op::Child);
         }
 
-        Ok((proof, (left_absence.0, right_absence.1)))
+        Ok((proof, (left_absence.0, right_absence.1), limit, offset))
     }
 
     /// Similar to `create_proof`. Recurses into the child on the given side and
@@ -320,28 +831,101 @@ where
         &mut self,
         left: bool,
         query: &[QueryItem],
-    ) -> Result<(LinkedList<Op>, (bool, bool))> {
-        Ok(if !query.is_empty() {
+        limit: Option<usize>,
+        offset: Option<usize>,
+        left_to_right: bool,
+    ) -> Result<ProofAbsenceLimitOffset> {
+        Ok(if !query.is_empty() && limit != Some(0) {
             if let Some(mut child) = self.walk(left)? {
-                child.create_proof(query)?
+                child.create_full_proof(query, limit, offset, left_to_right)?
             } else {
-                (LinkedList::new(), (true, true))
+                (LinkedList::new(), (true, true), limit, offset)
             }
         } else if let Some(link) = self.tree().link(left) {
             let mut proof = LinkedList::new();
             proof.push_back(Op::Push(link.to_hash_node()));
-            (proof, (false, false))
+            (proof, (false, false), limit, offset)
         } else {
-            (LinkedList::new(), (false, false))
+            (LinkedList::new(), (false, false), limit, offset)
         })
     }
 }
 
+/// Splits the sorted query items into the items which must be queried in the
+/// left and right subtrees of the node with the given key, given the result of
+/// searching for the key in the items.
+#[cfg(feature = "full")]
+pub(crate) fn split_query<'a>(
+    query: &'a [QueryItem],
+    search: std::result::Result<usize, usize>,
+    key: &[u8],
+) -> (&'a [QueryItem], &'a [QueryItem]) {
+    match search {
+        Ok(index) => {
+            let item = &query[index];
+
+            // if range starts before this node's key (or is unbounded),
+            // include it in left child's query
+            let left_query = if item.lower_bound().is_none_or(|bound| bound < key) {
+                &query[..=index]
+            } else {
+                &query[..index]
+            };
+
+            // if range ends after this node's key (or is unbounded),
+            // include it in right child's query
+            let right_query = if item.upper_bound().0.is_none_or(|bound| bound > key) {
+                &query[index..]
+            } else {
+                &query[index + 1..]
+            };
+
+            (left_query, right_query)
+        }
+        Err(index) => (&query[..index], &query[index..]),
+    }
+}
+
 pub fn verify(bytes: &[u8], expected_hash: Hash) -> Result<Map> {
+    verify_with_algorithm(bytes, expected_hash, HashAlgorithm::default())
+}
+
+/// Verifies the encoded proof like `verify`, but computes hashes with the given
+/// hash algorithm. This must be the algorithm used by the store which created
+/// the proof (see `Merk::hash_algorithm`).
+pub fn verify_with_algorithm(
+    bytes: &[u8],
+    expected_hash: Hash,
+    hash_algorithm: HashAlgorithm,
+) -> Result<Map> {
+    let ops = Decoder::new(bytes);
+    let mut map_builder = MapBuilder::new();
+
+    let root = execute_with_algorithm(ops, true, hash_algorithm, |node| map_builder.insert(node))?;
+
+    let root_hash = root.hash_with_algorithm(hash_algorithm)?;
+    if root_hash != expected_hash {
+        return Err(Error::HashMismatch(expected_hash, root_hash));
+    }
+
+    Ok(map_builder.build())
+}
+
+/// Verifies the encoded proof like `verify`, but returns an error if the tree
+/// reconstructed from the proof is higher than `max_depth`, without executing
+/// the rest of the proof. This caps the cost of verifying proofs from an
+/// untrusted source.
+///
+/// Since Merk trees are AVL-balanced, a tree with `n` nodes is at most about
+/// `1.44 * log2(n)` levels high, so legitimate proofs stay well within a limit
+/// derived from the expected size of the tree.
+pub fn verify_with_limits(bytes: &[u8], expected_hash: Hash, max_depth: usize) -> Result<Map> {
     let ops = Decoder::new(bytes);
     let mut map_builder = MapBuilder::new();
 
-    let root = execute(ops, true, |node| map_builder.insert(node))?;
+    let root = execute_with_limits(ops, true, HashAlgorithm::default(), max_depth, |node| {
+        map_builder.insert(node)
+    })?;
 
     if root.hash()? != expected_hash {
         return Err(Error::HashMismatch(expected_hash, root.hash()?));
@@ -350,54 +934,373 @@ pub fn verify(bytes: &[u8], expected_hash: Hash) -> Result<Map> {
     Ok(map_builder.build())
 }
 
-/// Verifies the encoded proof with the given query and expected hash.
-///
-/// Every key in `keys` is checked to either have a key/value pair in the proof,
-/// or to have its absence in the tree proven.
-///
-/// Returns `Err` if the proof is invalid, or a list of proven values associated
-/// with `keys`. For example, if `keys` contains keys `A` and `B`, the returned
-/// list will contain 2 elements, the value of `A` and the value of `B`. Keys
-/// proven to be absent in the tree will have an entry of `None`, keys that have
-/// a proven value will have an entry of `Some(value)`.
-#[deprecated]
-pub fn verify_query(
+/// Verifies a proof created by `Merk::prove_successor` against the expected
+/// hash, returning the entry with the smallest key greater than `key`, or
+/// `None` if the proof shows there are no keys greater than `key`. Returns an
+/// error if the proof does not cover the keys following `key`.
+pub fn verify_successor(
     bytes: &[u8],
-    query: &Query,
+    key: &[u8],
     expected_hash: Hash,
-) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
-    let mut output = Vec::with_capacity(query.len());
-    let mut last_push = None;
-    let mut query = query.iter().peekable();
-    let mut in_range = false;
+) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+    let map = verify(bytes, expected_hash)?;
+    let entry = map
+        .range((Bound::Excluded(key), Bound::Unbounded))
+        .next()
+        .transpose()?;
+    Ok(entry.map(|(key, value)| (key.to_vec(), value.to_vec())))
+}
 
-    let ops = Decoder::new(bytes);
+/// Verifies a proof created by `Merk::prove_predecessor` against the expected
+/// hash, returning the entry with the largest key less than `key`, or `None` if
+/// the proof shows there are no keys less than `key`. Returns an error if the
+/// proof does not cover the keys preceding `key`.
+pub fn verify_predecessor(
+    bytes: &[u8],
+    key: &[u8],
+    expected_hash: Hash,
+) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+    let map = verify(bytes, expected_hash)?;
+    let entry = map
+        .range((Bound::Unbounded, Bound::Excluded(key)))
+        .next_back()
+        .transpose()?;
+    Ok(entry.map(|(key, value)| (key.to_vec(), value.to_vec())))
+}
 
-    let root = execute(ops, true, |node| {
-        if let Node::KV(key, value) = node {
-            while let Some(item) = query.peek() {
-                // get next item in query
-                let query_item = *item;
-                // we have not reached next queried part of tree
-                if *query_item > key.as_slice() {
-                    // continue to next push
-                    break;
-                }
+/// Verifies a proof created by `Merk::prove_range_empty` against the expected
+/// hash, returning an error unless the proof shows that no keys exist in
+/// `range`. Since the proof must cover the whole range contiguously, a proof of
+/// a smaller range is rejected.
+pub fn verify_range_empty(
+    bytes: &[u8],
+    range: std::ops::Range<Vec<u8>>,
+    expected_hash: Hash,
+) -> Result<()> {
+    let map = verify(bytes, expected_hash)?;
+    let range = range.start.as_slice()..range.end.as_slice();
+    match map.range(range).next().transpose()? {
+        None => Ok(()),
+        Some((key, _)) => Err(Error::Proof(format!(
+            "Range is not empty, it contains key {key:?}"
+        ))),
+    }
+}
 
-                if !in_range {
-                    // this is the first data we have encountered for this query
-                    // item. ensure lower bound of query item is proven
-                    match last_push {
-                        // lower bound is proven - we have an exact match
-                        _ if key == query_item.lower_bound() => {}
+/// Verifies the encoded proof against the expected hash, returning the tree
+/// reconstructed from it instead of the proven entries. The tree contains every
+/// node in the proof (including `Node::Hash` and `Node::KVHash` nodes), so it
+/// can be traversed to inspect the structure of the proven part of the tree or
+/// to aggregate data in custom ways.
+pub fn verify_to_tree(bytes: &[u8], expected_hash: Hash) -> Result<Tree> {
+    let ops = Decoder::new(bytes);
+    let root = execute(ops, false, |_| Ok(()))?;
 
-                        // lower bound is proven - this is the leftmost node
-                        // in the tree
-                        None => {}
+    let root_hash = root.hash()?;
+    if root_hash != expected_hash {
+        return Err(Error::HashMismatch(expected_hash, root_hash));
+    }
 
-                        // lower bound is proven - the preceding tree node
-                        // is lower than the bound
-                        Some(Node::KV(_, _)) => {}
+    Ok(root)
+}
+
+/// Verifies the encoded proof against the expected hash, returning the sum of
+/// the values of all entries matching `query`. Each value is interpreted as a
+/// big-endian `u64`, and values which are not 8 bytes long result in an error.
+///
+/// The proof must include every entry in the queried ranges, so the query's
+/// limit and offset are not supported.
+pub fn verify_sum(bytes: &[u8], query: &Query, expected_hash: Hash) -> Result<u128> {
+    let map = verify(bytes, expected_hash)?;
+
+    let mut sum = 0;
+
+    for item in query.iter() {
+        let upper_bound = match item.upper_bound() {
+            (Some(bound), true) => Bound::Included(bound),
+            (Some(bound), false) => Bound::Excluded(bound),
+            (None, _) => Bound::Unbounded,
+        };
+        let lower_bound = item.lower_bound().map_or(Bound::Unbounded, Bound::Included);
+
+        for entry in map.range((lower_bound, upper_bound)) {
+            let (key, value) = entry?;
+            let value: [u8; 8] = value.try_into().map_err(|_| {
+                Error::Proof(format!(
+                    "Expected 8-byte value for key {:?}, got {} bytes",
+                    key,
+                    value.len()
+                ))
+            })?;
+            sum += u64::from_be_bytes(value) as u128;
+        }
+    }
+
+    Ok(sum)
+}
+
+/// The verified data from a proof, along with the lowest and highest keys
+/// included in the proof, as returned by `execute_proof_with_bounds`.
+pub type MapWithBounds = (Map, Option<Vec<u8>>, Option<Vec<u8>>);
+
+/// Verifies the encoded proof against the expected hash and checks that it
+/// includes every entry matching `query`, also returning the lowest and highest
+/// keys included in the proof (with or without their value).
+///
+/// For a query which does not reach the edges of the tree, these are the keys
+/// of the neighboring entries just outside the queried ranges, so proofs of
+/// adjacent ranges can be checked to leave no gaps when stitched together.
+pub fn execute_proof_with_bounds(
+    bytes: &[u8],
+    query: &Query,
+    expected_hash: Hash,
+) -> Result<MapWithBounds> {
+    let ops = Decoder::new(bytes);
+    let mut map_builder = MapBuilder::new();
+    let mut bounds: Option<(Vec<u8>, Vec<u8>)> = None;
+
+    let root = execute(ops, true, |node| {
+        if let Node::KV(key, _) | Node::KVDigest(key, _) = node {
+            match &mut bounds {
+                None => bounds = Some((key.clone(), key.clone())),
+                // keys are checked to be increasing during execution
+                Some((_, right)) => *right = key.clone(),
+            }
+        }
+        map_builder.insert(node)
+    })?;
+
+    if root.hash()? != expected_hash {
+        return Err(Error::HashMismatch(expected_hash, root.hash()?));
+    }
+
+    let map = map_builder.build();
+    for item in query.iter() {
+        let upper_bound = match item.upper_bound() {
+            (Some(bound), true) => Bound::Included(bound),
+            (Some(bound), false) => Bound::Excluded(bound),
+            (None, _) => Bound::Unbounded,
+        };
+        let lower_bound = item.lower_bound().map_or(Bound::Unbounded, Bound::Included);
+
+        // errors if the proof is missing data within the range
+        for entry in map.range((lower_bound, upper_bound)) {
+            entry?;
+        }
+    }
+
+    let (left, right) = bounds.map_or((None, None), |(left, right)| (Some(left), Some(right)));
+    Ok((map, left, right))
+}
+
+/// Verifies the encoded proof against the expected hash, calling `visit` with
+/// each key/value pair in the proof (in key order) rather than collecting them
+/// into a `Map`.
+///
+/// Since the root hash is only known once the whole proof has been executed,
+/// `visit` is called before the proof is verified. If this returns `Err`, any
+/// data passed to `visit` must be discarded.
+pub fn verify_streaming<F>(bytes: &[u8], expected_hash: Hash, mut visit: F) -> Result<()>
+where
+    F: FnMut(&[u8], &[u8]) -> Result<()>,
+{
+    let ops = Decoder::new(bytes);
+
+    let root = execute(ops, true, |node| match node {
+        Node::KV(key, value) => visit(key, value),
+        _ => Ok(()),
+    })?;
+
+    if root.hash()? != expected_hash {
+        return Err(Error::HashMismatch(expected_hash, root.hash()?));
+    }
+
+    Ok(())
+}
+
+/// Verifies the encoded proof against the expected hash, returning the proven
+/// data as an owned map rather than a `Map`.
+///
+/// Every key included in the proof is an entry in the returned map. Keys which
+/// were only included to prove the bounds of the query (so their values are not
+/// part of the proof) map to `None`. Unlike `Map`, the returned map can not be
+/// used to check whether a key is absent from the tree.
+pub fn verify_into(
+    bytes: &[u8],
+    expected_hash: Hash,
+) -> Result<BTreeMap<Vec<u8>, Option<Vec<u8>>>> {
+    verify(bytes, expected_hash).map(Map::into_entries)
+}
+
+/// Verifies the encoded proof against the expected hash like `verify`, but
+/// also checks that the proof does not include the values of any keys which
+/// are not covered by `query`. Nodes which are only included to prove the
+/// bounds of the query (`KVDigest`, `KVHash`, and `Hash` nodes) are allowed.
+pub fn verify_query_strict(bytes: &[u8], query: &Query, expected_hash: Hash) -> Result<Map> {
+    let ops = Decoder::new(bytes);
+    let mut map_builder = MapBuilder::new();
+
+    let root = execute(ops, true, |node| {
+        if let Node::KV(key, _) = node {
+            if !query.contains_key(key) {
+                return Err(Error::Proof(format!(
+                    "Proof contains value for key {:?} which is not in the query",
+                    key
+                )));
+            }
+        }
+
+        map_builder.insert(node)
+    })?;
+
+    if root.hash()? != expected_hash {
+        return Err(Error::HashMismatch(expected_hash, root.hash()?));
+    }
+
+    Ok(map_builder.build())
+}
+
+/// Verifies a sectioned proof created by `Merk::prove_sectioned`, which
+/// contains one length-prefixed sub-proof per query. Each section is verified
+/// against the expected hash with `verify_query_strict` using the query at the
+/// same index, and the resulting maps are returned in the same order as
+/// `queries`.
+pub fn verify_sectioned(bytes: &[u8], queries: &[Query], expected_hash: Hash) -> Result<Vec<Map>> {
+    let mut input = bytes;
+    let mut maps = Vec::with_capacity(queries.len());
+    for query in queries {
+        let length: u32 = Decode::decode(&mut input)?;
+        let length = length as usize;
+        if length > input.len() {
+            return Err(Error::Proof("Proof section is truncated".into()));
+        }
+
+        let (section, rest) = input.split_at(length);
+        maps.push(verify_query_strict(section, query, expected_hash)?);
+        input = rest;
+    }
+
+    if !input.is_empty() {
+        return Err(Error::Proof(
+            "Proof contains more sections than queries".into(),
+        ));
+    }
+
+    Ok(maps)
+}
+
+/// Verifies the encoded proof against the expected hash, returning the proven
+/// data along with the number of key/value nodes encountered in the proof.
+///
+/// Comparing the count against the query's limit can be used to detect whether
+/// the proof was truncated by the limit. Returns an error if the proof includes
+/// more entries matching `query` than its limit allows.
+pub fn execute_proof_counted(
+    bytes: &[u8],
+    query: &Query,
+    expected_hash: Hash,
+) -> Result<(Map, usize)> {
+    let ops = Decoder::new(bytes);
+    let mut map_builder = MapBuilder::new();
+    let mut kv_count = 0;
+    let mut queried_count = 0;
+
+    let root = execute(ops, true, |node| {
+        if let Node::KV(key, _) = node {
+            kv_count += 1;
+            if query.contains_key(key) {
+                queried_count += 1;
+            }
+        }
+
+        map_builder.insert(node)
+    })?;
+
+    if root.hash()? != expected_hash {
+        return Err(Error::HashMismatch(expected_hash, root.hash()?));
+    }
+
+    if query.limit().is_some_and(|limit| queried_count > limit) {
+        return Err(Error::Proof(
+            "Proof contains more entries than the query limit".into(),
+        ));
+    }
+
+    Ok((map_builder.build(), kv_count))
+}
+
+/// Verifies the encoded proof with the given query and expected hash.
+///
+/// Every key in `keys` is checked to either have a key/value pair in the proof,
+/// or to have its absence in the tree proven.
+///
+/// Returns `Err` if the proof is invalid, or a list of proven values associated
+/// with `keys`. For example, if `keys` contains keys `A` and `B`, the returned
+/// list will contain 2 elements, the value of `A` and the value of `B`. Keys
+/// proven to be absent in the tree will have an entry of `None`, keys that have
+/// a proven value will have an entry of `Some(value)`.
+///
+/// If the query has a limit, verification of the queried keys stops once that
+/// many values have been proven. If the query has an offset, the lower bound of
+/// the first proven value is not checked since the skipped entries are
+/// abridged. If the query is reversed, these apply from the highest keys and
+/// the values are returned in descending key order.
+#[deprecated]
+pub fn verify_query(
+    bytes: &[u8],
+    query: &Query,
+    expected_hash: Hash,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut output = Vec::with_capacity(query.len());
+    let mut last_push = None;
+    let limit = query.limit();
+    let skipped = query.offset().is_some_and(|offset| offset > 0);
+    let left_to_right = query.left_to_right();
+    // entries before the first proven value may be abridged if they were
+    // skipped by the offset, or were past the limit of a reversed query
+    let abridged_left = if left_to_right {
+        skipped
+    } else {
+        limit.is_some()
+    };
+    let mut done = false;
+    let mut query = query.iter().peekable();
+    let mut in_range = false;
+
+    let ops = Decoder::new(bytes);
+
+    let root = execute(ops, true, |node| {
+        if done || (left_to_right && limit == Some(output.len())) {
+            // the limit has been reached, the rest of the proof is only needed
+            // to compute the root hash
+        } else if let Node::KV(key, _) | Node::KVDigest(key, _) = node {
+            while let Some(item) = query.peek() {
+                // get next item in query
+                let query_item = *item;
+                // we have not reached next queried part of tree
+                if *query_item > key.as_slice() {
+                    // continue to next push
+                    break;
+                }
+
+                if !in_range {
+                    // this is the first data we have encountered for this query
+                    // item. ensure lower bound of query item is proven
+                    match last_push {
+                        // lower bound is proven - we have an exact match
+                        _ if query_item.lower_bound() == Some(key.as_slice()) => {}
+
+                        // lower bound is proven - this is the leftmost node
+                        // in the tree
+                        None => {}
+
+                        // lower bound is proven - the preceding tree node
+                        // is lower than the bound
+                        Some(Node::KV(_, _)) | Some(Node::KVDigest(_, _)) => {}
+
+                        // lower bound is not required - the preceding
+                        // entries were skipped by the offset or past the limit
+                        Some(_) if abridged_left && output.is_empty() => {}
 
                         // cannot verify lower bound - we have an abridged
                         // tree so we cannot tell what the preceding key was
@@ -409,7 +1312,11 @@ pub fn verify_query(
                     }
                 }
 
-                if key.as_slice() >= query_item.upper_bound().0 {
+                let past_upper = query_item
+                    .upper_bound()
+                    .0
+                    .is_some_and(|bound| key.as_slice() >= bound);
+                if past_upper {
                     // at or past upper bound of range (or this was an exact
                     // match on a single-key queryitem), advance to next query
                     // item
@@ -424,6 +1331,12 @@ pub fn verify_query(
 
                 // this push matches the queried item
                 if query_item.contains(key) {
+                    // queried nodes must include their value
+                    let value = match node {
+                        Node::KV(_, value) => value,
+                        _ => return Err(Error::MissingData),
+                    };
+
                     // add data to output
                     output.push((key.clone(), value.clone()));
 
@@ -434,9 +1347,16 @@ pub fn verify_query(
                 // continue to next queried item
             }
         } else if in_range {
-            // we encountered a queried range but the proof was abridged (saw a
-            // non-KV push), we are missing some part of the range
-            return Err(Error::MissingData);
+            if !left_to_right && skipped {
+                // the rest of the entries were skipped by the offset of a
+                // reversed query, the rest of the proof is only needed to
+                // compute the root hash
+                done = true;
+            } else {
+                // we encountered a queried range but the proof was abridged
+                // (saw a non-KV push), we are missing some part of the range
+                return Err(Error::MissingData);
+            }
         }
 
         last_push = Some(node.clone());
@@ -444,12 +1364,17 @@ pub fn verify_query(
         Ok(())
     })?;
 
+    // the end of the proof may be abridged if the limit was reached, or the
+    // rest of the entries were skipped by the offset of a reversed query
+    let truncated =
+        done || (left_to_right && limit == Some(output.len())) || (!left_to_right && skipped);
+
     // we have remaining query items, check absence proof against right edge of
     // tree
-    if query.peek().is_some() {
+    if query.peek().is_some() && !truncated {
         match last_push {
             // last node in tree was less than queried item
-            Some(Node::KV(_, _)) => {}
+            Some(Node::KV(_, _)) | Some(Node::KVDigest(_, _)) => {}
 
             // proof contains abridged data so we cannot verify absence of
             // remaining query items
@@ -463,6 +1388,10 @@ pub fn verify_query(
         return Err(Error::HashMismatch(expected_hash, root.hash()?));
     }
 
+    if !left_to_right {
+        output.reverse();
+    }
+
     Ok(output)
 }
 
@@ -472,8 +1401,8 @@ mod test {
     use super::super::encoding::encode_into;
     use super::super::*;
     use super::*;
-    use crate::test_utils::make_tree_seq;
-    use crate::tree::{NoopCommit, PanicSource, RefWalker, Tree};
+    use crate::test_utils::{apply_to_memonly, make_tree_seq, seq_key};
+    use crate::tree::{value_hash, Hasher, NoopCommit, PanicSource, RefWalker, Tree};
 
     fn make_3_node_tree() -> Result<Tree> {
         let mut tree = Tree::new(vec![5], vec![5])?
@@ -500,8 +1429,8 @@ mod test {
         encode_into(proof.iter(), &mut bytes);
 
         let expected_hash = [
-            185, 181, 28, 21, 108, 13, 202, 48, 129, 184, 3, 8, 157, 78, 213, 241, 94, 200, 205,
-            95, 179, 177, 195, 177, 216, 233, 164, 73, 102, 32, 141, 37,
+            105, 26, 31, 34, 190, 254, 217, 207, 35, 179, 151, 43, 57, 196, 121, 245, 93, 242, 227,
+            62, 78, 209, 60, 165, 234, 59, 119, 143, 151, 96, 48, 60,
         ];
 
         let mut query = Query::new();
@@ -583,23 +1512,23 @@ mod test {
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                203, 210, 184, 52, 29, 56, 76, 7, 155, 239, 81, 16, 54, 13, 106, 27, 44, 218, 198,
-                245, 203, 189, 15, 203, 55, 184, 75, 146, 127, 38, 143, 214
+                248, 21, 170, 240, 86, 237, 0, 13, 74, 39, 244, 13, 66, 135, 41, 42, 231, 195, 96,
+                210, 191, 62, 165, 13, 185, 141, 66, 61, 57, 161, 144, 28
             ])))
         );
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::KVHash([
-                169, 4, 73, 65, 62, 49, 160, 159, 37, 166, 195, 249, 63, 31, 23, 11, 169, 0, 24,
-                104, 179, 211, 218, 38, 108, 129, 117, 232, 65, 101, 194, 157
+                44, 134, 84, 82, 88, 52, 136, 190, 9, 122, 157, 190, 152, 247, 14, 154, 52, 21, 25,
+                101, 179, 136, 187, 43, 75, 164, 254, 43, 76, 207, 156, 160
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Parent));
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                219, 24, 98, 131, 160, 47, 139, 94, 223, 118, 217, 187, 42, 215, 213, 101, 213,
-                225, 169, 57, 224, 210, 17, 135, 220, 63, 160, 42, 148, 0, 121, 115
+                159, 116, 150, 211, 179, 159, 32, 196, 67, 52, 175, 60, 190, 223, 155, 221, 245,
+                240, 255, 106, 17, 82, 125, 100, 255, 205, 123, 154, 241, 72, 187, 39
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Child));
@@ -627,8 +1556,8 @@ mod test {
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                203, 210, 184, 52, 29, 56, 76, 7, 155, 239, 81, 16, 54, 13, 106, 27, 44, 218, 198,
-                245, 203, 189, 15, 203, 55, 184, 75, 146, 127, 38, 143, 214
+                248, 21, 170, 240, 86, 237, 0, 13, 74, 39, 244, 13, 66, 135, 41, 42, 231, 195, 96,
+                210, 191, 62, 165, 13, 185, 141, 66, 61, 57, 161, 144, 28
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Push(Node::KV(vec![5], vec![5]))));
@@ -636,8 +1565,8 @@ mod test {
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                219, 24, 98, 131, 160, 47, 139, 94, 223, 118, 217, 187, 42, 215, 213, 101, 213,
-                225, 169, 57, 224, 210, 17, 135, 220, 63, 160, 42, 148, 0, 121, 115
+                159, 116, 150, 211, 179, 159, 32, 196, 67, 52, 175, 60, 190, 223, 155, 221, 245,
+                240, 255, 106, 17, 82, 125, 100, 255, 205, 123, 154, 241, 72, 187, 39
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Child));
@@ -670,16 +1599,16 @@ mod test {
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::KVHash([
-                169, 4, 73, 65, 62, 49, 160, 159, 37, 166, 195, 249, 63, 31, 23, 11, 169, 0, 24,
-                104, 179, 211, 218, 38, 108, 129, 117, 232, 65, 101, 194, 157
+                44, 134, 84, 82, 88, 52, 136, 190, 9, 122, 157, 190, 152, 247, 14, 154, 52, 21, 25,
+                101, 179, 136, 187, 43, 75, 164, 254, 43, 76, 207, 156, 160
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Parent));
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                219, 24, 98, 131, 160, 47, 139, 94, 223, 118, 217, 187, 42, 215, 213, 101, 213,
-                225, 169, 57, 224, 210, 17, 135, 220, 63, 160, 42, 148, 0, 121, 115
+                159, 116, 150, 211, 179, 159, 32, 196, 67, 52, 175, 60, 190, 223, 155, 221, 245,
+                240, 255, 106, 17, 82, 125, 100, 255, 205, 123, 154, 241, 72, 187, 39
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Child));
@@ -712,8 +1641,8 @@ mod test {
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::KVHash([
-                169, 4, 73, 65, 62, 49, 160, 159, 37, 166, 195, 249, 63, 31, 23, 11, 169, 0, 24,
-                104, 179, 211, 218, 38, 108, 129, 117, 232, 65, 101, 194, 157
+                44, 134, 84, 82, 88, 52, 136, 190, 9, 122, 157, 190, 152, 247, 14, 154, 52, 21, 25,
+                101, 179, 136, 187, 43, 75, 164, 254, 43, 76, 207, 156, 160
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Parent));
@@ -784,19 +1713,25 @@ mod test {
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                203, 210, 184, 52, 29, 56, 76, 7, 155, 239, 81, 16, 54, 13, 106, 27, 44, 218, 198,
-                245, 203, 189, 15, 203, 55, 184, 75, 146, 127, 38, 143, 214
+                248, 21, 170, 240, 86, 237, 0, 13, 74, 39, 244, 13, 66, 135, 41, 42, 231, 195, 96,
+                210, 191, 62, 165, 13, 185, 141, 66, 61, 57, 161, 144, 28
             ])))
         );
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::KVHash([
-                169, 4, 73, 65, 62, 49, 160, 159, 37, 166, 195, 249, 63, 31, 23, 11, 169, 0, 24,
-                104, 179, 211, 218, 38, 108, 129, 117, 232, 65, 101, 194, 157
+                44, 134, 84, 82, 88, 52, 136, 190, 9, 122, 157, 190, 152, 247, 14, 154, 52, 21, 25,
+                101, 179, 136, 187, 43, 75, 164, 254, 43, 76, 207, 156, 160
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Parent));
-        assert_eq!(iter.next(), Some(&Op::Push(Node::KV(vec![7], vec![7]))));
+        assert_eq!(
+            iter.next(),
+            Some(&Op::Push(Node::KVDigest(
+                vec![7],
+                value_hash::<Hasher>(&[7])
+            )))
+        );
         assert_eq!(iter.next(), Some(&Op::Child));
         assert!(iter.next().is_none());
         assert_eq!(absence, (false, true));
@@ -826,13 +1761,25 @@ mod test {
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                203, 210, 184, 52, 29, 56, 76, 7, 155, 239, 81, 16, 54, 13, 106, 27, 44, 218, 198,
-                245, 203, 189, 15, 203, 55, 184, 75, 146, 127, 38, 143, 214
+                248, 21, 170, 240, 86, 237, 0, 13, 74, 39, 244, 13, 66, 135, 41, 42, 231, 195, 96,
+                210, 191, 62, 165, 13, 185, 141, 66, 61, 57, 161, 144, 28
             ])))
         );
-        assert_eq!(iter.next(), Some(&Op::Push(Node::KV(vec![5], vec![5]))));
+        assert_eq!(
+            iter.next(),
+            Some(&Op::Push(Node::KVDigest(
+                vec![5],
+                value_hash::<Hasher>(&[5])
+            )))
+        );
         assert_eq!(iter.next(), Some(&Op::Parent));
-        assert_eq!(iter.next(), Some(&Op::Push(Node::KV(vec![7], vec![7]))));
+        assert_eq!(
+            iter.next(),
+            Some(&Op::Push(Node::KVDigest(
+                vec![7],
+                value_hash::<Hasher>(&[7])
+            )))
+        );
         assert_eq!(iter.next(), Some(&Op::Child));
         assert!(iter.next().is_none());
         assert_eq!(absence, (false, false));
@@ -911,16 +1858,16 @@ mod test {
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::KVHash([
-                169, 4, 73, 65, 62, 49, 160, 159, 37, 166, 195, 249, 63, 31, 23, 11, 169, 0, 24,
-                104, 179, 211, 218, 38, 108, 129, 117, 232, 65, 101, 194, 157
+                44, 134, 84, 82, 88, 52, 136, 190, 9, 122, 157, 190, 152, 247, 14, 154, 52, 21, 25,
+                101, 179, 136, 187, 43, 75, 164, 254, 43, 76, 207, 156, 160
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Parent));
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                148, 241, 151, 144, 247, 220, 92, 79, 70, 252, 168, 222, 27, 218, 53, 156, 0, 136,
-                161, 107, 83, 78, 150, 246, 51, 230, 164, 248, 17, 30, 147, 91
+                12, 84, 132, 104, 177, 178, 18, 38, 152, 33, 211, 165, 42, 227, 67, 14, 135, 156,
+                14, 85, 37, 68, 79, 33, 126, 252, 183, 187, 102, 85, 158, 2
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Child));
@@ -933,10 +1880,10 @@ mod test {
             bytes,
             vec![
                 3, 1, 1, 0, 1, 1, 3, 1, 2, 0, 1, 2, 16, 3, 1, 3, 0, 1, 3, 3, 1, 4, 0, 1, 4, 16, 17,
-                2, 169, 4, 73, 65, 62, 49, 160, 159, 37, 166, 195, 249, 63, 31, 23, 11, 169, 0, 24,
-                104, 179, 211, 218, 38, 108, 129, 117, 232, 65, 101, 194, 157, 16, 1, 148, 241,
-                151, 144, 247, 220, 92, 79, 70, 252, 168, 222, 27, 218, 53, 156, 0, 136, 161, 107,
-                83, 78, 150, 246, 51, 230, 164, 248, 17, 30, 147, 91, 17
+                2, 44, 134, 84, 82, 88, 52, 136, 190, 9, 122, 157, 190, 152, 247, 14, 154, 52, 21,
+                25, 101, 179, 136, 187, 43, 75, 164, 254, 43, 76, 207, 156, 160, 16, 1, 12, 84,
+                132, 104, 177, 178, 18, 38, 152, 33, 211, 165, 42, 227, 67, 14, 135, 156, 14, 85,
+                37, 68, 79, 33, 126, 252, 183, 187, 102, 85, 158, 2, 17
             ]
         );
 
@@ -979,6 +1926,16 @@ mod test {
         );
         assert!(QueryItem::Range(vec![15]..vec![25]) == QueryItem::Range(vec![20]..vec![30]));
         assert!(QueryItem::Range(vec![20]..vec![30]) > QueryItem::Range(vec![10]..vec![20]));
+
+        assert!(QueryItem::Key(vec![10]) < QueryItem::RangeFrom(vec![20]..));
+        assert!(QueryItem::Key(vec![20]) == QueryItem::RangeFrom(vec![20]..));
+        assert!(QueryItem::Key(vec![255]) == QueryItem::RangeFrom(vec![20]..));
+        assert!(QueryItem::Key(vec![0]) == QueryItem::RangeTo(..vec![20]));
+        assert!(QueryItem::Key(vec![20]) > QueryItem::RangeTo(..vec![20]));
+        assert!(QueryItem::RangeTo(..vec![20]) < QueryItem::RangeFrom(vec![20]..));
+        assert!(QueryItem::RangeTo(..vec![21]) == QueryItem::RangeFrom(vec![20]..));
+        assert!(QueryItem::Range(vec![10]..vec![20]) < QueryItem::RangeFrom(vec![20]..));
+        assert!(QueryItem::Range(vec![10]..vec![20]) == QueryItem::RangeTo(..vec![15]));
     }
 
     #[test]
@@ -1004,6 +1961,224 @@ mod test {
             mine.merge(other),
             QueryItem::RangeInclusive(vec![1]..=vec![10])
         );
+
+        let mine = QueryItem::RangeFrom(vec![10]..);
+        let other = QueryItem::RangeInclusive(vec![5]..=vec![20]);
+        let merged = mine.merge(other);
+        assert_eq!(merged, QueryItem::RangeFrom(vec![5]..));
+        assert_eq!(merged.upper_bound().0, None);
+
+        let mine = QueryItem::RangeTo(..vec![10]);
+        let other = QueryItem::Range(vec![5]..vec![20]);
+        let merged = mine.merge(other);
+        assert_eq!(merged, QueryItem::RangeTo(..vec![20]));
+        assert_eq!(merged.lower_bound(), None);
+
+        let mine = QueryItem::RangeTo(..vec![10]);
+        let other = QueryItem::RangeInclusive(vec![5]..=vec![20]);
+        let merged = mine.merge(other);
+        assert_eq!(merged, QueryItem::RangeToInclusive(..=vec![20]));
+        assert_eq!(merged.lower_bound(), None);
+        assert_eq!(merged.upper_bound(), (Some(&[20][..]), true));
+    }
+
+    #[test]
+    fn query_item_contains_unbounded() {
+        let item = QueryItem::RangeFrom(vec![10]..);
+        assert!(!item.contains(&[9]));
+        assert!(item.contains(&[10]));
+        assert!(item.contains(&[255, 255]));
+
+        let item = QueryItem::RangeTo(..vec![10]);
+        assert!(item.contains(&[]));
+        assert!(item.contains(&[9, 255]));
+        assert!(!item.contains(&[10]));
+
+        let item = QueryItem::RangeFull(..);
+        assert!(item.contains(&[]));
+        assert!(item.contains(&[10]));
+        assert!(item.contains(&[255, 255]));
+    }
+
+    #[test]
+    fn query_item_merge_range_full() {
+        let merged = QueryItem::RangeFrom(vec![10]..).merge(QueryItem::RangeTo(..vec![20]));
+        assert_eq!(merged, QueryItem::RangeFull(..));
+        assert_eq!(merged.lower_bound(), None);
+        assert_eq!(merged.upper_bound(), (None, true));
+
+        let mut query = Query::new();
+        query.insert_key(vec![5]);
+        query.insert_range(vec![10]..vec![20]);
+        query.insert_range_full();
+        query.insert_key(vec![30]);
+        assert_eq!(
+            Vec::<QueryItem>::from(query),
+            vec![QueryItem::RangeFull(..)]
+        );
+    }
+
+    #[test]
+    fn query_merge() {
+        let mut query = Query::new();
+        assert!(query.is_empty());
+        query.insert_range(vec![3]..vec![8]);
+
+        let mut other = Query::new();
+        other.insert_range(vec![1]..vec![5]);
+
+        query.merge(other);
+        assert!(!query.is_empty());
+        let items = Vec::<QueryItem>::from(query);
+        assert_eq!(items.len(), 1);
+        assert!(matches!(&items[0], QueryItem::Range(range) if range == &(vec![1]..vec![8])));
+    }
+
+    #[test]
+    fn query_extend() {
+        let mut query = Query::new();
+        query.insert_key(vec![10]);
+        query.extend(vec![
+            QueryItem::Range(vec![2]..vec![4]),
+            QueryItem::RangeInclusive(vec![3]..=vec![5]),
+            QueryItem::Key(vec![10]),
+        ]);
+        assert_eq!(
+            Vec::<QueryItem>::from(query),
+            vec![
+                QueryItem::RangeInclusive(vec![2]..=vec![5]),
+                QueryItem::Key(vec![10]),
+            ]
+        );
+    }
+
+    #[test]
+    fn query_contains_key() {
+        let mut query = Query::new();
+        query.insert_key(vec![2]);
+        query.insert_range(vec![5]..vec![8]);
+        query.insert_range_inclusive(vec![10]..=vec![12]);
+
+        assert!(query.contains_key(&[2]));
+        assert!(!query.contains_key(&[2, 0]));
+        assert!(!query.contains_key(&[1]));
+
+        assert!(!query.contains_key(&[4]));
+        assert!(query.contains_key(&[5]));
+        assert!(query.contains_key(&[7, 255]));
+        assert!(!query.contains_key(&[8]));
+
+        assert!(!query.contains_key(&[9, 255]));
+        assert!(query.contains_key(&[10]));
+        assert!(query.contains_key(&[12]));
+        assert!(!query.contains_key(&[12, 0]));
+
+        assert!(!Query::new().contains_key(&[2]));
+    }
+
+    #[test]
+    fn query_remove_key() {
+        let mut query = Query::new();
+        query.insert_key(vec![2]);
+        query.insert_range(vec![5]..vec![8]);
+
+        assert!(query.remove_key(&[6]));
+        let items = Vec::<QueryItem>::from(query);
+        assert_eq!(items.len(), 3);
+        assert!(matches!(&items[0], QueryItem::Key(key) if key == &vec![2]));
+        assert!(matches!(&items[1], QueryItem::Range(range) if range == &(vec![5]..vec![6])));
+        assert!(matches!(&items[2], QueryItem::Range(range) if range == &(vec![6, 0]..vec![8])));
+
+        let mut query = Query::new();
+        query.insert_key(vec![2]);
+        query.insert_range(vec![5]..vec![8]);
+
+        assert!(query.remove_key(&[2]));
+        assert!(!query.remove_key(&[2]));
+        assert!(!query.remove_key(&[8]));
+        assert!(!query.contains_key(&[2]));
+        assert!(query.contains_key(&[5]));
+        assert_eq!(query.len(), 1);
+
+        assert!(query.remove_key(&[5]));
+        assert!(!query.contains_key(&[5]));
+        assert!(query.contains_key(&[5, 0]));
+        assert_eq!(query.len(), 1);
+    }
+
+    #[test]
+    fn query_remove_range() {
+        let mut query = Query::new();
+        query.insert_key(vec![2]);
+        query.insert_range_inclusive(vec![5]..=vec![8]);
+        query.insert_range_from(vec![10]..);
+
+        assert!(!query.remove_range(&(vec![3]..vec![4])));
+        assert!(query.remove_range(&(vec![6]..vec![11])));
+
+        let items = Vec::<QueryItem>::from(query);
+        assert_eq!(items.len(), 3);
+        assert!(matches!(&items[0], QueryItem::Key(key) if key == &vec![2]));
+        assert!(matches!(&items[1], QueryItem::Range(range) if range == &(vec![5]..vec![6])));
+        assert!(matches!(&items[2], QueryItem::RangeFrom(range) if range == &(vec![11]..)));
+    }
+
+    #[test]
+    fn query_encoding_roundtrip() {
+        let mut query = Query::new();
+        query.insert_key(vec![1]);
+        query.insert_key(vec![]);
+        query.insert_range(vec![3]..vec![5, 5]);
+        query.insert_range_inclusive(vec![7]..=vec![8]);
+        query.insert_range_from(vec![20; 300]..);
+        query.set_limit(10);
+        query.set_offset(2);
+        query.set_reverse();
+
+        let bytes = query.encode().unwrap();
+        assert_eq!(bytes.len(), Encode::encoding_length(&query).unwrap());
+
+        let decoded = Query::decode(bytes.as_slice()).unwrap();
+        assert_eq!(decoded.limit(), Some(10));
+        assert_eq!(decoded.offset(), Some(2));
+        assert!(!decoded.left_to_right());
+        assert_eq!(format!("{:?}", decoded.items), format!("{:?}", query.items));
+
+        let mut query = Query::new();
+        query.insert_range_to(..vec![5]);
+        query.insert_key(vec![9]);
+        let decoded = Query::decode(query.encode().unwrap().as_slice()).unwrap();
+        assert_eq!(decoded.limit(), None);
+        assert_eq!(decoded.offset(), None);
+        assert!(decoded.left_to_right());
+        assert_eq!(format!("{:?}", decoded.items), format!("{:?}", query.items));
+
+        let mut query = Query::new();
+        query.insert_range_to_inclusive(..=vec![5]);
+        query.insert_key(vec![9]);
+        let decoded = Query::decode(query.encode().unwrap().as_slice()).unwrap();
+        assert_eq!(format!("{:?}", decoded.items), format!("{:?}", query.items));
+
+        let mut query = Query::new();
+        query.insert_range_full();
+        let decoded = Query::decode(query.encode().unwrap().as_slice()).unwrap();
+        assert_eq!(format!("{:?}", decoded.items), format!("{:?}", query.items));
+    }
+
+    #[test]
+    fn query_encode_long_bound() {
+        let mut query = Query::new();
+        query.insert_key(vec![1; u16::MAX as usize]);
+        assert!(query.encode().is_ok());
+
+        query.insert_range_from(vec![2; u16::MAX as usize + 1]..);
+        assert!(query.encode().is_err());
+    }
+
+    #[test]
+    fn query_decode_invalid_item() {
+        let bytes = [0, 0, 0, 1, 0x07];
+        assert!(Query::decode(&bytes).is_err());
     }
 
     #[test]
@@ -1040,23 +2215,23 @@ mod test {
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                131, 182, 249, 107, 5, 43, 253, 172, 175, 5, 92, 100, 112, 7, 61, 179, 216, 127,
-                180, 104, 127, 239, 76, 175, 20, 208, 82, 101, 163, 177, 107, 229
+                105, 67, 163, 184, 80, 66, 201, 21, 34, 182, 26, 176, 95, 63, 125, 133, 215, 154,
+                113, 177, 159, 79, 168, 115, 52, 254, 179, 182, 38, 69, 70, 136
             ])))
         );
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::KVHash([
-                252, 83, 231, 211, 74, 65, 100, 80, 251, 110, 182, 76, 90, 44, 213, 30, 241, 239,
-                2, 5, 216, 202, 184, 130, 47, 53, 146, 68, 179, 22, 45, 30
+                187, 8, 238, 72, 221, 212, 11, 228, 138, 85, 234, 252, 107, 67, 53, 107, 220, 106,
+                176, 188, 47, 21, 203, 96, 223, 4, 72, 123, 42, 160, 214, 183
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Parent));
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                71, 142, 184, 184, 188, 130, 2, 241, 17, 17, 179, 82, 112, 27, 31, 20, 92, 69, 145,
-                176, 112, 235, 30, 16, 54, 157, 64, 114, 154, 54, 63, 253
+                143, 120, 241, 185, 190, 135, 216, 63, 33, 99, 75, 209, 69, 71, 37, 193, 142, 56,
+                215, 133, 22, 232, 150, 224, 141, 24, 199, 39, 160, 245, 100, 144
             ])))
         );
         assert_eq!(
@@ -1077,17 +2252,17 @@ mod test {
         assert_eq!(iter.next(), Some(&Op::Child));
         assert_eq!(
             iter.next(),
-            Some(&Op::Push(Node::KV(
+            Some(&Op::Push(Node::KVDigest(
                 vec![0, 0, 0, 0, 0, 0, 0, 7],
-                vec![123; 60]
+                value_hash::<Hasher>(&[123; 60])
             )))
         );
         assert_eq!(iter.next(), Some(&Op::Parent));
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                150, 100, 68, 82, 53, 2, 5, 199, 230, 152, 77, 216, 114, 30, 205, 210, 226, 140,
-                161, 62, 235, 10, 116, 142, 115, 201, 56, 218, 44, 151, 86, 154
+                98, 204, 116, 32, 183, 230, 113, 112, 69, 208, 69, 69, 17, 56, 230, 34, 211, 182,
+                88, 108, 156, 152, 167, 118, 110, 159, 131, 248, 113, 179, 228, 103
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Child));
@@ -1127,23 +2302,23 @@ mod test {
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                131, 182, 249, 107, 5, 43, 253, 172, 175, 5, 92, 100, 112, 7, 61, 179, 216, 127,
-                180, 104, 127, 239, 76, 175, 20, 208, 82, 101, 163, 177, 107, 229
+                105, 67, 163, 184, 80, 66, 201, 21, 34, 182, 26, 176, 95, 63, 125, 133, 215, 154,
+                113, 177, 159, 79, 168, 115, 52, 254, 179, 182, 38, 69, 70, 136
             ])))
         );
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::KVHash([
-                252, 83, 231, 211, 74, 65, 100, 80, 251, 110, 182, 76, 90, 44, 213, 30, 241, 239,
-                2, 5, 216, 202, 184, 130, 47, 53, 146, 68, 179, 22, 45, 30
+                187, 8, 238, 72, 221, 212, 11, 228, 138, 85, 234, 252, 107, 67, 53, 107, 220, 106,
+                176, 188, 47, 21, 203, 96, 223, 4, 72, 123, 42, 160, 214, 183
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Parent));
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                71, 142, 184, 184, 188, 130, 2, 241, 17, 17, 179, 82, 112, 27, 31, 20, 92, 69, 145,
-                176, 112, 235, 30, 16, 54, 157, 64, 114, 154, 54, 63, 253
+                143, 120, 241, 185, 190, 135, 216, 63, 33, 99, 75, 209, 69, 71, 37, 193, 142, 56,
+                215, 133, 22, 232, 150, 224, 141, 24, 199, 39, 160, 245, 100, 144
             ])))
         );
         assert_eq!(
@@ -1173,8 +2348,8 @@ mod test {
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                150, 100, 68, 82, 53, 2, 5, 199, 230, 152, 77, 216, 114, 30, 205, 210, 226, 140,
-                161, 62, 235, 10, 116, 142, 115, 201, 56, 218, 44, 151, 86, 154
+                98, 204, 116, 32, 183, 230, 113, 112, 69, 208, 69, 69, 17, 56, 230, 34, 211, 182,
+                88, 108, 156, 152, 167, 118, 110, 159, 131, 248, 113, 179, 228, 103
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Child));
@@ -1215,23 +2390,23 @@ mod test {
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                131, 182, 249, 107, 5, 43, 253, 172, 175, 5, 92, 100, 112, 7, 61, 179, 216, 127,
-                180, 104, 127, 239, 76, 175, 20, 208, 82, 101, 163, 177, 107, 229
+                105, 67, 163, 184, 80, 66, 201, 21, 34, 182, 26, 176, 95, 63, 125, 133, 215, 154,
+                113, 177, 159, 79, 168, 115, 52, 254, 179, 182, 38, 69, 70, 136
             ])))
         );
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::KVHash([
-                252, 83, 231, 211, 74, 65, 100, 80, 251, 110, 182, 76, 90, 44, 213, 30, 241, 239,
-                2, 5, 216, 202, 184, 130, 47, 53, 146, 68, 179, 22, 45, 30
+                187, 8, 238, 72, 221, 212, 11, 228, 138, 85, 234, 252, 107, 67, 53, 107, 220, 106,
+                176, 188, 47, 21, 203, 96, 223, 4, 72, 123, 42, 160, 214, 183
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Parent));
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                71, 142, 184, 184, 188, 130, 2, 241, 17, 17, 179, 82, 112, 27, 31, 20, 92, 69, 145,
-                176, 112, 235, 30, 16, 54, 157, 64, 114, 154, 54, 63, 253
+                143, 120, 241, 185, 190, 135, 216, 63, 33, 99, 75, 209, 69, 71, 37, 193, 142, 56,
+                215, 133, 22, 232, 150, 224, 141, 24, 199, 39, 160, 245, 100, 144
             ])))
         );
         assert_eq!(
@@ -1252,17 +2427,17 @@ mod test {
         assert_eq!(iter.next(), Some(&Op::Child));
         assert_eq!(
             iter.next(),
-            Some(&Op::Push(Node::KV(
+            Some(&Op::Push(Node::KVDigest(
                 vec![0, 0, 0, 0, 0, 0, 0, 7],
-                vec![123; 60]
+                value_hash::<Hasher>(&[123; 60])
             )))
         );
         assert_eq!(iter.next(), Some(&Op::Parent));
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                150, 100, 68, 82, 53, 2, 5, 199, 230, 152, 77, 216, 114, 30, 205, 210, 226, 140,
-                161, 62, 235, 10, 116, 142, 115, 201, 56, 218, 44, 151, 86, 154
+                98, 204, 116, 32, 183, 230, 113, 112, 69, 208, 69, 69, 17, 56, 230, 34, 211, 182,
+                88, 108, 156, 152, 167, 118, 110, 159, 131, 248, 113, 179, 228, 103
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Child));
@@ -1303,30 +2478,30 @@ mod test {
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                131, 182, 249, 107, 5, 43, 253, 172, 175, 5, 92, 100, 112, 7, 61, 179, 216, 127,
-                180, 104, 127, 239, 76, 175, 20, 208, 82, 101, 163, 177, 107, 229
+                105, 67, 163, 184, 80, 66, 201, 21, 34, 182, 26, 176, 95, 63, 125, 133, 215, 154,
+                113, 177, 159, 79, 168, 115, 52, 254, 179, 182, 38, 69, 70, 136
             ])))
         );
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::KVHash([
-                252, 83, 231, 211, 74, 65, 100, 80, 251, 110, 182, 76, 90, 44, 213, 30, 241, 239,
-                2, 5, 216, 202, 184, 130, 47, 53, 146, 68, 179, 22, 45, 30
+                187, 8, 238, 72, 221, 212, 11, 228, 138, 85, 234, 252, 107, 67, 53, 107, 220, 106,
+                176, 188, 47, 21, 203, 96, 223, 4, 72, 123, 42, 160, 214, 183
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Parent));
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                71, 142, 184, 184, 188, 130, 2, 241, 17, 17, 179, 82, 112, 27, 31, 20, 92, 69, 145,
-                176, 112, 235, 30, 16, 54, 157, 64, 114, 154, 54, 63, 253
+                143, 120, 241, 185, 190, 135, 216, 63, 33, 99, 75, 209, 69, 71, 37, 193, 142, 56,
+                215, 133, 22, 232, 150, 224, 141, 24, 199, 39, 160, 245, 100, 144
             ])))
         );
         assert_eq!(
             iter.next(),
-            Some(&Op::Push(Node::KV(
+            Some(&Op::Push(Node::KVDigest(
                 vec![0, 0, 0, 0, 0, 0, 0, 5],
-                vec![123; 60]
+                value_hash::<Hasher>(&[123; 60])
             )))
         );
         assert_eq!(iter.next(), Some(&Op::Parent));
@@ -1340,17 +2515,17 @@ mod test {
         assert_eq!(iter.next(), Some(&Op::Child));
         assert_eq!(
             iter.next(),
-            Some(&Op::Push(Node::KV(
+            Some(&Op::Push(Node::KVDigest(
                 vec![0, 0, 0, 0, 0, 0, 0, 7],
-                vec![123; 60]
+                value_hash::<Hasher>(&[123; 60])
             )))
         );
         assert_eq!(iter.next(), Some(&Op::Parent));
         assert_eq!(
             iter.next(),
             Some(&Op::Push(Node::Hash([
-                150, 100, 68, 82, 53, 2, 5, 199, 230, 152, 77, 216, 114, 30, 205, 210, 226, 140,
-                161, 62, 235, 10, 116, 142, 115, 201, 56, 218, 44, 151, 86, 154
+                98, 204, 116, 32, 183, 230, 113, 112, 69, 208, 69, 69, 17, 56, 230, 34, 211, 182,
+                88, 108, 156, 152, 167, 118, 110, 159, 131, 248, 113, 179, 228, 103
             ])))
         );
         assert_eq!(iter.next(), Some(&Op::Child));
@@ -1369,17 +2544,305 @@ mod test {
     }
 
     #[test]
-    fn query_from_vec() {
-        let queryitems = vec![QueryItem::Range(
-            vec![0, 0, 0, 0, 0, 0, 0, 5, 5]..vec![0, 0, 0, 0, 0, 0, 0, 7],
-        )];
-        let query = Query::from(queryitems);
+    fn range_from_proof() {
+        let mut tree = make_tree_seq(10);
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
 
-        let mut expected = BTreeSet::new();
-        expected.insert(QueryItem::Range(
-            vec![0, 0, 0, 0, 0, 0, 0, 5, 5]..vec![0, 0, 0, 0, 0, 0, 0, 7],
-        ));
-        assert_eq!(query.items, expected);
+        let queryitems = vec![QueryItem::RangeFrom(vec![0, 0, 0, 0, 0, 0, 0, 5]..)];
+        let (proof, absence) = walker
+            .create_proof(queryitems.as_slice())
+            .expect("create_proof errored");
+        assert_eq!(absence, (false, true));
+
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+        let mut query = Query::new();
+        for item in queryitems {
+            query.insert_item(item);
+        }
+        let res = verify_query(bytes.as_slice(), &query, tree.hash()).unwrap();
+        assert_eq!(
+            res,
+            (5..10)
+                .map(|i| (vec![0, 0, 0, 0, 0, 0, 0, i], vec![123; 60]))
+                .collect::<Vec<_>>()
+        );
+
+        // the right edge of the tree is proven, so the map can iterate to it
+        let map = verify(bytes.as_slice(), tree.hash()).unwrap();
+        let start = [0, 0, 0, 0, 0, 0, 0, 5];
+        let entries = map.range(&start[..]..).collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries.last().unwrap().0, &[0, 0, 0, 0, 0, 0, 0, 9]);
+    }
+
+    #[test]
+    fn range_to_proof() {
+        let mut tree = make_tree_seq(10);
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let queryitems = vec![QueryItem::RangeTo(..vec![0, 0, 0, 0, 0, 0, 0, 3])];
+        let (proof, absence) = walker
+            .create_proof(queryitems.as_slice())
+            .expect("create_proof errored");
+        assert_eq!(absence, (true, false));
+
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+        let mut query = Query::new();
+        for item in queryitems {
+            query.insert_item(item);
+        }
+        let res = verify_query(bytes.as_slice(), &query, tree.hash()).unwrap();
+        assert_eq!(
+            res,
+            vec![
+                (vec![0, 0, 0, 0, 0, 0, 0, 0], vec![123; 60]),
+                (vec![0; 20], vec![123; 60]),
+                (vec![0, 0, 0, 0, 0, 0, 0, 1], vec![123; 60]),
+                (vec![0, 0, 0, 0, 0, 0, 0, 2], vec![123; 60]),
+            ]
+        );
+    }
+
+    #[test]
+    fn range_full_proof() {
+        let mut tree = make_tree_seq(10);
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let queryitems = vec![QueryItem::RangeFull(..)];
+        let (proof, absence) = walker
+            .create_proof(queryitems.as_slice())
+            .expect("create_proof errored");
+        assert_eq!(absence, (true, true));
+        assert!(proof
+            .iter()
+            .all(|op| !matches!(op, Op::Push(Node::Hash(_)) | Op::Push(Node::KVHash(_)))));
+
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+        let map = verify(bytes.as_slice(), tree.hash()).unwrap();
+        let entries = map.range(..).collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(entries.len(), 11);
+        assert_eq!(entries[0].0, &[0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(entries[1].0, &[0; 20]);
+        assert_eq!(entries[10].0, &[0, 0, 0, 0, 0, 0, 0, 9]);
+    }
+
+    #[test]
+    fn limited_range_proof() {
+        let mut tree = make_tree_seq(10);
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let queryitems = vec![QueryItem::RangeFrom(vec![0, 0, 0, 0, 0, 0, 0, 2]..)];
+        let (proof, absence, limit, _) = walker
+            .create_full_proof(queryitems.as_slice(), Some(3), None, true)
+            .expect("create_proof errored");
+        assert_eq!(absence, (false, false));
+        assert_eq!(limit, Some(0));
+
+        let kv_count = proof
+            .iter()
+            .filter(|op| matches!(op, Op::Push(Node::KV(..))))
+            .count();
+        assert_eq!(kv_count, 3);
+
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+        let mut query = Query::new();
+        for item in queryitems {
+            query.insert_item(item);
+        }
+        query.set_limit(3);
+        let res = verify_query(bytes.as_slice(), &query, tree.hash()).unwrap();
+        assert_eq!(
+            res,
+            (2..5)
+                .map(|i| (vec![0, 0, 0, 0, 0, 0, 0, i], vec![123; 60]))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn unreached_limit_proof() {
+        let mut tree = make_tree_seq(10);
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let queryitems = vec![QueryItem::RangeFrom(vec![0, 0, 0, 0, 0, 0, 0, 8]..)];
+        let (proof, absence, limit, _) = walker
+            .create_full_proof(queryitems.as_slice(), Some(5), None, true)
+            .expect("create_proof errored");
+        assert_eq!(absence, (false, true));
+        assert_eq!(limit, Some(3));
+
+        let (unlimited_proof, ..) = walker
+            .create_proof(queryitems.as_slice())
+            .expect("create_proof errored");
+        assert_eq!(proof, unlimited_proof);
+    }
+
+    #[test]
+    fn offset_range_proof() {
+        let mut tree = make_tree_seq(10);
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let queryitems = vec![QueryItem::RangeFrom(vec![0, 0, 0, 0, 0, 0, 0, 2]..)];
+        let (proof, absence, limit, offset) = walker
+            .create_full_proof(queryitems.as_slice(), Some(2), Some(3), true)
+            .expect("create_proof errored");
+        assert_eq!(absence, (false, false));
+        assert_eq!(limit, Some(0));
+        assert_eq!(offset, Some(0));
+
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+        let map = verify(bytes.as_slice(), tree.hash()).unwrap();
+        assert!(map.get(&[0, 0, 0, 0, 0, 0, 0, 4]).is_err());
+        assert_eq!(
+            map.get(&[0, 0, 0, 0, 0, 0, 0, 5]).unwrap(),
+            Some(&[123; 60][..])
+        );
+
+        let mut query = Query::new();
+        for item in queryitems {
+            query.insert_item(item);
+        }
+        query.set_offset(3);
+        query.set_limit(2);
+        let res = verify_query(bytes.as_slice(), &query, tree.hash()).unwrap();
+        assert_eq!(
+            res,
+            (5..7)
+                .map(|i| (vec![0, 0, 0, 0, 0, 0, 0, i], vec![123; 60]))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn reverse_range_proof() {
+        let mut tree = make_tree_seq(10);
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let queryitems = vec![QueryItem::RangeFrom(vec![0, 0, 0, 0, 0, 0, 0, 2]..)];
+        let (proof, absence, ..) = walker
+            .create_full_proof(queryitems.as_slice(), None, None, false)
+            .expect("create_proof errored");
+        assert_eq!(absence, (false, true));
+
+        let (forward_proof, ..) = walker
+            .create_proof(queryitems.as_slice())
+            .expect("create_proof errored");
+        assert_eq!(proof, forward_proof);
+
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+        let mut query = Query::new();
+        for item in queryitems {
+            query.insert_item(item);
+        }
+        query.set_reverse();
+        let res = verify_query(bytes.as_slice(), &query, tree.hash()).unwrap();
+        assert_eq!(
+            res,
+            (2..10)
+                .rev()
+                .map(|i| (vec![0, 0, 0, 0, 0, 0, 0, i], vec![123; 60]))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn reverse_limited_range_proof() {
+        let mut tree = make_tree_seq(10);
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let queryitems = vec![QueryItem::Range(
+            vec![0, 0, 0, 0, 0, 0, 0, 2]..vec![0, 0, 0, 0, 0, 0, 0, 9],
+        )];
+        let (proof, _, limit, offset) = walker
+            .create_full_proof(queryitems.as_slice(), Some(3), Some(1), false)
+            .expect("create_proof errored");
+        assert_eq!(limit, Some(0));
+        assert_eq!(offset, Some(0));
+
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+        let map = verify(bytes.as_slice(), tree.hash()).unwrap();
+        let entries = map
+            .range(&[0, 0, 0, 0, 0, 0, 0, 5][..]..=&[0, 0, 0, 0, 0, 0, 0, 7][..])
+            .rev()
+            .take(3)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            entries.iter().map(|(key, _)| key[7]).collect::<Vec<_>>(),
+            vec![7, 6, 5]
+        );
+        assert!(map.get(&[0, 0, 0, 0, 0, 0, 0, 4]).is_err());
+        assert!(map.get(&[0, 0, 0, 0, 0, 0, 0, 8]).is_err());
+
+        let mut query = Query::new();
+        for item in queryitems {
+            query.insert_item(item);
+        }
+        query.set_reverse();
+        query.set_limit(3);
+        query.set_offset(1);
+        let res = verify_query(bytes.as_slice(), &query, tree.hash()).unwrap();
+        assert_eq!(
+            res,
+            (5..8)
+                .rev()
+                .map(|i| (vec![0, 0, 0, 0, 0, 0, 0, i], vec![123; 60]))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn counted_proof() {
+        let mut tree = make_tree_seq(10);
+        let root_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let mut query = Query::new();
+        query.insert_range_full();
+        let queryitems: Vec<QueryItem> = query.iter().cloned().collect();
+
+        let (proof, ..) = walker
+            .create_proof(queryitems.as_slice())
+            .expect("create_proof errored");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+        let (map, count) = execute_proof_counted(bytes.as_slice(), &query, root_hash).unwrap();
+        assert_eq!(count, 11);
+        assert_eq!(map.range(..).count(), 11);
+
+        // a proof which includes more entries than the limit is rejected
+        query.set_limit(4);
+        assert!(execute_proof_counted(bytes.as_slice(), &query, root_hash).is_err());
+
+        let (proof, ..) = walker
+            .create_full_proof(queryitems.as_slice(), Some(4), None, true)
+            .expect("create_proof errored");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+        let (_, count) = execute_proof_counted(bytes.as_slice(), &query, root_hash).unwrap();
+        assert_eq!(count, 4);
+        assert_eq!(Some(count), query.limit());
+    }
+
+    #[test]
+    fn query_from_vec() {
+        let queryitems = vec![QueryItem::Range(
+            vec![0, 0, 0, 0, 0, 0, 0, 5, 5]..vec![0, 0, 0, 0, 0, 0, 0, 7],
+        )];
+        let query = Query::from(queryitems);
+
+        let mut expected = BTreeSet::new();
+        expected.insert(QueryItem::Range(
+            vec![0, 0, 0, 0, 0, 0, 0, 5, 5]..vec![0, 0, 0, 0, 0, 0, 0, 7],
+        ));
+        assert_eq!(query.items, expected);
     }
 
     #[test]
@@ -1477,4 +2940,464 @@ mod test {
 
         let _result = verify_query(bytes.as_slice(), &query, [42; 32]).expect("verify failed");
     }
+
+    #[test]
+    fn boundary_nodes_omit_values() {
+        let batch: Vec<_> = (0..10)
+            .map(|i| (seq_key(i), crate::tree::Op::Put(vec![i as u8; 10_000])))
+            .collect();
+        let mut tree = apply_to_memonly(None, &batch).unwrap();
+        let root_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let mut query = Query::new();
+        query.insert_range(seq_key(4)..seq_key(6));
+        query.insert_key(vec![0, 0, 0, 0, 0, 0, 0, 7, 1]);
+        let queryitems: Vec<QueryItem> = query.iter().cloned().collect();
+        let (proof, _) = walker
+            .create_proof(queryitems.as_slice())
+            .expect("create_proof errored");
+
+        let mut digest_keys = vec![];
+        let mut kv_keys = vec![];
+        for op in proof.iter() {
+            match op {
+                Op::Push(Node::KVDigest(key, _)) => digest_keys.push(key.clone()),
+                Op::Push(Node::KV(key, _)) => kv_keys.push(key.clone()),
+                _ => {}
+            }
+        }
+        assert_eq!(kv_keys, vec![seq_key(4), seq_key(5)]);
+        assert_eq!(digest_keys, vec![seq_key(6), seq_key(7), seq_key(8)]);
+
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+        assert!(bytes.len() < 3 * 10_000);
+
+        let map = verify(bytes.as_slice(), root_hash).unwrap();
+        assert_eq!(map.get(&seq_key(4)).unwrap(), Some(&[4; 10_000][..]));
+        assert_eq!(map.get(&seq_key(5)).unwrap(), Some(&[5; 10_000][..]));
+        assert_eq!(map.get(&[0, 0, 0, 0, 0, 0, 0, 7, 1]).unwrap(), None);
+        assert!(map.get(&seq_key(6)).is_err());
+
+        let result = verify_query(bytes.as_slice(), &query, root_hash).unwrap();
+        assert_eq!(
+            result,
+            vec![(seq_key(4), vec![4; 10_000]), (seq_key(5), vec![5; 10_000])]
+        );
+    }
+
+    #[test]
+    fn verify_into_owned() {
+        let mut tree = make_tree_seq(10);
+        let root_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let queryitems = vec![
+            QueryItem::Key(seq_key(2)),
+            QueryItem::Key(vec![0, 0, 0, 0, 0, 0, 0, 5, 1]),
+            QueryItem::Range(seq_key(7)..seq_key(9)),
+        ];
+        let (proof, _) = walker
+            .create_proof(queryitems.as_slice())
+            .expect("create_proof errored");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        let entries = verify_into(bytes.as_slice(), root_hash).unwrap();
+        let map = verify(bytes.as_slice(), root_hash).unwrap();
+
+        // the boundaries of the absent key are included without their values
+        assert!(entries.values().any(Option::is_none));
+        for (key, value) in entries.iter() {
+            match value {
+                Some(value) => assert_eq!(map.get(key).unwrap(), Some(value.as_slice())),
+                None => assert!(map.get(key).is_err()),
+            }
+        }
+
+        let values: Vec<_> = entries
+            .into_iter()
+            .filter_map(|(key, value)| value.map(|value| (key, value)))
+            .collect();
+        assert_eq!(
+            values,
+            [2, 7, 8]
+                .iter()
+                .map(|i| (seq_key(*i), vec![123; 60]))
+                .collect::<Vec<_>>()
+        );
+
+        assert!(verify_into(bytes.as_slice(), [0; 32]).is_err());
+    }
+
+    #[test]
+    fn verify_streaming_visits_entries() {
+        let mut tree = make_tree_seq(100);
+        let root_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let queryitems = vec![
+            QueryItem::Key(seq_key(2)),
+            QueryItem::Range(seq_key(20)..seq_key(60)),
+        ];
+        let (proof, _) = walker
+            .create_proof(queryitems.as_slice())
+            .expect("create_proof errored");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        let mut keys = vec![];
+        let mut value_bytes = 0;
+        verify_streaming(bytes.as_slice(), root_hash, |key, value| {
+            keys.push(key.to_vec());
+            value_bytes += value.len();
+            Ok(())
+        })
+        .unwrap();
+
+        let map = verify(bytes.as_slice(), root_hash).unwrap();
+        let map_entries: Vec<_> = map
+            .range(seq_key(20).as_slice()..seq_key(60).as_slice())
+            .collect::<Result<_>>()
+            .unwrap();
+        let map_value_bytes: usize = map_entries.iter().map(|(_, value)| value.len()).sum();
+        assert_eq!(
+            value_bytes,
+            map_value_bytes + map.get(&seq_key(2)).unwrap().unwrap().len()
+        );
+        assert_eq!(keys[0], seq_key(2));
+        assert_eq!(keys.len(), map_entries.len() + 1);
+
+        let res = verify_streaming(bytes.as_slice(), [0; 32], |_, _| Ok(()));
+        assert!(matches!(res, Err(Error::HashMismatch(..))));
+
+        let res = verify_streaming(bytes.as_slice(), root_hash, |_, _| {
+            Err(Error::Proof("stop".into()))
+        });
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn from_keys_and_prefixes() {
+        let query = Query::from_keys(vec![vec![3], vec![1], vec![2], vec![1]]);
+        let items: Vec<_> = query.into_iter().collect();
+        assert_eq!(
+            items,
+            vec![
+                QueryItem::Key(vec![1]),
+                QueryItem::Key(vec![2]),
+                QueryItem::Key(vec![3])
+            ]
+        );
+
+        let query = Query::from_prefixes(vec![vec![1, 2], vec![5], vec![1], vec![1, 3, 4]]);
+        let items: Vec<_> = query.into_iter().collect();
+        assert_eq!(items.len(), 2);
+        assert!(matches!(
+            &items[0],
+            QueryItem::Range(range) if range.start == [1] && range.end == [2]
+        ));
+        assert!(matches!(
+            &items[1],
+            QueryItem::Range(range) if range.start == [5] && range.end == [6]
+        ));
+
+        let query = Query::from_prefixes(vec![vec![1, 0xff], vec![1, 0xff, 2]]);
+        assert_eq!(query.len(), 1);
+        assert!(query.contains_key(&[1, 0xff, 2, 3]));
+        assert!(!query.contains_key(&[2]));
+    }
+
+    #[test]
+    fn insert_prefix() {
+        let mut query = Query::new();
+        query.insert_prefix(vec![1, 2]);
+        assert!(matches!(
+            query.iter().next(),
+            Some(QueryItem::Range(range)) if range.start == [1, 2] && range.end == [1, 3]
+        ));
+        assert!(query.contains_key(&[1, 2]));
+        assert!(query.contains_key(&[1, 2, 0xff, 0xff]));
+        assert!(!query.contains_key(&[1, 1, 0xff]));
+        assert!(!query.contains_key(&[1, 3]));
+
+        let mut query = Query::new();
+        query.insert_prefix(vec![1, 0xff, 0xff]);
+        assert!(matches!(
+            query.iter().next(),
+            Some(QueryItem::Range(range)) if range.start == [1, 0xff, 0xff] && range.end == [2]
+        ));
+        assert!(query.contains_key(&[1, 0xff, 0xff, 0]));
+        assert!(!query.contains_key(&[1, 0xff, 0xfe, 0xff]));
+        assert!(!query.contains_key(&[2]));
+
+        let mut query = Query::new();
+        query.insert_prefix(vec![0xff, 0xff]);
+        assert!(matches!(
+            query.iter().next(),
+            Some(QueryItem::RangeFrom(range)) if range.start == [0xff, 0xff]
+        ));
+        assert!(query.contains_key(&[0xff, 0xff, 0xff, 0xff]));
+        assert!(!query.contains_key(&[0xff, 0xfe]));
+
+        let mut query = Query::new();
+        query.insert_prefix(vec![]);
+        assert!(matches!(
+            query.iter().next(),
+            Some(QueryItem::RangeFull(..))
+        ));
+        assert!(query.contains_key(&[]));
+        assert!(query.contains_key(&[0xff]));
+    }
+
+    #[test]
+    fn empty_value_proof() {
+        let batch = vec![
+            (vec![1], crate::tree::Op::Put(vec![123])),
+            (vec![2], crate::tree::Op::Put(vec![])),
+            (vec![4], crate::tree::Op::Put(vec![123])),
+        ];
+        let mut tree = apply_to_memonly(None, &batch).unwrap();
+        let root_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let mut query = Query::new();
+        query.insert_key(vec![2]);
+        query.insert_key(vec![3]);
+        let queryitems: Vec<QueryItem> = query.iter().cloned().collect();
+        let (proof, _) = walker
+            .create_proof(queryitems.as_slice())
+            .expect("create_proof errored");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        // a present key with an empty value is distinct from an absent key
+        let map = verify(bytes.as_slice(), root_hash).unwrap();
+        assert_eq!(map.get(&[2]).unwrap(), Some(&[][..]));
+        assert_eq!(map.get(&[3]).unwrap(), None);
+
+        let result = verify_query(bytes.as_slice(), &query, root_hash).unwrap();
+        assert_eq!(result, vec![(vec![2], vec![])]);
+
+        let entries = verify_into(bytes.as_slice(), root_hash).unwrap();
+        assert_eq!(entries.get(&vec![2]), Some(&Some(vec![])));
+    }
+
+    #[test]
+    fn execute_proof_bounds() {
+        let batch: Vec<_> = (0..10)
+            .map(|i| (vec![i], crate::tree::Op::Put(vec![i])))
+            .collect();
+        let mut tree = apply_to_memonly(None, &batch).unwrap();
+        let root_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let mut query = Query::new();
+        query.insert_range(vec![2, 0]..vec![6]);
+        let queryitems: Vec<QueryItem> = query.iter().cloned().collect();
+        let (proof, _) = walker
+            .create_proof(queryitems.as_slice())
+            .expect("create_proof errored");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        let (map, left, right) =
+            execute_proof_with_bounds(bytes.as_slice(), &query, root_hash).unwrap();
+        assert_eq!(left, Some(vec![2]));
+        assert_eq!(right, Some(vec![6]));
+        assert_eq!(map.get(&[4]).unwrap(), Some(&[4][..]));
+
+        // the proof does not cover a wider query
+        let mut wide_query = Query::new();
+        wide_query.insert_range(vec![1, 0]..vec![6]);
+        assert!(execute_proof_with_bounds(bytes.as_slice(), &wide_query, root_hash).is_err());
+    }
+
+    #[test]
+    fn prefix_proof() {
+        let batch: Vec<_> = vec![
+            vec![1],
+            vec![1, 0xff],
+            vec![1, 0xff, 3],
+            vec![2],
+            vec![2, 0],
+        ]
+        .into_iter()
+        .map(|key| (key, crate::tree::Op::Put(vec![123])))
+        .collect();
+        let mut tree = apply_to_memonly(None, &batch).unwrap();
+        let root_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let mut query = Query::new();
+        query.insert_prefix(vec![1, 0xff]);
+        let queryitems: Vec<QueryItem> = query.iter().cloned().collect();
+        let (proof, _) = walker
+            .create_proof(queryitems.as_slice())
+            .expect("create_proof errored");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        let result = verify_query(bytes.as_slice(), &query, root_hash).unwrap();
+        assert_eq!(
+            result,
+            vec![(vec![1, 0xff], vec![123]), (vec![1, 0xff, 3], vec![123])]
+        );
+    }
+
+    #[test]
+    fn verify_sum_range() {
+        let batch: Vec<_> = (0..100)
+            .map(|i| {
+                (
+                    seq_key(i),
+                    crate::tree::Op::Put((i * 10).to_be_bytes().to_vec()),
+                )
+            })
+            .collect();
+        let mut tree = apply_to_memonly(None, &batch).unwrap();
+        let root_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let mut query = Query::new();
+        query.insert_range(seq_key(10)..seq_key(20));
+        query.insert_range_inclusive(seq_key(30)..=seq_key(39));
+        query.insert_key(seq_key(50));
+        query.insert_key(vec![0, 0, 0, 0, 0, 0, 0, 60, 1]);
+        let queryitems: Vec<QueryItem> = query.iter().cloned().collect();
+        let (proof, _) = walker
+            .create_proof(queryitems.as_slice())
+            .expect("create_proof errored");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        let expected: u128 = (10..20).chain(30..40).map(|i| i * 10).sum::<u128>() + 500;
+        assert_eq!(
+            verify_sum(bytes.as_slice(), &query, root_hash).unwrap(),
+            expected
+        );
+        assert!(verify_sum(bytes.as_slice(), &query, [0; 32]).is_err());
+
+        // the proof does not include the whole range
+        let mut wider_query = Query::new();
+        wider_query.insert_range(seq_key(10)..seq_key(30));
+        assert!(verify_sum(bytes.as_slice(), &wider_query, root_hash).is_err());
+    }
+
+    #[test]
+    fn verify_sum_invalid_value() {
+        let mut tree = make_tree_seq(10);
+        let root_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let mut query = Query::new();
+        query.insert_key(seq_key(5));
+        let queryitems: Vec<QueryItem> = query.iter().cloned().collect();
+        let (proof, _) = walker
+            .create_proof(queryitems.as_slice())
+            .expect("create_proof errored");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        assert!(verify_sum(bytes.as_slice(), &query, root_hash).is_err());
+    }
+
+    #[test]
+    fn verify_query_strict_coverage() {
+        let mut tree = make_tree_seq(20);
+        let root_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let mut query = Query::new();
+        query.insert_range(seq_key(5)..seq_key(10));
+        query.insert_key(vec![0, 0, 0, 0, 0, 0, 0, 15, 1]);
+        let queryitems: Vec<QueryItem> = query.iter().cloned().collect();
+        let (proof, _) = walker
+            .create_proof(queryitems.as_slice())
+            .expect("create_proof errored");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        let map = verify_query_strict(bytes.as_slice(), &query, root_hash).unwrap();
+        assert_eq!(map.get(&seq_key(5)).unwrap(), Some(&[123; 60][..]));
+        assert_eq!(map.get(&[0, 0, 0, 0, 0, 0, 0, 15, 1]).unwrap(), None);
+        assert!(verify_query_strict(bytes.as_slice(), &query, [0; 32]).is_err());
+
+        // a proof of a wider range includes values which were not queried
+        let (proof, _) = walker
+            .create_proof(&[QueryItem::Range(seq_key(0)..seq_key(10))])
+            .expect("create_proof errored");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        assert!(verify(bytes.as_slice(), root_hash).is_ok());
+        assert!(verify_query_strict(bytes.as_slice(), &query, root_hash).is_err());
+    }
+
+    #[test]
+    fn verify_with_limits_depth() {
+        // a valid proof of a degenerate tree where each node is the left child
+        // of the next, so the tree is as high as it has nodes
+        let mut ops = vec![Op::Push(Node::KV(vec![0], vec![]))];
+        for i in 1..100 {
+            ops.push(Op::Push(Node::KV(vec![i], vec![])));
+            ops.push(Op::Parent);
+        }
+        let mut bytes = vec![];
+        encode_into(ops.iter(), &mut bytes);
+
+        let root_hash = execute(Decoder::new(&bytes), true, |_| Ok(()))
+            .unwrap()
+            .hash()
+            .unwrap();
+        assert!(verify(bytes.as_slice(), root_hash).is_ok());
+        assert!(verify_with_limits(bytes.as_slice(), root_hash, 100).is_ok());
+        match verify_with_limits(bytes.as_slice(), root_hash, 10) {
+            Err(Error::Proof(message)) => assert!(message.contains("maximum depth of 10")),
+            _ => panic!("expected proof to be rejected"),
+        }
+
+        // proofs of balanced trees fit within a logarithmic depth
+        let mut tree = make_tree_seq(1000);
+        let root_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+        let (proof, _) = walker
+            .create_proof(&[QueryItem::Key(seq_key(500))])
+            .expect("create_proof errored");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+        assert!(verify_with_limits(bytes.as_slice(), root_hash, 15).is_ok());
+    }
+
+    #[test]
+    fn verify_to_tree_structure() {
+        let mut tree = make_tree_seq(10);
+        let expected_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+        let (proof, _) = walker
+            .create_proof(&[QueryItem::Key(seq_key(5))])
+            .expect("create_proof errored");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        let root = verify_to_tree(bytes.as_slice(), expected_hash).expect("verify failed");
+        assert_eq!(root.hash().unwrap(), expected_hash);
+
+        // nodes are visited in key order, which is the order they were pushed
+        let mut nodes = vec![];
+        root.visit_refs(&mut |tree| nodes.push(tree.node.clone()));
+        let expected: Vec<_> = proof
+            .iter()
+            .filter_map(|op| match op {
+                Op::Push(node) => Some(node.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(nodes, expected);
+        assert!(nodes.contains(&Node::KV(seq_key(5), vec![123; 60])));
+        assert!(nodes.iter().any(|node| matches!(node, Node::KVHash(_))));
+
+        assert!(verify_to_tree(bytes.as_slice(), [0; 32]).is_err());
+    }
 }
@@ -1,6 +1,6 @@
 use super::{Node, Op};
 use crate::error::{Error, Result};
-use crate::tree::{kv_hash, node_hash, Hash, Hasher, NULL_HASH};
+use crate::tree::{Hash, HashAlgorithm, NULL_HASH};
 
 /// Contains a tree's child node and its hash. The hash can always be assumed to
 /// be up-to-date.
@@ -48,17 +48,30 @@ impl PartialEq for Tree {
 }
 
 impl Tree {
-    /// Gets or computes the hash for this tree node.
+    /// Gets or computes the hash for this tree node, using the default hash
+    /// algorithm.
     pub fn hash(&self) -> Result<Hash> {
-        fn compute_hash(tree: &Tree, kv_hash: Hash) -> Hash {
-            node_hash::<Hasher>(&kv_hash, &tree.child_hash(true), &tree.child_hash(false))
-        }
+        self.hash_with_algorithm(HashAlgorithm::default())
+    }
+
+    /// Gets or computes the hash for this tree node, using the given hash
+    /// algorithm. The hashes of the node's children were computed when they
+    /// were attached.
+    pub fn hash_with_algorithm(&self, hash_algorithm: HashAlgorithm) -> Result<Hash> {
+        let compute_hash = |kv_hash: Hash| {
+            hash_algorithm.node_hash(&kv_hash, &self.child_hash(true), &self.child_hash(false))
+        };
 
         match &self.node {
             Node::Hash(hash) => Ok(*hash),
-            Node::KVHash(kv_hash) => Ok(compute_hash(self, *kv_hash)),
-            Node::KV(key, value) => kv_hash::<Hasher>(key.as_slice(), value.as_slice())
-                .map(|kv_hash| compute_hash(self, kv_hash))
+            Node::KVHash(kv_hash) => Ok(compute_hash(*kv_hash)),
+            Node::KV(key, value) => hash_algorithm
+                .kv_hash(key.as_slice(), value.as_slice())
+                .map(compute_hash)
+                .map_err(Into::into),
+            Node::KVDigest(key, value_hash) => hash_algorithm
+                .kv_digest_hash(key.as_slice(), value_hash)
+                .map(compute_hash)
                 .map_err(Into::into),
         }
     }
@@ -116,9 +129,15 @@ impl Tree {
         }
     }
 
-    /// Attaches the child to the `Tree`'s given side. Panics if there is
-    /// already a child attached to this side.
-    pub(crate) fn attach(&mut self, left: bool, child: Tree) -> Result<()> {
+    /// Attaches the child to the `Tree`'s given side, computing its hash with
+    /// the given hash algorithm. Returns an error if there is already a child
+    /// attached to this side.
+    pub(crate) fn attach(
+        &mut self,
+        left: bool,
+        child: Tree,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<()> {
         if self.child(left).is_some() {
             return Err(Error::Attach(
                 "Tried to attach to left child, but it is already Some".into(),
@@ -127,7 +146,7 @@ impl Tree {
 
         self.height = self.height.max(child.height + 1);
 
-        let hash = child.hash()?;
+        let hash = child.hash_with_algorithm(hash_algorithm)?;
         let tree = Box::new(child);
         *self.child_mut(left) = Some(Child { tree, hash });
 
@@ -143,9 +162,12 @@ impl Tree {
     }
 
     /// Consumes the tree node, calculates its hash, and returns a `Node::Hash`
-    /// variant.
-    fn try_into_hash(self) -> Result<Tree> {
-        self.hash().map(Node::Hash).map(Into::into)
+    /// variant. The height of the tree is retained.
+    fn try_into_hash(self, hash_algorithm: HashAlgorithm) -> Result<Tree> {
+        let height = self.height;
+        let mut tree: Tree = Node::Hash(self.hash_with_algorithm(hash_algorithm)?).into();
+        tree.height = height;
+        Ok(tree)
     }
 
     #[cfg(feature = "full")]
@@ -238,7 +260,38 @@ impl<'a> Iterator for LayerIter<'a> {
 /// `visit_node` will be called once for every push operation in the proof, in
 /// key-order. If `visit_node` returns an `Err` result, it will halt the
 /// execution and `execute` will return the error.
-pub(crate) fn execute<I, F>(ops: I, collapse: bool, mut visit_node: F) -> Result<Tree>
+pub(crate) fn execute<I, F>(ops: I, collapse: bool, visit_node: F) -> Result<Tree>
+where
+    I: IntoIterator<Item = Result<Op>>,
+    F: FnMut(&Node) -> Result<()>,
+{
+    execute_with_algorithm(ops, collapse, HashAlgorithm::default(), visit_node)
+}
+
+/// Executes a proof like `execute`, but computes hashes with the given hash
+/// algorithm.
+pub(crate) fn execute_with_algorithm<I, F>(
+    ops: I,
+    collapse: bool,
+    hash_algorithm: HashAlgorithm,
+    visit_node: F,
+) -> Result<Tree>
+where
+    I: IntoIterator<Item = Result<Op>>,
+    F: FnMut(&Node) -> Result<()>,
+{
+    execute_with_limits(ops, collapse, hash_algorithm, usize::MAX, visit_node)
+}
+
+/// Executes a proof like `execute_with_algorithm`, but returns an error as soon
+/// as the tree reconstructed from the proof is higher than `max_depth`.
+pub(crate) fn execute_with_limits<I, F>(
+    ops: I,
+    collapse: bool,
+    hash_algorithm: HashAlgorithm,
+    max_depth: usize,
+    mut visit_node: F,
+) -> Result<Tree>
 where
     I: IntoIterator<Item = Result<Op>>,
     F: FnMut(&Node) -> Result<()>,
@@ -253,6 +306,15 @@ where
         }
     }
 
+    let check_depth = |tree: &Tree| {
+        if tree.height > max_depth {
+            return Err(Error::Proof(format!(
+                "Proof tree exceeds maximum depth of {max_depth}"
+            )));
+        }
+        Ok(())
+    };
+
     for op in ops {
         match op? {
             Op::Parent => {
@@ -260,11 +322,13 @@ where
                 parent.attach(
                     true,
                     if collapse {
-                        child.try_into_hash()?
+                        child.try_into_hash(hash_algorithm)?
                     } else {
                         child
                     },
+                    hash_algorithm,
                 )?;
+                check_depth(&parent)?;
                 stack.push(parent);
             }
             Op::Child => {
@@ -272,15 +336,17 @@ where
                 parent.attach(
                     false,
                     if collapse {
-                        child.try_into_hash()?
+                        child.try_into_hash(hash_algorithm)?
                     } else {
                         child
                     },
+                    hash_algorithm,
                 )?;
+                check_depth(&parent)?;
                 stack.push(parent);
             }
             Op::Push(node) => {
-                if let Node::KV(key, _) = &node {
+                if let Node::KV(key, _) | Node::KVDigest(key, _) = &node {
                     // keys should always increase
                     if let Some(last_key) = &maybe_last_key {
                         if key <= last_key {
@@ -319,13 +385,19 @@ mod test {
 
         let mut tree = make_node(3);
         let mut left = make_node(1);
-        left.attach(true, make_node(0)).unwrap();
-        left.attach(false, make_node(2)).unwrap();
+        left.attach(true, make_node(0), HashAlgorithm::default())
+            .unwrap();
+        left.attach(false, make_node(2), HashAlgorithm::default())
+            .unwrap();
         let mut right = make_node(5);
-        right.attach(true, make_node(4)).unwrap();
-        right.attach(false, make_node(6)).unwrap();
-        tree.attach(true, left).unwrap();
-        tree.attach(false, right).unwrap();
+        right
+            .attach(true, make_node(4), HashAlgorithm::default())
+            .unwrap();
+        right
+            .attach(false, make_node(6), HashAlgorithm::default())
+            .unwrap();
+        tree.attach(true, left, HashAlgorithm::default()).unwrap();
+        tree.attach(false, right, HashAlgorithm::default()).unwrap();
 
         tree
     }
@@ -3,27 +3,55 @@
 mod crash_merk;
 mod temp_merk;
 
+use crate::proofs::query::{verify_query_strict, Query, QueryItem};
 use crate::tree::{Batch, BatchEntry, NoopCommit, Op, PanicSource, Tree, Walker};
+use crate::Merk;
 use rand::prelude::*;
 use std::convert::TryInto;
-use std::ops::Range;
+use std::ops::{Bound, Range};
 
 pub use crash_merk::CrashMerk;
 pub use temp_merk::TempMerk;
 
+/// Asserts that the loaded nodes of the tree are balanced, ordered by key and
+/// have no modified links, panicking with a message describing the violated
+/// invariant.
 pub fn assert_tree_invariants(tree: &Tree) {
-    assert!(tree.balance_factor().abs() < 2);
+    assert!(
+        tree.balance_factor().abs() < 2,
+        "Tree is unbalanced at key {:?}: balance factor {}",
+        tree.key(),
+        tree.balance_factor()
+    );
 
     let maybe_left = tree.link(true);
     if let Some(left) = maybe_left {
-        assert!(left.key() < tree.key());
-        assert!(!left.is_modified());
+        assert!(
+            left.key() < tree.key(),
+            "Left child key {:?} is not less than parent key {:?}",
+            left.key(),
+            tree.key()
+        );
+        assert!(
+            !left.is_modified(),
+            "Left link of key {:?} is still modified",
+            tree.key()
+        );
     }
 
     let maybe_right = tree.link(false);
     if let Some(right) = maybe_right {
-        assert!(right.key() > tree.key());
-        assert!(!right.is_modified());
+        assert!(
+            right.key() > tree.key(),
+            "Right child key {:?} is not greater than parent key {:?}",
+            right.key(),
+            tree.key()
+        );
+        assert!(
+            !right.is_modified(),
+            "Right link of key {:?} is still modified",
+            tree.key()
+        );
     }
 
     if let Some(left) = tree.child(true) {
@@ -34,6 +62,51 @@ pub fn assert_tree_invariants(tree: &Tree) {
     }
 }
 
+/// Proves the query against the store, verifies the proof against the store's
+/// root hash, and checks that the proven entries match the store's contents:
+/// every queried key must have the same value (or absence) as `merk.get`, and
+/// every entry in a queried range must be included in the proof.
+///
+/// Queries with a limit or offset are not supported.
+pub fn assert_prove_verify(merk: &Merk, query: Query) {
+    assert!(query.limit().is_none() && query.offset().is_none());
+
+    let items: Vec<QueryItem> = query.iter().cloned().collect();
+    let proof = merk.prove_query(query).expect("prove failed");
+    let query = items.iter().cloned().fold(Query::new(), |mut query, item| {
+        query.insert_item(item);
+        query
+    });
+    let map =
+        verify_query_strict(proof.as_slice(), &query, merk.root_hash()).expect("verify failed");
+
+    for item in items {
+        if let QueryItem::Key(key) = &item {
+            let proven = map.get(key).expect("key missing from proof");
+            assert_eq!(proven, merk.get(key).unwrap().as_deref());
+        }
+
+        let lower = item.lower_bound().map_or(Bound::Unbounded, Bound::Included);
+        let upper = match item.upper_bound() {
+            (None, _) => Bound::Unbounded,
+            (Some(bound), true) => Bound::Included(bound),
+            (Some(bound), false) => Bound::Excluded(bound),
+        };
+        let proven = map
+            .range((lower, upper))
+            .map(|entry| entry.map(|(key, value)| (key.to_vec(), value.to_vec())))
+            .collect::<crate::Result<Vec<_>>>()
+            .expect("range missing from proof");
+
+        let expected = merk
+            .iter()
+            .map(|entry| entry.unwrap())
+            .filter(|(key, _)| item.contains(key))
+            .collect::<Vec<_>>();
+        assert_eq!(proven, expected);
+    }
+}
+
 pub fn apply_memonly_unchecked(tree: Tree, batch: &Batch) -> Tree {
     let walker = Walker::<PanicSource>::new(tree, PanicSource {});
     let mut tree = Walker::<PanicSource>::apply_to(Some(walker), batch, PanicSource {})
@@ -20,6 +20,30 @@ impl Tree {
         Encode::encoding_length(self).unwrap()
     }
 
+    /// Decodes an encoded node into this existing `Tree`, replacing its
+    /// contents and setting its key to `key`.
+    ///
+    /// The node's heap allocations (including its value buffer) are reused
+    /// where possible, so a single `Tree` can be used to scan over many
+    /// encoded nodes without allocating for each one. The node's hash
+    /// algorithm is left unchanged.
+    ///
+    /// # Example
+    /// ```
+    /// # let mut store = merk::test_utils::TempMerk::new().unwrap();
+    /// # store.apply(&[(vec![1], merk::Op::Put(vec![2]))], &[]).unwrap();
+    /// use merk::tree::Tree;
+    ///
+    /// let mut node = Tree::new(vec![], vec![]).unwrap();
+    /// let mut iter = store.raw_iter();
+    /// iter.seek_to_first();
+    /// while iter.valid() {
+    ///     // pass an empty key to avoid copying it if it is not needed
+    ///     node.decode_into(vec![], iter.value().unwrap());
+    ///     assert_eq!(node.value(), &[2]);
+    ///     iter.next();
+    /// }
+    /// ```
     #[inline]
     pub fn decode_into(&mut self, key: Vec<u8>, input: &[u8]) {
         // operation is infallible so it's ok to unwrap
@@ -27,6 +51,7 @@ impl Tree {
         self.inner.kv.key = key;
     }
 
+    /// Decodes an encoded node into a new `Tree` with the given key.
     #[inline]
     pub fn decode(key: Vec<u8>, input: &[u8]) -> Tree {
         // operation is infallible so it's ok to unwrap
@@ -34,6 +59,16 @@ impl Tree {
         tree.inner.kv.key = key;
         tree
     }
+
+    /// Decodes an encoded node like `decode`, but returns an error instead of
+    /// panicking if the bytes are not a valid encoding (e.g. if they were
+    /// corrupted in the backing store).
+    #[inline]
+    pub fn try_decode(key: Vec<u8>, input: &[u8]) -> ed::Result<Tree> {
+        let mut tree: Tree = Decode::decode(input)?;
+        tree.inner.kv.key = key;
+        Ok(tree)
+    }
 }
 
 #[cfg(test)]
@@ -182,4 +217,27 @@ mod tests {
             panic!("Expected Link::Reference");
         }
     }
+
+    #[test]
+    fn decode_into_reuses_allocations() {
+        let encoded: Vec<Vec<u8>> = (0..10u8)
+            .map(|i| {
+                Tree::from_fields(vec![i], vec![i; 100 - i as usize], [i; 32], None, None).encode()
+            })
+            .collect();
+
+        let mut node = Tree::new(vec![], vec![0; 100]).unwrap();
+        let value_ptr = node.value().as_ptr();
+        let inner_ptr = &*node.inner as *const _;
+
+        for (i, bytes) in encoded.iter().enumerate() {
+            node.decode_into(vec![], bytes);
+            assert_eq!(node.value(), &vec![i as u8; 100 - i][..]);
+            assert_eq!(node.kv_hash(), &[i as u8; 32]);
+
+            // the node and its value buffer were decoded in place
+            assert_eq!(node.value().as_ptr(), value_ptr);
+            assert_eq!(&*node.inner as *const _, inner_ptr);
+        }
+    }
 }
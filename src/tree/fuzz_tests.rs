@@ -125,6 +125,11 @@ fn apply_to_map(map: &mut Map, batch: &Batch) {
             (key, Op::Delete) => {
                 map.remove(key);
             }
+            (key, Op::Append(suffix)) => {
+                map.entry(key.to_vec())
+                    .or_default()
+                    .extend_from_slice(suffix);
+            }
         }
     }
 }
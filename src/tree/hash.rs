@@ -1,7 +1,9 @@
+#[cfg(feature = "keccak")]
+use sha2::digest;
 use sha2::{Digest, Sha512_256};
 use std::{convert::TryFrom, num::TryFromIntError};
 
-/// The hash algorithm used for both KV hashes and node hashes.
+/// The default hash algorithm used for both KV hashes and node hashes.
 pub type Hasher = Sha512_256;
 
 /// The length of a `Hash` (in bytes).
@@ -13,32 +15,69 @@ pub const NULL_HASH: Hash = [0; HASH_LENGTH];
 /// A cryptographic hash digest.
 pub type Hash = [u8; HASH_LENGTH];
 
-/// Hashes a key/value pair.
+/// Hashes a value.
+pub fn value_hash<D: Digest>(value: &[u8]) -> Hash {
+    let mut hasher = D::new();
+    hasher.update(value);
+
+    let res = hasher.finalize();
+    let mut hash: Hash = Default::default();
+    hash.copy_from_slice(&res[..]);
+    hash
+}
+
+/// Hashes a key/value pair. This is the hash returned by a tree node's
+/// `kv_hash` method, so it can be used to compute what Merk's hash of an entry
+/// will be without creating a node, e.g. when the value is stored elsewhere.
+///
+/// The value is hashed separately, so that the key/value hash can also be
+/// computed from only the key and the value's hash (see `kv_digest_hash`).
+///
+/// **NOTE:** This will fail if the key is longer than `u32::MAX` bytes (though
+/// keys in a tree are limited to `MAX_KEY_LENGTH`).
 ///
-/// **NOTE:** This will fail if the key is longer than 255 bytes, or the value
-/// is longer than 65,535 bytes.
+/// # Example
+/// ```
+/// use merk::tree::{kv_hash, Hasher, Tree};
+///
+/// let hash = kv_hash::<Hasher>(b"key", b"value").unwrap();
+/// let tree = Tree::new(b"key".to_vec(), b"value".to_vec()).unwrap();
+/// assert_eq!(&hash, tree.kv_hash());
+/// ```
 pub fn kv_hash<D: Digest>(key: &[u8], value: &[u8]) -> Result<Hash, TryFromIntError> {
+    kv_digest_hash::<D>(key, &value_hash::<D>(value))
+}
+
+/// Hashes a key/value pair from the key and the hash of the value.
+pub fn kv_digest_hash<D: Digest>(key: &[u8], value_hash: &Hash) -> Result<Hash, TryFromIntError> {
     let mut hasher = D::new();
     hasher.update([0]);
 
-    u32::try_from(key.len())
-        .and_then(|key| u32::try_from(value.len()).map(|value| (key, value)))
-        .map(|(key_length, val_length)| {
-            hasher.update(key_length.to_le_bytes());
-            hasher.update(key);
+    u32::try_from(key.len()).map(|key_length| {
+        hasher.update(key_length.to_le_bytes());
+        hasher.update(key);
 
-            hasher.update(val_length.to_le_bytes());
-            hasher.update(value);
+        hasher.update(value_hash);
 
-            let res = hasher.finalize();
-            let mut hash: Hash = Default::default();
-            hash.copy_from_slice(&res[..]);
-            hash
-        })
+        let res = hasher.finalize();
+        let mut hash: Hash = Default::default();
+        hash.copy_from_slice(&res[..]);
+        hash
+    })
 }
 
 /// Hashes a node based on the hash of its left child (if any), its key/value
-/// pair, and the hash of its right child (if any).
+/// pair, and the hash of its right child (if any). Missing children are
+/// represented by `NULL_HASH`.
+///
+/// # Example
+/// ```
+/// use merk::tree::{kv_hash, node_hash, Hasher, Tree, NULL_HASH};
+///
+/// let kv = kv_hash::<Hasher>(b"key", b"value").unwrap();
+/// let tree = Tree::new(b"key".to_vec(), b"value".to_vec()).unwrap();
+/// assert_eq!(node_hash::<Hasher>(&kv, &NULL_HASH, &NULL_HASH), tree.hash());
+/// ```
 pub fn node_hash<D: Digest>(kv: &Hash, left: &Hash, right: &Hash) -> Hash {
     let mut hasher = D::new();
     hasher.update([1]);
@@ -51,3 +90,144 @@ pub fn node_hash<D: Digest>(kv: &Hash, left: &Hash, right: &Hash) -> Hash {
     hash.copy_from_slice(&res[..]);
     hash
 }
+
+/// Selects the hash algorithm used for KV hashes and node hashes. A store uses
+/// a single algorithm for its whole lifetime, and proofs must be verified with
+/// the same algorithm which was used to create them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// SHA-512/256 (see `Hasher`).
+    #[default]
+    Sha512_256,
+    /// Keccak-256, for interoperability with Ethereum tooling.
+    #[cfg(feature = "keccak")]
+    Keccak256,
+}
+
+impl HashAlgorithm {
+    /// Returns the byte used to identify the algorithm when it is persisted.
+    pub fn to_byte(self) -> u8 {
+        match self {
+            HashAlgorithm::Sha512_256 => 0,
+            #[cfg(feature = "keccak")]
+            HashAlgorithm::Keccak256 => 1,
+        }
+    }
+
+    /// Parses an algorithm from the byte returned by `to_byte`. Returns `None`
+    /// if the byte is unknown, or if the algorithm is not enabled in this
+    /// build.
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(HashAlgorithm::Sha512_256),
+            #[cfg(feature = "keccak")]
+            1 => Some(HashAlgorithm::Keccak256),
+            _ => None,
+        }
+    }
+
+    /// Hashes a value (see `value_hash`).
+    pub fn value_hash(self, value: &[u8]) -> Hash {
+        match self {
+            HashAlgorithm::Sha512_256 => value_hash::<Hasher>(value),
+            #[cfg(feature = "keccak")]
+            HashAlgorithm::Keccak256 => value_hash::<Keccak256Hasher>(value),
+        }
+    }
+
+    /// Hashes a key/value pair (see `kv_hash`).
+    pub fn kv_hash(self, key: &[u8], value: &[u8]) -> Result<Hash, TryFromIntError> {
+        match self {
+            HashAlgorithm::Sha512_256 => kv_hash::<Hasher>(key, value),
+            #[cfg(feature = "keccak")]
+            HashAlgorithm::Keccak256 => kv_hash::<Keccak256Hasher>(key, value),
+        }
+    }
+
+    /// Hashes a key/value pair from the key and the hash of the value (see
+    /// `kv_digest_hash`).
+    pub fn kv_digest_hash(self, key: &[u8], value_hash: &Hash) -> Result<Hash, TryFromIntError> {
+        match self {
+            HashAlgorithm::Sha512_256 => kv_digest_hash::<Hasher>(key, value_hash),
+            #[cfg(feature = "keccak")]
+            HashAlgorithm::Keccak256 => kv_digest_hash::<Keccak256Hasher>(key, value_hash),
+        }
+    }
+
+    /// Hashes a node (see `node_hash`).
+    pub fn node_hash(self, kv: &Hash, left: &Hash, right: &Hash) -> Hash {
+        match self {
+            HashAlgorithm::Sha512_256 => node_hash::<Hasher>(kv, left, right),
+            #[cfg(feature = "keccak")]
+            HashAlgorithm::Keccak256 => node_hash::<Keccak256Hasher>(kv, left, right),
+        }
+    }
+}
+
+/// A Keccak-256 hasher (the original Keccak padding used by Ethereum, not
+/// SHA3-256), implementing the `Digest` traits so it can be used with the
+/// generic hash functions in this module.
+#[cfg(feature = "keccak")]
+#[derive(Clone)]
+pub struct Keccak256Hasher(tiny_keccak::Keccak);
+
+#[cfg(feature = "keccak")]
+impl Default for Keccak256Hasher {
+    fn default() -> Self {
+        Keccak256Hasher(tiny_keccak::Keccak::v256())
+    }
+}
+
+#[cfg(feature = "keccak")]
+impl digest::HashMarker for Keccak256Hasher {}
+
+#[cfg(feature = "keccak")]
+impl digest::OutputSizeUser for Keccak256Hasher {
+    type OutputSize = digest::consts::U32;
+}
+
+#[cfg(feature = "keccak")]
+impl digest::Update for Keccak256Hasher {
+    fn update(&mut self, data: &[u8]) {
+        tiny_keccak::Hasher::update(&mut self.0, data);
+    }
+}
+
+#[cfg(feature = "keccak")]
+impl digest::FixedOutput for Keccak256Hasher {
+    fn finalize_into(self, out: &mut digest::Output<Self>) {
+        tiny_keccak::Hasher::finalize(self.0, out.as_mut_slice());
+    }
+}
+
+#[cfg(all(test, feature = "keccak"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keccak256_empty() {
+        // well-known Keccak-256 digest of the empty string
+        assert_eq!(
+            value_hash::<Keccak256Hasher>(&[]),
+            [
+                0xc5, 0xd2, 0x46, 0x01, 0x86, 0xf7, 0x23, 0x3c, 0x92, 0x7e, 0x7d, 0xb2, 0xdc, 0xc7,
+                0x03, 0xc0, 0xe5, 0x00, 0xb6, 0x53, 0xca, 0x82, 0x27, 0x3b, 0x7b, 0xfa, 0xd8, 0x04,
+                0x5d, 0x85, 0xa4, 0x70
+            ]
+        );
+    }
+
+    #[test]
+    fn algorithm_byte_roundtrip() {
+        for algorithm in [HashAlgorithm::Sha512_256, HashAlgorithm::Keccak256]
+            .iter()
+            .copied()
+        {
+            assert_eq!(
+                HashAlgorithm::from_byte(algorithm.to_byte()),
+                Some(algorithm)
+            );
+        }
+        assert_eq!(HashAlgorithm::from_byte(2), None);
+    }
+}
@@ -1,3 +1,5 @@
+use std::ops::{Bound, RangeBounds};
+
 use super::Tree;
 
 /// An entry stored on an `Iter`'s stack, containing a reference to a `Tree`,
@@ -82,3 +84,182 @@ impl<'a> Iterator for Iter<'a> {
         }
     }
 }
+
+/// An iterator which yields the key/value pairs of the tree with keys in a
+/// given range, in order. Only subtrees which can contain keys in the range are
+/// traversed, and pruned subtrees are skipped.
+pub struct RangeIter<'a> {
+    stack: Vec<StackItem<'a>>,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+}
+
+impl<'a> RangeIter<'a> {
+    /// Creates a new iterator for the given tree and range.
+    pub fn new<'r>(tree: &'a Tree, range: impl RangeBounds<&'r [u8]>) -> Self {
+        let to_owned = |bound: Bound<&&[u8]>| match bound {
+            Bound::Included(key) => Bound::Included(key.to_vec()),
+            Bound::Excluded(key) => Bound::Excluded(key.to_vec()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+
+        let mut iter = RangeIter {
+            stack: vec![],
+            start: to_owned(range.start_bound()),
+            end: to_owned(range.end_bound()),
+        };
+        iter.push(tree);
+        iter
+    }
+
+    /// Returns `true` if `key` is at or below the start of the range, so none
+    /// of the keys less than it can be in the range.
+    fn at_or_below_start(&self, key: &[u8]) -> bool {
+        match &self.start {
+            Bound::Included(start) | Bound::Excluded(start) => key <= start.as_slice(),
+            Bound::Unbounded => false,
+        }
+    }
+
+    /// Returns `true` if `key` is at or above the end of the range, so none of
+    /// the keys greater than it can be in the range.
+    fn at_or_above_end(&self, key: &[u8]) -> bool {
+        match &self.end {
+            Bound::Included(end) | Bound::Excluded(end) => key >= end.as_slice(),
+            Bound::Unbounded => false,
+        }
+    }
+
+    /// Pushes a `StackItem` for the given tree, marking children which can not
+    /// contain keys in the range as already traversed.
+    fn push(&mut self, tree: &'a Tree) {
+        let mut item = StackItem::new(tree);
+        item.traversed.0 |= self.at_or_below_start(tree.key());
+        item.traversed.2 |= self.at_or_above_end(tree.key());
+        self.stack.push(item);
+    }
+
+    /// Returns `true` if `key` is in the range.
+    fn contains(&self, key: &[u8]) -> bool {
+        let above_start = match &self.start {
+            Bound::Included(start) => key >= start.as_slice(),
+            Bound::Excluded(start) => key > start.as_slice(),
+            Bound::Unbounded => true,
+        };
+        let below_end = match &self.end {
+            Bound::Included(end) => key <= end.as_slice(),
+            Bound::Excluded(end) => key < end.as_slice(),
+            Bound::Unbounded => true,
+        };
+        above_start && below_end
+    }
+}
+
+impl<'a> Tree {
+    /// Creates an iterator which yields `(key, value)` tuples for the tree's
+    /// nodes with keys in the given range which are retained in memory
+    /// (skipping pruned subtrees).
+    pub fn range_iter<'r>(&'a self, range: impl RangeBounds<&'r [u8]>) -> RangeIter<'a> {
+        RangeIter::new(self, range)
+    }
+}
+
+impl<'a> Iterator for RangeIter<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    /// Traverses to and yields the next key/value pair in the range, in key
+    /// order.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let last = self.stack.last_mut()?;
+            if !last.traversed.0 {
+                last.traversed.0 = true;
+                let tree = last.tree.child(true).unwrap();
+                self.push(tree);
+            } else if !last.traversed.1 {
+                last.traversed.1 = true;
+                let tree = last.tree;
+                if self.contains(tree.key()) {
+                    return Some((tree.key(), tree.value()));
+                }
+            } else if !last.traversed.2 {
+                last.traversed.2 = true;
+                let tree = last.tree.child(false).unwrap();
+                self.push(tree);
+            } else {
+                self.stack.pop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{make_tree_seq, seq_key};
+
+    #[test]
+    fn range_iter() {
+        let tree = make_tree_seq(1000);
+        let all_keys: Vec<_> = tree.iter().map(|(key, _)| key).collect();
+
+        let assert_range = |range: (Bound<&[u8]>, Bound<&[u8]>)| {
+            let keys: Vec<_> = tree
+                .range_iter(range)
+                .map(|(key, _)| key.to_vec())
+                .collect();
+            let expected: Vec<_> = all_keys
+                .iter()
+                .filter(|key| range.contains(&key.as_slice()))
+                .cloned()
+                .collect();
+            assert_eq!(keys, expected);
+            keys.len()
+        };
+
+        let (start, end) = (seq_key(100), seq_key(200));
+        let (start, end) = (start.as_slice(), end.as_slice());
+        assert_eq!(
+            assert_range((Bound::Included(start), Bound::Excluded(end))),
+            100
+        );
+        assert_eq!(
+            assert_range((Bound::Included(start), Bound::Included(end))),
+            101
+        );
+        assert_eq!(
+            assert_range((Bound::Excluded(start), Bound::Unbounded)),
+            899
+        );
+        assert_eq!(
+            assert_range((Bound::Unbounded, Bound::Excluded(start))),
+            101
+        );
+        assert_eq!(assert_range((Bound::Unbounded, Bound::Unbounded)), 1001);
+
+        // bounds between keys
+        let (start, end) = ([0, 0, 0, 0, 0, 0, 0, 9, 1], [0, 0, 0, 0, 0, 0, 0, 20, 1]);
+        assert_eq!(
+            assert_range((Bound::Included(&start), Bound::Excluded(&end))),
+            11
+        );
+
+        // empty ranges
+        assert_eq!(
+            assert_range((Bound::Included(&end), Bound::Excluded(&start))),
+            0
+        );
+        let past_end = seq_key(2000);
+        assert_eq!(
+            assert_range((Bound::Included(&past_end), Bound::Unbounded)),
+            0
+        );
+
+        // other range types
+        assert_eq!(
+            tree.range_iter(start.as_slice()..end.as_slice()).count(),
+            11
+        );
+        assert_eq!(tree.range_iter(..).count(), 1001);
+    }
+}
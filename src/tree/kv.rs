@@ -1,9 +1,16 @@
-use super::hash::{kv_hash, Hash, Hasher, HASH_LENGTH, NULL_HASH};
+//! Key/value pairs stored in tree nodes.
+//!
+//! Keys are limited to `MAX_KEY_LENGTH` (255) bytes, since node references and
+//! proofs encode the key length as a single byte. Longer keys are rejected when
+//! a `KV` is created, rather than producing encodings which can not be decoded.
+
+use super::hash::{Hash, HashAlgorithm, HASH_LENGTH, NULL_HASH};
+use crate::error::Error;
 use ed::{Decode, Encode, Result};
-use std::{
-    io::{Read, Write},
-    num::TryFromIntError,
-};
+use std::io::{Read, Write};
+
+/// The maximum length of a key, in bytes.
+pub const MAX_KEY_LENGTH: usize = u8::MAX as usize;
 
 // TODO: maybe use something similar to Vec but without capacity field,
 //       (should save 16 bytes per entry). also, maybe a shorter length
@@ -15,28 +22,51 @@ pub struct KV {
     pub(super) key: Vec<u8>,
     pub(super) value: Vec<u8>,
     pub(super) hash: Hash,
+    pub(super) hash_algorithm: HashAlgorithm,
 }
 
 impl KV {
-    /// Creates a new `KV` with the given key and value and computes its hash.
+    /// Creates a new `KV` with the given key and value and computes its hash
+    /// using the given hash algorithm.
+    ///
+    /// Returns `Error::KeyTooLong` if the key is longer than `MAX_KEY_LENGTH`.
     #[inline]
-    pub fn new(key: Vec<u8>, value: Vec<u8>) -> std::result::Result<Self, TryFromIntError> {
-        kv_hash::<Hasher>(key.as_slice(), value.as_slice()).map(|hash| KV { key, value, hash })
+    pub fn new(
+        key: Vec<u8>,
+        value: Vec<u8>,
+        hash_algorithm: HashAlgorithm,
+    ) -> crate::error::Result<Self> {
+        if key.len() > MAX_KEY_LENGTH {
+            return Err(Error::KeyTooLong(key.len(), MAX_KEY_LENGTH));
+        }
+
+        let hash = hash_algorithm.kv_hash(key.as_slice(), value.as_slice())?;
+        Ok(KV {
+            key,
+            value,
+            hash,
+            hash_algorithm,
+        })
     }
 
     /// Creates a new `KV` with the given key, value, and hash. The hash is not
     /// checked to be correct for the given key/value.
     #[inline]
     pub fn from_fields(key: Vec<u8>, value: Vec<u8>, hash: Hash) -> Self {
-        KV { key, value, hash }
+        KV {
+            key,
+            value,
+            hash,
+            hash_algorithm: HashAlgorithm::default(),
+        }
     }
 
     /// Replaces the `KV`'s value with the given value, updates the hash, and
     /// returns the modified `KV`.
     #[inline]
-    pub fn with_value(mut self, value: Vec<u8>) -> std::result::Result<Self, TryFromIntError> {
+    pub fn with_value(mut self, value: Vec<u8>) -> crate::error::Result<Self> {
         self.value = value;
-        self.hash = kv_hash::<Hasher>(self.key(), self.value())?;
+        self.hash = self.hash_algorithm.kv_hash(self.key(), self.value())?;
         Ok(self)
     }
 
@@ -58,6 +88,12 @@ impl KV {
         &self.hash
     }
 
+    /// Returns the algorithm used to compute the hash.
+    #[inline]
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+
     /// Consumes the `KV` and returns its key without allocating or cloning.
     #[inline]
     pub fn take_key(self) -> Vec<u8> {
@@ -87,6 +123,7 @@ impl Decode for KV {
             key: Vec::with_capacity(0),
             value: Vec::with_capacity(128),
             hash: NULL_HASH,
+            hash_algorithm: HashAlgorithm::default(),
         };
         KV::decode_into(&mut kv, input)?;
         Ok(kv)
@@ -110,8 +147,8 @@ mod test {
     use super::*;
 
     #[test]
-    fn new_kv() -> std::result::Result<(), TryFromIntError> {
-        let kv = KV::new(vec![1, 2, 3], vec![4, 5, 6])?;
+    fn new_kv() -> crate::error::Result<()> {
+        let kv = KV::new(vec![1, 2, 3], vec![4, 5, 6], HashAlgorithm::default())?;
 
         assert_eq!(kv.key(), &[1, 2, 3]);
         assert_eq!(kv.value(), &[4, 5, 6]);
@@ -120,12 +157,29 @@ mod test {
     }
 
     #[test]
-    fn with_value() -> std::result::Result<(), TryFromIntError> {
-        let kv = KV::new(vec![1, 2, 3], vec![4, 5, 6])?.with_value(vec![7, 8, 9])?;
+    fn with_value() -> crate::error::Result<()> {
+        let kv = KV::new(vec![1, 2, 3], vec![4, 5, 6], HashAlgorithm::default())?
+            .with_value(vec![7, 8, 9])?;
 
         assert_eq!(kv.key(), &[1, 2, 3]);
         assert_eq!(kv.value(), &[7, 8, 9]);
         assert_ne!(kv.hash(), &super::super::hash::NULL_HASH);
         Ok(())
     }
+
+    #[test]
+    fn max_key_length() {
+        let kv = KV::new(vec![1; MAX_KEY_LENGTH], vec![], HashAlgorithm::default())
+            .expect("max length key should be accepted");
+        assert_eq!(kv.key().len(), 255);
+
+        match KV::new(
+            vec![1; MAX_KEY_LENGTH + 1],
+            vec![],
+            HashAlgorithm::default(),
+        ) {
+            Err(Error::KeyTooLong(256, 255)) => {}
+            _ => panic!("expected KeyTooLong error"),
+        }
+    }
 }
@@ -88,6 +88,12 @@ impl Link {
     /// Returns `true` if the link is of the `Link::Loaded` variant.
     #[inline]
     pub fn is_stored(&self) -> bool {
+        self.is_loaded()
+    }
+
+    /// Returns `true` if the link is of the `Link::Loaded` variant.
+    #[inline]
+    pub fn is_loaded(&self) -> bool {
         matches!(self, Link::Loaded { .. })
     }
 
@@ -128,29 +134,30 @@ impl Link {
         }
     }
 
-    /// Returns the height of the children of the tree referenced by the link,
-    /// if any (note: not the height of the referenced tree itself). Return
-    /// value is `(left_child_height, right_child_height)`.
+    /// Returns the heights of the children of the tree referenced by the link,
+    /// as `(left_child_height, right_child_height)` (0 for a missing child).
     #[inline]
-    pub fn height(&self) -> u8 {
-        let (left_height, right_height) = match self {
+    pub fn child_heights(&self) -> (u8, u8) {
+        match self {
             Link::Reference { child_heights, .. } => *child_heights,
             Link::Modified { child_heights, .. } => *child_heights,
             Link::Uncommitted { child_heights, .. } => *child_heights,
             Link::Loaded { child_heights, .. } => *child_heights,
-        };
+        }
+    }
+
+    /// Returns the height of the tree referenced by the link, computed from the
+    /// heights of its children (see `child_heights`).
+    #[inline]
+    pub fn height(&self) -> u8 {
+        let (left_height, right_height) = self.child_heights();
         1 + max(left_height, right_height)
     }
 
     /// Returns the balance factor of the tree referenced by the link.
     #[inline]
     pub fn balance_factor(&self) -> i8 {
-        let (left_height, right_height) = match self {
-            Link::Reference { child_heights, .. } => *child_heights,
-            Link::Modified { child_heights, .. } => *child_heights,
-            Link::Uncommitted { child_heights, .. } => *child_heights,
-            Link::Loaded { child_heights, .. } => *child_heights,
-        };
+        let (left_height, right_height) = self.child_heights();
         right_height as i8 - left_height as i8
     }
 
@@ -401,6 +408,52 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn inspect_variants() -> std::result::Result<(), crate::error::Error> {
+        let child_heights = (2, 1);
+        let tree = || Tree::new(vec![0], vec![1]);
+        let links = [
+            Link::Reference {
+                hash: NULL_HASH,
+                child_heights,
+                key: vec![0],
+            },
+            Link::Modified {
+                pending_writes: 1,
+                child_heights,
+                tree: tree()?,
+            },
+            Link::Uncommitted {
+                hash: NULL_HASH,
+                child_heights,
+                tree: tree()?,
+            },
+            Link::Loaded {
+                hash: NULL_HASH,
+                child_heights,
+                tree: tree()?,
+            },
+        ];
+
+        for (i, link) in links.iter().enumerate() {
+            let variants = [
+                link.is_reference(),
+                link.is_modified(),
+                link.is_uncommitted(),
+                link.is_loaded(),
+            ];
+            // exactly the predicate for this variant is true
+            for (j, is_variant) in variants.iter().enumerate() {
+                assert_eq!(*is_variant, i == j);
+            }
+            assert_eq!(link.is_stored(), link.is_loaded());
+            assert_eq!(link.child_heights(), (2, 1));
+            assert_eq!(link.height(), 3);
+            assert_eq!(link.balance_factor(), -1);
+        }
+        Ok(())
+    }
+
     #[test]
     #[should_panic(expected = "Cannot get hash from modified link")]
     fn modified_hash() {
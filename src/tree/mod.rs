@@ -16,8 +16,14 @@ use ed::{Decode, Encode};
 
 use super::error::Result;
 pub use commit::{Commit, NoopCommit};
-pub use hash::{kv_hash, node_hash, Hash, Hasher, HASH_LENGTH, NULL_HASH};
+#[cfg(feature = "keccak")]
+pub use hash::Keccak256Hasher;
+pub use hash::{
+    kv_digest_hash, kv_hash, node_hash, value_hash, Hash, HashAlgorithm, Hasher, HASH_LENGTH,
+    NULL_HASH,
+};
 use kv::KV;
+pub use kv::MAX_KEY_LENGTH;
 pub use link::Link;
 pub use ops::{Batch, BatchEntry, Op, PanicSource};
 pub use walk::{Fetch, RefWalker, Walker};
@@ -48,7 +54,17 @@ impl Tree {
     ///
     /// Hashes the key/value pair and initializes the `kv_hash` field.
     pub fn new(key: Vec<u8>, value: Vec<u8>) -> Result<Self> {
-        KV::new(key, value).map_err(Into::into).map(|kv| Tree {
+        Tree::new_with_hash_algorithm(key, value, HashAlgorithm::default())
+    }
+
+    /// Creates a new `Tree` with the given key and value, and no children,
+    /// using the given algorithm for its hashes.
+    pub fn new_with_hash_algorithm(
+        key: Vec<u8>,
+        value: Vec<u8>,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<Self> {
+        KV::new(key, value, hash_algorithm).map(|kv| Tree {
             inner: Box::new(TreeInner {
                 kv,
                 left: None,
@@ -57,6 +73,21 @@ impl Tree {
         })
     }
 
+    /// Sets the algorithm used to compute the node's hashes and returns the
+    /// modified `Tree`. The stored `kv_hash` is not recomputed, so this is
+    /// meant for nodes which were decoded from a store using that algorithm.
+    #[inline]
+    pub fn with_hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.inner.kv.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    /// Returns the algorithm used to compute the node's hashes.
+    #[inline]
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.inner.kv.hash_algorithm()
+    }
+
     /// Creates a `Tree` by supplying all the raw struct fields (mainly useful
     /// for testing). The `kv_hash` and `Link`s are not ensured to be correct.
     pub fn from_fields(
@@ -155,7 +186,7 @@ impl Tree {
     /// Computes and returns the hash of the root node.
     #[inline]
     pub fn hash(&self) -> Hash {
-        node_hash::<Hasher>(
+        self.hash_algorithm().node_hash(
             self.inner.kv.hash(),
             self.child_hash(true),
             self.child_hash(false),
@@ -312,7 +343,33 @@ impl Tree {
         Ok(self)
     }
 
-    // TODO: add compute_hashes method
+    /// Recomputes the hashes of all modified nodes, replacing their
+    /// `Link::Modified` links with `Link::Uncommitted` links, without writing
+    /// anything to a backing store. After this, `hash` can be called to get the
+    /// updated root hash. The nodes will still be written on the next call to
+    /// `commit`.
+    pub fn compute_hashes(&mut self) {
+        for left in [true, false] {
+            let slot = self.slot_mut(left);
+            if let Some(Link::Modified { .. }) = slot {
+                if let Some(Link::Modified {
+                    mut tree,
+                    child_heights,
+                    ..
+                }) = slot.take()
+                {
+                    tree.compute_hashes();
+                    *slot = Some(Link::Uncommitted {
+                        hash: tree.hash(),
+                        tree,
+                        child_heights,
+                    });
+                } else {
+                    unreachable!()
+                }
+            }
+        }
+    }
 
     /// Called to finalize modifications to a tree, recompute its hashes, and
     /// write the updated nodes to a backing store.
@@ -323,36 +380,62 @@ impl Tree {
     /// method to test whether or not to keep or prune nodes from memory.
     #[inline]
     pub fn commit<C: Commit>(&mut self, c: &mut C) -> Result<()> {
-        // TODO: make this method less ugly
-        // TODO: call write in-order for better performance in writing batch to db?
+        self.commit_child(true, c, Tree::commit)?;
+        self.commit_child(false, c, Tree::commit)?;
 
-        if let Some(Link::Modified { .. }) = self.inner.left {
-            if let Some(Link::Modified {
-                mut tree,
-                child_heights,
-                ..
-            }) = self.inner.left.take()
-            {
-                tree.commit(c)?;
-                self.inner.left = Some(Link::Loaded {
-                    hash: tree.hash(),
-                    tree,
-                    child_heights,
-                });
-            } else {
-                unreachable!()
-            }
-        }
+        c.write(self)?;
 
-        if let Some(Link::Modified { .. }) = self.inner.right {
-            if let Some(Link::Modified {
-                mut tree,
-                child_heights,
-                ..
-            }) = self.inner.right.take()
+        self.prune_children(c);
+        Ok(())
+    }
+
+    /// Like `commit`, but calls the `Commit` object's `write` method in key
+    /// order rather than post-order, so that a backing store receives its
+    /// writes sequentially.
+    ///
+    /// Since a node's encoding includes the hashes of its children, all hashes
+    /// are computed up-front (see `compute_hashes`) before any nodes are
+    /// written.
+    pub fn commit_inorder<C: Commit>(&mut self, c: &mut C) -> Result<()> {
+        self.compute_hashes();
+        self.commit_hashed_inorder(c)
+    }
+
+    fn commit_hashed_inorder<C: Commit>(&mut self, c: &mut C) -> Result<()> {
+        self.commit_child(true, c, Tree::commit_hashed_inorder)?;
+        c.write(self)?;
+        self.commit_child(false, c, Tree::commit_hashed_inorder)?;
+
+        self.prune_children(c);
+        Ok(())
+    }
+
+    /// Commits the child on the given side with `commit` if it is a
+    /// `Link::Modified` or `Link::Uncommitted`, replacing it with a
+    /// `Link::Loaded`.
+    fn commit_child<C: Commit>(
+        &mut self,
+        left: bool,
+        c: &mut C,
+        commit: fn(&mut Tree, &mut C) -> Result<()>,
+    ) -> Result<()> {
+        let slot = self.slot_mut(left);
+        if let Some(Link::Modified { .. } | Link::Uncommitted { .. }) = slot {
+            if let Some(
+                Link::Modified {
+                    mut tree,
+                    child_heights,
+                    ..
+                }
+                | Link::Uncommitted {
+                    mut tree,
+                    child_heights,
+                    ..
+                },
+            ) = slot.take()
             {
-                tree.commit(c)?;
-                self.inner.right = Some(Link::Loaded {
+                commit(&mut tree, c)?;
+                *slot = Some(Link::Loaded {
                     hash: tree.hash(),
                     tree,
                     child_heights,
@@ -362,8 +445,12 @@ impl Tree {
             }
         }
 
-        c.write(self)?;
+        Ok(())
+    }
 
+    /// Prunes the children which the `Commit` object's `prune` method says
+    /// should be removed from memory.
+    fn prune_children<C: Commit>(&mut self, c: &C) {
         let (prune_left, prune_right) = c.prune(self);
         if prune_left {
             self.inner.left = self.inner.left.take().map(|link| link.into_reference());
@@ -371,8 +458,6 @@ impl Tree {
         if prune_right {
             self.inner.right = self.inner.right.take().map(|link| link.into_reference());
         }
-
-        Ok(())
     }
 
     /// Fetches the child on the given side using the given data source, and
@@ -403,25 +488,41 @@ impl Tree {
     }
 
     pub fn get_value(&self, key: &[u8]) -> Result<GetResult> {
+        Ok(match self.find(key) {
+            Some(Some(node)) => GetResult::Found(node.value().to_vec()),
+            Some(None) => GetResult::NotFound,
+            None => GetResult::Pruned,
+        })
+    }
+
+    /// Returns `Some(true)` if the in-memory tree contains the given key, or
+    /// `Some(false)` if it does not, without copying the value. Returns `None`
+    /// if the key would be in a pruned subtree (the caller will have to check
+    /// the backing store).
+    pub fn contains_key(&self, key: &[u8]) -> Option<bool> {
+        self.find(key).map(|maybe_node| maybe_node.is_some())
+    }
+
+    /// Traverses the in-memory tree to the node with the given key. Returns
+    /// `Some(None)` if the key is not in the tree, or `None` if the traversal
+    /// reached a pruned subtree.
+    fn find(&self, key: &[u8]) -> Option<Option<&Tree>> {
         let mut cursor = self;
 
         loop {
             if key == cursor.key() {
-                return Ok(GetResult::Found(cursor.value().to_vec()));
+                return Some(Some(cursor));
             }
 
             let left = key < cursor.key();
             let link = match cursor.link(left) {
-                None => return Ok(GetResult::NotFound), // not found
+                None => return Some(None), // not found
                 Some(link) => link,
             };
 
-            let maybe_child = link.tree();
-            match maybe_child {
-                None => return Ok(GetResult::Pruned), /* value is pruned, caller will have to */
-                // fetch from disk
-                Some(child) => cursor = child, // traverse to child
-            }
+            // traverse to child, or return if the child is pruned (caller will
+            // have to fetch from disk)
+            cursor = link.tree()?;
         }
     }
 }
@@ -442,10 +543,12 @@ pub fn side_to_str(left: bool) -> &'static str {
 
 #[cfg(test)]
 mod test {
-    use super::commit::NoopCommit;
+    use super::commit::{Commit, NoopCommit};
     use super::hash::NULL_HASH;
     use super::Tree;
+    use super::{PanicSource, Walker};
     use crate::error::Result;
+    use crate::test_utils::{make_batch_seq, make_tree_seq};
 
     #[test]
     fn build_tree() -> Result<()> {
@@ -541,8 +644,8 @@ mod test {
         assert_eq!(
             tree.child_hash(true),
             &[
-                130, 215, 14, 92, 6, 226, 203, 67, 180, 206, 170, 81, 142, 77, 62, 33, 146, 78, 29,
-                252, 100, 149, 207, 172, 89, 254, 96, 166, 159, 49, 169, 106
+                130, 126, 206, 195, 44, 109, 199, 131, 235, 114, 94, 249, 218, 21, 163, 131, 52,
+                240, 128, 254, 30, 2, 195, 19, 116, 32, 120, 100, 189, 219, 71, 13
             ]
         );
         assert_eq!(tree.child_hash(false), &NULL_HASH);
@@ -555,8 +658,8 @@ mod test {
         assert_eq!(
             tree.hash(),
             [
-                115, 223, 9, 212, 135, 4, 223, 163, 244, 126, 25, 190, 255, 217, 132, 76, 219, 149,
-                151, 237, 164, 103, 67, 44, 196, 177, 227, 195, 217, 146, 156, 86
+                58, 125, 52, 27, 13, 20, 94, 190, 38, 160, 74, 247, 131, 108, 210, 131, 208, 117,
+                38, 238, 206, 123, 123, 77, 150, 43, 247, 159, 209, 47, 96, 231
             ]
         );
         Ok(())
@@ -606,4 +709,95 @@ mod test {
         assert!(tree.link(false).expect("expected link").is_stored());
         Ok(())
     }
+
+    #[test]
+    fn external_hashes() -> Result<()> {
+        use super::{kv_hash, node_hash, Hasher};
+
+        let tree = make_tree_seq(3);
+        let left = tree.child(true).expect("expected child");
+        let right = tree.child(false).expect("expected child");
+
+        for node in [&tree, left, right] {
+            let kv = kv_hash::<Hasher>(node.key(), node.value())?;
+            assert_eq!(&kv, node.kv_hash());
+        }
+
+        let kv = kv_hash::<Hasher>(tree.key(), tree.value())?;
+        assert_eq!(
+            node_hash::<Hasher>(&kv, &left.hash(), &right.hash()),
+            tree.hash()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn commit_inorder() {
+        struct KeyCommit(Vec<Vec<u8>>);
+        impl Commit for KeyCommit {
+            fn write(&mut self, tree: &Tree) -> Result<()> {
+                self.0.push(tree.key().to_vec());
+                Ok(())
+            }
+        }
+
+        let batch = make_batch_seq(50..150);
+        let apply = |tree| {
+            let walker = Walker::new(tree, PanicSource {});
+            Walker::apply_to(Some(walker), &batch, PanicSource {})
+                .expect("apply failed")
+                .0
+                .expect("expected tree")
+        };
+
+        let mut expected = apply(make_tree_seq(100));
+        let mut post_order = KeyCommit(vec![]);
+        expected.commit(&mut post_order).expect("commit failed");
+
+        let mut tree = apply(make_tree_seq(100));
+        let mut in_order = KeyCommit(vec![]);
+        tree.commit_inorder(&mut in_order).expect("commit failed");
+        assert_eq!(tree.hash(), expected.hash());
+        assert!(!tree.link(false).expect("expected link").is_uncommitted());
+
+        // same nodes are written, but sorted by key
+        assert!(in_order.0.windows(2).all(|pair| pair[0] < pair[1]));
+        post_order.0.sort();
+        assert_eq!(in_order.0, post_order.0);
+    }
+
+    #[test]
+    fn compute_hashes() {
+        struct CountCommit(usize);
+        impl Commit for CountCommit {
+            fn write(&mut self, _tree: &Tree) -> Result<()> {
+                self.0 += 1;
+                Ok(())
+            }
+        }
+
+        let batch = make_batch_seq(50..150);
+        let apply = |tree| {
+            let walker = Walker::new(tree, PanicSource {});
+            Walker::apply_to(Some(walker), &batch, PanicSource {})
+                .expect("apply failed")
+                .0
+                .expect("expected tree")
+        };
+
+        let mut expected = apply(make_tree_seq(100));
+        expected.commit(&mut NoopCommit {}).expect("commit failed");
+
+        let mut tree = apply(make_tree_seq(100));
+        tree.compute_hashes();
+        assert_eq!(tree.hash(), expected.hash());
+        assert!(tree.link(false).expect("expected link").is_uncommitted());
+
+        // the modified nodes are still written by the next commit
+        let mut commit = CountCommit(0);
+        tree.commit(&mut commit).expect("commit failed");
+        assert!(commit.0 >= 100);
+        assert_eq!(tree.hash(), expected.hash());
+        assert!(!tree.link(false).expect("expected link").is_uncommitted());
+    }
 }
@@ -1,7 +1,8 @@
 use super::{Fetch, Tree, Walker};
-use crate::error::Result;
+use crate::error::{Error, Result};
 use std::collections::LinkedList;
 use std::fmt;
+use std::thread;
 use Op::*;
 
 /// An operation to be applied to a key in the store.
@@ -10,6 +11,9 @@ pub enum Op {
     Put(Vec<u8>),
     /// Deletes the key/value entry.
     Delete,
+    /// Appends the given bytes to the key's existing value. If the key does
+    /// not exist, this behaves like `Put`.
+    Append(Vec<u8>),
 }
 
 impl fmt::Debug for Op {
@@ -20,6 +24,7 @@ impl fmt::Debug for Op {
             match self {
                 Put(value) => format!("Put({value:?})"),
                 Delete => "Delete".to_string(),
+                Append(value) => format!("Append({value:?})"),
             }
         )
     }
@@ -42,6 +47,31 @@ impl Fetch for PanicSource {
     }
 }
 
+impl Tree {
+    /// Builds a balanced in-memory tree from a batch of `Put` operations,
+    /// without reading from or writing to any store. Returns `None` if the
+    /// batch is empty, and `Error::KeyDelete` if the batch contains a
+    /// `Delete` operation (since there are no existing keys to delete).
+    ///
+    /// The nodes of the returned tree have not been committed yet, so
+    /// `commit` or `compute_hashes` must be called before getting its hash.
+    ///
+    /// Keys in batch must be sorted and unique (this is only checked in debug
+    /// builds).
+    pub fn from_sorted_batch(batch: &Batch) -> Result<Option<Tree>> {
+        debug_assert!(
+            batch.windows(2).all(|pair| pair[0].0 < pair[1].0),
+            "Keys in batch must be sorted and unique"
+        );
+
+        if let Some((key, _)) = batch.iter().find(|(_, op)| matches!(op, Delete)) {
+            return Err(Error::KeyDelete(key.clone()));
+        }
+
+        Walker::build(batch, PanicSource {})
+    }
+}
+
 impl<S> Walker<S>
 where
     S: Fetch + Sized + Send + Clone,
@@ -69,6 +99,94 @@ where
         Ok((maybe_tree, deleted_keys))
     }
 
+    /// Applies a batch of operations like `apply_to`, but the operations for
+    /// the root's left and right subtrees are applied concurrently, with the
+    /// left subtree in a separate thread. The resulting tree is identical to
+    /// the one produced by `apply_to`.
+    ///
+    /// Keys in batch must be sorted and unique.
+    pub fn apply_to_parallel(
+        maybe_tree: Option<Self>,
+        batch: &Batch,
+        source: S,
+    ) -> Result<(Option<Tree>, LinkedList<Vec<u8>>)> {
+        let walker = match maybe_tree {
+            Some(walker) if !batch.is_empty() => walker,
+            maybe_tree => return Self::apply_to(maybe_tree, batch, source),
+        };
+
+        // split the batch at the root key, same as `apply`
+        let search = batch.binary_search_by(|(key, _op)| key.as_slice().cmp(walker.tree().key()));
+        let (walker, left_batch, right_batch, delete) = match search {
+            Ok(index) => match &batch[index].1 {
+                Put(value) => (
+                    walker.with_value(value.to_vec())?,
+                    &batch[..index],
+                    &batch[index + 1..],
+                    false,
+                ),
+                Append(suffix) => (
+                    walker.append_value(suffix)?,
+                    &batch[..index],
+                    &batch[index + 1..],
+                    false,
+                ),
+                Delete => (walker, &batch[..index], &batch[index + 1..], true),
+            },
+            Err(index) => (walker, &batch[..index], &batch[index..], false),
+        };
+
+        // only detach children with operations to apply, unless the root is
+        // being removed
+        let (walker, maybe_left) = if delete || !left_batch.is_empty() {
+            let (walker, maybe_left) = walker.detach(true)?;
+            (walker, Some(maybe_left))
+        } else {
+            (walker, None)
+        };
+        let (walker, maybe_right) = if delete || !right_batch.is_empty() {
+            let (walker, maybe_right) = walker.detach(false)?;
+            (walker, Some(maybe_right))
+        } else {
+            (walker, None)
+        };
+
+        let (left_res, right_res) = thread::scope(|scope| {
+            let left_handle = maybe_left.map(|maybe_left| {
+                let source = source.clone();
+                scope.spawn(move || Self::apply_to(maybe_left, left_batch, source))
+            });
+            let right_res = maybe_right
+                .map(|maybe_right| Self::apply_to(maybe_right, right_batch, source.clone()));
+            let left_res = left_handle.map(|handle| handle.join().expect("apply thread panicked"));
+            (left_res, right_res)
+        });
+
+        let mut deleted_keys = LinkedList::default();
+        let mut walker = walker;
+        if let Some(res) = left_res {
+            let (maybe_left, mut deleted_keys_left) = res?;
+            deleted_keys.append(&mut deleted_keys_left);
+            walker = walker.attach(true, maybe_left);
+        }
+        if delete {
+            deleted_keys.push_back(walker.tree().key().to_vec());
+        }
+        if let Some(res) = right_res {
+            let (maybe_right, mut deleted_keys_right) = res?;
+            deleted_keys.append(&mut deleted_keys_right);
+            walker = walker.attach(false, maybe_right);
+        }
+
+        let maybe_walker = if delete {
+            walker.remove()?.map(|w| w.maybe_balance()).transpose()?
+        } else {
+            Some(walker.maybe_balance()?)
+        };
+
+        Ok((maybe_walker.map(|walker| walker.into_inner()), deleted_keys))
+    }
+
     /// Builds a `Tree` from a batch of operations.
     ///
     /// Keys in batch must be sorted and unique.
@@ -93,12 +211,19 @@ where
                 };
                 return Ok(maybe_tree.map(|tree| tree.into()));
             }
-            Put(value) => value,
+            // there is no existing value to append to
+            Put(value) | Append(value) => value,
         };
 
         // TODO: take from batch so we don't have to clone
-        let mid_tree = Tree::new(mid_key.to_vec(), mid_value.to_vec())?;
-        let mid_walker = Walker::new(mid_tree, PanicSource {});
+        let mid_tree = Tree::new_with_hash_algorithm(
+            mid_key.to_vec(),
+            mid_value.to_vec(),
+            source.hash_algorithm(),
+        )?;
+        // the new node has no children to fetch, but its source is used when
+        // building its subtrees
+        let mid_walker = Walker::new(mid_tree, source);
         Ok(mid_walker
             .recurse(batch, mid_index, true)?
             .0 // use walker, ignore deleted_keys since it should be empty
@@ -118,6 +243,7 @@ where
             match &batch[index].1 {
                 // TODO: take vec from batch so we don't need to clone
                 Put(value) => self.with_value(value.to_vec()),
+                Append(suffix) => self.append_value(suffix),
                 Delete => {
                     let source = self.clone_source();
                     let key = self.tree().key().to_vec();
@@ -301,7 +427,8 @@ where
 mod test {
     use super::*;
     use crate::test_utils::{
-        apply_memonly, assert_tree_invariants, del_entry, make_tree_seq, put_entry, seq_key,
+        apply_memonly, assert_tree_invariants, del_entry, make_batch_seq, make_tree_seq, put_entry,
+        seq_key,
     };
     use crate::tree::*;
 
@@ -513,6 +640,25 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn from_sorted_batch() -> Result<()> {
+        assert!(Tree::from_sorted_batch(&[])?.is_none());
+
+        let batch = make_batch_seq(0..1000);
+        let mut tree = Tree::from_sorted_batch(&batch)?.expect("expected tree");
+        tree.commit(&mut NoopCommit {})?;
+        assert_tree_invariants(&tree);
+        assert_eq!(tree.height(), 10);
+
+        let mut batch = make_batch_seq(0..10);
+        batch[5].1 = Op::Delete;
+        assert!(matches!(
+            Tree::from_sorted_batch(&batch),
+            Err(Error::KeyDelete(key)) if key == seq_key(5)
+        ));
+        Ok(())
+    }
+
     #[test]
     fn insert_100_sequential() -> Result<()> {
         let mut tree = Tree::new(vec![0], vec![123])?;
@@ -1,4 +1,4 @@
-use super::super::{Link, Tree};
+use super::super::{HashAlgorithm, Link, Tree};
 use crate::error::{Error, Result};
 
 /// A source of data to be used by the tree when encountering a pruned node.
@@ -18,4 +18,11 @@ pub trait Fetch {
         self.fetch_by_key(key)?
             .ok_or_else(|| Error::Key(format!("Key does not exist: {key:?}")))
     }
+
+    /// The hash algorithm used for nodes created when applying operations to
+    /// the tree. Sources backed by a store should return the store's
+    /// algorithm.
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        HashAlgorithm::default()
+    }
 }
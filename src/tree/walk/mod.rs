@@ -134,6 +134,15 @@ where
         self.tree.own_fallible(|t| t.with_value(value))?;
         Ok(self)
     }
+
+    /// Replaces the root node's value with its existing value followed by
+    /// `suffix`.
+    pub fn append_value(self, suffix: &[u8]) -> Result<Self> {
+        let mut value = Vec::with_capacity(self.tree().value().len() + suffix.len());
+        value.extend_from_slice(self.tree().value());
+        value.extend_from_slice(suffix);
+        self.with_value(value)
+    }
 }
 
 impl<S> From<Walker<S>> for Tree
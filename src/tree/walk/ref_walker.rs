@@ -53,4 +53,61 @@ where
         let child = self.tree.child_mut(left).unwrap();
         Ok(Some(RefWalker::new(child, self.source.clone())))
     }
+
+    /// Returns an iterator over the nodes which are currently loaded in memory,
+    /// in key order. Pruned nodes (behind `Link::Reference`) are not fetched,
+    /// so neither they nor any of their descendants are yielded.
+    pub fn loaded_nodes(&mut self) -> impl Iterator<Item = &Tree> {
+        let mut stack = vec![];
+        let mut node = Some(&*self.tree);
+
+        std::iter::from_fn(move || {
+            while let Some(tree) = node {
+                stack.push(tree);
+                node = tree.child(true);
+            }
+
+            let tree = stack.pop()?;
+            node = tree.child(false);
+            Some(tree)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{make_batch_seq, seq_key};
+    use crate::tree::{Commit, PanicSource, Walker};
+
+    struct PruneCommit {}
+
+    impl Commit for PruneCommit {
+        fn write(&mut self, _tree: &Tree) -> Result<()> {
+            Ok(())
+        }
+
+        fn prune(&self, tree: &Tree) -> (bool, bool) {
+            // keep only the top two levels
+            (tree.height() <= 2, tree.height() <= 2)
+        }
+    }
+
+    #[test]
+    fn loaded_nodes() {
+        let batch = make_batch_seq(0..7);
+        let mut tree = Walker::<PanicSource>::apply_to(None, &batch, PanicSource {})
+            .expect("apply failed")
+            .0
+            .expect("expected tree");
+        tree.commit(&mut PruneCommit {}).expect("commit failed");
+        assert_eq!(tree.height(), 3);
+
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+        let keys: Vec<_> = walker
+            .loaded_nodes()
+            .map(|tree| tree.key().to_vec())
+            .collect();
+        assert_eq!(keys, vec![seq_key(1), seq_key(3), seq_key(5)]);
+    }
 }